@@ -1,35 +1,23 @@
 use std::env;
-use std::fs;
-use std::path::Path;
 
 fn main() {
     println!("cargo:rerun-if-changed=locales/");
     println!("cargo:rustc-check-cfg=cfg(locale, values(\"ru\", \"en\"))");
 
-    let out_dir = env::var("OUT_DIR").unwrap();
-    let dest_path = Path::new(&out_dir).join("locale.ftl");
-
     // Get locale from environment variable, default to "ru"
-    let locale = env::var("NEXT_PUBLIC_LANGUAGE").unwrap_or_else(|_| "ru".to_string());
-
-    let locale_file = match locale.as_str() {
-        "ru" => "locales/ru.ftl",
-        "en" => "locales/en.ftl",
-        _ => {
-            println!(
-                "cargo:warning=Unknown locale '{}', falling back to 'ru'",
-                locale
-            );
-            "locales/ru.ftl"
-        }
-    };
+    let mut locale = env::var("NEXT_PUBLIC_LANGUAGE").unwrap_or_else(|_| "ru".to_string());
 
-    if Path::new(locale_file).exists() {
-        fs::copy(locale_file, dest_path).unwrap();
-    } else {
-        panic!("Locale file {} not found", locale_file);
+    if locale != "ru" && locale != "en" {
+        println!(
+            "cargo:warning=Unknown locale '{}', falling back to 'ru'",
+            locale
+        );
+        locale = "ru".to_string();
     }
 
+    // Both locales are embedded, this one is used if no other is requested
+    println!("cargo:rustc-env=CLO_DEFAULT_LOCALE={}", locale);
+
     // Set a cfg flag for the locale
     println!("cargo:rustc-cfg=locale=\"{}\"", locale);
 }
@@ -1,10 +1,11 @@
 #!/bin/sh
+# Overrides of the config, the file is not changed
 if [ -n "$TOKEN" ]; then
-/clo set token $TOKEN
+export CLO_TOKEN="$TOKEN"
 fi
 
 if [ -n "$SERVER" ]; then
-/clo set server $SERVER
+export CLO_SERVER="$SERVER"
 fi
 
 if [ -n "$HTTP" ]; then
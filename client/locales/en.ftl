@@ -2,6 +2,7 @@ server = https://cloudpub.online
 # Error messages
 error-network = Network error, trying again.
 error-process-terminated = Server process was unexpectedly terminated
+error-crash-loop = Server process keeps crashing, gave up after {$restarts} restarts
 error-auth-missing = Authorization token is missing
 error-measurement = Measurement error
 
@@ -46,10 +47,45 @@ error-setting-permissions = Error setting execution permissions
 error-creating-marker = Error creating marker file
 error-writing-httpd-conf = Error writing httpd.conf
 
+# 1C plugin messages
+error-onec-platform-not-found = 1C platform not found, set its bitness (x32/x64) and path in the settings
+error-onec-home-not-found = 1C platform path ({$path}) not found, set it in the settings
+error-wsap-not-found = Module {$module} not found in {$path}. Check the settings and make sure the web server extension modules for 1C are installed
+error-creating-publish-dir = Error creating publish directory
+error-writing-vrd = Error writing default.vrd
+
 # Service messages
 service-published = Service published: {$endpoint}
 service-registered = Service registered: {$endpoint}
 service-stopped = Service stopped: {$guid}
+service-unhealthy = Service is unhealthy: {$guid}
+service-healthy = Service is healthy again: {$guid}
+error-page-upstream-down = The service is temporarily unavailable. Please try again later.
+error-page-maintenance = The service is under maintenance. Please try again later.
+error-page-not-found = Error page template not found: {$path}
+maintenance-enabled = Maintenance mode enabled: {$guid}
+maintenance-disabled = Maintenance mode disabled: {$guid}
+invalid-guid = Invalid service GUID: {$guid}
+service-installed = Service installed successfully
+service-uninstalled = Service uninstalled successfully
+service-started = Service started successfully
+service-stopped-successfully = Service stopped successfully
+service-status-running = Service is running
+service-status-stopped = Service is stopped
+service-status-not-installed = Service is not installed
+service-status-unknown = Service status is unknown
+service-not-found = Service not found: {$name}
+no-service-logs = No logs for service: {$guid}
+agent-not-running = Agent is not running
+status-agent = Agent {$pid} v{$version}: {$state}, server {$server}
+status-uptime = Uptime {$uptime}, connected {$connected}
+status-latency = RTT {$rtt} ms, last heartbeat {$heartbeat} s ago
+status-endpoint-stats = channels {$channels}, sent {$sent} B, received {$received} B
+status-pid = process {$pid}
+error-exec-spawn = Failed to launch command: {$command}
+error-exec-port-timeout = Application didn't start listening on {$address}
+error-exec-register = Command can be launched only with publish
+error-exec-failed = Application exited with {$status}
 service-removed = Service removed: {$guid}
 no-registered-services = No registered services
 all-services-removed = All services removed
@@ -59,10 +95,41 @@ enter-email = Enter email:{" "}
 enter-password = Enter password:{" "}
 session-terminated = Session terminated, authorization token reset
 client-authorized = Client successfully authorized
+device-login = Open {$url} and confirm the code {$code}
+device-waiting = Waiting for confirmation in the browser
+device-unsupported = Server doesn't support login with a code
+device-access-denied = Login was denied
+device-code-expired = Code has expired, run the login again
+token-file-empty = Token file {$path} is empty
+error-reading-token-file = Failed to read the token file {$path}
+error-reading-password = Failed to read the password from stdin
 upgrade-available = New version available: {$version}
+downloading-upgrade = Downloading version {$version}
+downloading-signature = Downloading signature
+upgrade-installed = Version {$version} is installed
+upgrade-up-to-date = Version {$version} is up to date
+upgrade-failed = Upgrade failed: {$error}
+upgrade-not-newer = Version {$version} is not newer than the installed one, upgrade refused
+upgrade-check-unsupported = Server can't check for upgrades, please download the new version manually
+upgrade-key-missing = This build has no release key, please download the new version manually
+upgrade-bad-signature = Signature of the new version is invalid
+upgrade-broken-binary = New version doesn't start on this system
+upgrade-restart-required = Restart the agent to use the new version
+upgrade-service-restarted = Service restarted
+upgrade-no-rollback = No previous version to roll back to
+upgrade-rolled-back = Previous version restored
 
 # Ping statistics
 ping-time-percentiles = Ping time (percentiles):
+ping-jitter = Jitter: {$jitter}
+ping-loss = Messages: sent {$sent}, received {$received}, loss {$loss}%
+ping-handshake = Handshake
+ping-handshake-connect = connect {$time}
+ping-handshake-tls = TLS {$time}
+ping-handshake-websocket = WebSocket {$time}
+ping-handshake-data-channel = data channel {$time}
+ping-throughput = Throughput ({$streams} streams): upload {$upload}, download {$download}
+ping-histogram = Round trip histogram
 
 # Invalid formats
 invalid-url = Invalid URL
@@ -70,3 +137,74 @@ invalid-protocol = Invalid protocol
 invalid-address = Invalid address: {$address}
 invalid-address-error = Invalid address ({$error}): {$address}
 port-required = Port is required for this protocol
+health-command-required = Health check command is required (--health-cmd)
+private-tcp-only = Only tcp services can be private
+unix-socket-unsupported = Unix sockets are supported only for http, https and tcp protocols
+error-unix-socket-platform = Unix sockets are not supported on this platform: {$path}
+invalid-header = Invalid header format (should be 'name:value'): {$value}
+invalid-acl = Invalid ACL (should be 'user:role'): {$value}
+invalid-role = Invalid role: {$role}
+error-unsupported-language = Unsupported language: {$lang}
+
+# Dashboard
+dashboard-header = CloudPub {$version}: {$state}, server {$server}, RTT {$rtt} ms
+dashboard-endpoints = Services
+dashboard-connections = Connections
+dashboard-throughput = Throughput
+dashboard-events = Events
+dashboard-errors = Recent errors
+dashboard-keys = ↑↓ select  s stop  r remove  c copy URL  q quit
+dashboard-column-service = Service
+dashboard-column-url = URL
+dashboard-column-target = Target
+dashboard-column-status = Status
+dashboard-column-connections = Conns
+dashboard-column-sent = Sent
+dashboard-column-received = Received
+dashboard-no-visitors = No active connections
+dashboard-unknown-visitors = Connections without address: {$count}
+dashboard-url-copied = URL copied to clipboard: {$url}
+
+# Diagnostics
+doctor-header = Checking connection to {$server} over {$transport}
+doctor-step-dns = DNS resolution of {$host}
+doctor-step-tcp = TCP connection to {$address}
+doctor-step-proxy = Proxy CONNECT via {$proxy}
+doctor-step-tls = TLS handshake with {$host}
+doctor-step-websocket = WebSocket upgrade to {$url}
+doctor-step-hello = Authorization with the saved token
+doctor-step-service = Service {$guid} at {$target}
+doctor-reachable = reachable
+doctor-upgraded = upgraded
+doctor-behind-proxy = connections go through the proxy
+doctor-no-proxy = proxy is not configured
+doctor-no-tls = transport doesn't use TLS
+doctor-no-websocket = transport doesn't use WebSocket
+doctor-no-token = no saved token, run clo login
+doctor-certificate-verified = certificate chain verified
+doctor-certificate = #{$index}: {$subject}, issuer {$issuer}, valid until {$expires}
+doctor-redirect = redirected to {$host}
+doctor-authorized = authorized, {$count} services registered
+doctor-udp-target = UDP targets can't be checked
+doctor-plugin-target = target is served by a plugin
+doctor-target-down = local target doesn't accept connections
+doctor-timeout = no answer in {$secs} s
+doctor-summary = Passed: {$passed}, failed: {$failed}
+doctor-failed = Some checks failed
+
+# Private services
+visitor-listening = Private service {$guid} is available at {$address}
+visitor-unsupported = Server doesn't support private services
+visitor-bind-failed = Failed to listen on {$address}: {$error}
+p2p-no-reflection = Rendezvous {$rendezvous} doesn't answer
+p2p-no-answer = Publishing agent didn't answer the direct connection offer
+p2p-declined = Publishing agent declined the direct connection
+p2p-punch-failed = Failed to punch through NAT
+p2p-no-connection = Visitor didn't open the direct connection
+
+# Configuration
+config-source-env = environment variable {$var}
+config-source-file = config file {$path}
+config-overridden = Saved, but {$var} overrides this value
+config-valid = Config is valid
+config-invalid = Problems in the config: {$count}
@@ -2,6 +2,7 @@ server = https://cloudpub.ru
 # Error messages
 error-network = Ошибка сети, пробуем еще раз.
 error-process-terminated = Процесс сервера был неожиданно завершен
+error-crash-loop = Процесс сервера постоянно падает, перезапуск остановлен после {$restarts} попыток
 error-auth-missing = Отсутствует токен авторизации
 error-measurement = Ошибка измерения
 
@@ -46,10 +47,45 @@ error-setting-permissions = Ошибка установки прав на исп
 error-creating-marker = Ошибка создания файла метки
 error-writing-httpd-conf = Ошибка записи httpd.conf
 
+# 1C plugin messages
+error-onec-platform-not-found = Платформа 1C не найдена, укажите ее битность (x32/x64) и путь в настройках
+error-onec-home-not-found = Путь до платформы 1C ({$path}) не найден, укажите его в настройках
+error-wsap-not-found = Модуль {$module} не найден в {$path}. Проверьте настройки и убедитесь что у вас установлены модули расширения веб-сервера для 1С
+error-creating-publish-dir = Ошибка создания каталога публикации
+error-writing-vrd = Ошибка записи default.vrd
+
 # Service messages
 service-published = Сервис опубликован: {$endpoint}
 service-registered = Сервис зарегистрирован: {$endpoint}
 service-stopped = Сервис остановлен: {$guid}
+service-unhealthy = Сервис недоступен: {$guid}
+service-healthy = Сервис снова доступен: {$guid}
+error-page-upstream-down = Сервис временно недоступен. Попробуйте позже.
+error-page-maintenance = На сервисе ведутся технические работы. Попробуйте позже.
+error-page-not-found = Шаблон страницы ошибки не найден: {$path}
+maintenance-enabled = Режим обслуживания включен: {$guid}
+maintenance-disabled = Режим обслуживания выключен: {$guid}
+invalid-guid = Неверный GUID сервиса: {$guid}
+service-installed = Сервис успешно установлен
+service-uninstalled = Сервис успешно удален
+service-started = Сервис успешно запущен
+service-stopped-successfully = Сервис успешно остановлен
+service-status-running = Сервис запущен
+service-status-stopped = Сервис остановлен
+service-status-not-installed = Сервис не установлен
+service-status-unknown = Состояние сервиса неизвестно
+service-not-found = Сервис не найден: {$name}
+no-service-logs = Нет логов для сервиса: {$guid}
+agent-not-running = Агент не запущен
+status-agent = Агент {$pid} v{$version}: {$state}, сервер {$server}
+status-uptime = Время работы {$uptime}, подключен {$connected}
+status-latency = RTT {$rtt} мс, последний heartbeat {$heartbeat} с назад
+status-endpoint-stats = соединений {$channels}, отправлено {$sent} Б, получено {$received} Б
+status-pid = процесс {$pid}
+error-exec-spawn = Не удалось запустить команду: {$command}
+error-exec-port-timeout = Приложение не открыло порт {$address}
+error-exec-register = Запуск команды поддерживается только для publish
+error-exec-failed = Приложение завершилось с кодом {$status}
 service-removed = Сервис удален: {$guid}
 no-registered-services = Нет зарегистрированных сервисов
 all-services-removed = Все сервисы удалены
@@ -59,10 +95,41 @@ enter-email = Введите email:{" "}
 enter-password = Введите пароль:{" "}
 session-terminated = Сессия завершена, токен авторизации сброшен
 client-authorized = Клиент успешно авторизован
+device-login = Откройте {$url} и подтвердите код {$code}
+device-waiting = Ожидание подтверждения в браузере
+device-unsupported = Сервер не поддерживает вход по коду
+device-access-denied = Вход отклонен
+device-code-expired = Срок действия кода истек, повторите вход
+token-file-empty = Файл токена {$path} пуст
+error-reading-token-file = Не удалось прочитать файл токена {$path}
+error-reading-password = Не удалось прочитать пароль из stdin
 upgrade-available = Доступна новая версия: {$version}
+downloading-upgrade = Загрузка версии {$version}
+downloading-signature = Загрузка подписи
+upgrade-installed = Версия {$version} установлена
+upgrade-up-to-date = Версия {$version} актуальна
+upgrade-failed = Не удалось обновиться: {$error}
+upgrade-not-newer = Версия {$version} не новее установленной, обновление отклонено
+upgrade-check-unsupported = Сервер не поддерживает проверку обновлений, скачайте новую версию вручную
+upgrade-key-missing = В этой сборке нет ключа релизов, скачайте новую версию вручную
+upgrade-bad-signature = Подпись новой версии неверна
+upgrade-broken-binary = Новая версия не запускается на этой системе
+upgrade-restart-required = Перезапустите агент, чтобы использовать новую версию
+upgrade-service-restarted = Сервис перезапущен
+upgrade-no-rollback = Нет предыдущей версии для отката
+upgrade-rolled-back = Предыдущая версия восстановлена
 
 # Ping statistics
 ping-time-percentiles = Время пинга (процентили):
+ping-jitter = Джиттер: {$jitter}
+ping-loss = Сообщения: отправлено {$sent}, получено {$received}, потери {$loss}%
+ping-handshake = Подключение
+ping-handshake-connect = соединение {$time}
+ping-handshake-tls = TLS {$time}
+ping-handshake-websocket = WebSocket {$time}
+ping-handshake-data-channel = канал данных {$time}
+ping-throughput = Скорость (потоков: {$streams}): отправка {$upload}, загрузка {$download}
+ping-histogram = Гистограмма времени пинга
 
 # Invalid formats
 invalid-url = Неверный URL
@@ -70,3 +137,74 @@ invalid-protocol = Неверный протокол
 invalid-address = Неправильно указан адрес: {$address}
 invalid-address-error = Неправильно указан адрес ({$error}): {$address}
 port-required = Для этого прокола нужно указать порт
+health-command-required = Нужно указать команду проверки (--health-cmd)
+private-tcp-only = Приватными могут быть только tcp сервисы
+unix-socket-unsupported = Unix сокеты поддерживаются только для протоколов http, https и tcp
+error-unix-socket-platform = Unix сокеты не поддерживаются на этой платформе: {$path}
+invalid-header = Неверный формат заголовка (должен быть 'имя:значение'): {$value}
+invalid-acl = Неверный формат ACL (должен быть 'пользователь:роль'): {$value}
+invalid-role = Неверная роль: {$role}
+error-unsupported-language = Язык не поддерживается: {$lang}
+
+# Dashboard
+dashboard-header = CloudPub {$version}: {$state}, сервер {$server}, RTT {$rtt} мс
+dashboard-endpoints = Сервисы
+dashboard-connections = Подключения
+dashboard-throughput = Трафик
+dashboard-events = События
+dashboard-errors = Последние ошибки
+dashboard-keys = ↑↓ выбор  s остановить  r удалить  c копировать URL  q выход
+dashboard-column-service = Сервис
+dashboard-column-url = URL
+dashboard-column-target = Адрес
+dashboard-column-status = Статус
+dashboard-column-connections = Подкл.
+dashboard-column-sent = Отправлено
+dashboard-column-received = Получено
+dashboard-no-visitors = Нет активных подключений
+dashboard-unknown-visitors = Подключений без адреса: {$count}
+dashboard-url-copied = URL скопирован в буфер обмена: {$url}
+
+# Diagnostics
+doctor-header = Проверка подключения к {$server} через {$transport}
+doctor-step-dns = Разрешение имени {$host}
+doctor-step-tcp = TCP подключение к {$address}
+doctor-step-proxy = Прокси CONNECT через {$proxy}
+doctor-step-tls = TLS рукопожатие с {$host}
+doctor-step-websocket = WebSocket подключение к {$url}
+doctor-step-hello = Авторизация с сохраненным токеном
+doctor-step-service = Сервис {$guid} на {$target}
+doctor-reachable = доступен
+doctor-upgraded = подключено
+doctor-behind-proxy = подключения идут через прокси
+doctor-no-proxy = прокси не настроен
+doctor-no-tls = транспорт не использует TLS
+doctor-no-websocket = транспорт не использует WebSocket
+doctor-no-token = нет сохраненного токена, выполните clo login
+doctor-certificate-verified = цепочка сертификатов проверена
+doctor-certificate = #{$index}: {$subject}, издатель {$issuer}, действителен до {$expires}
+doctor-redirect = перенаправление на {$host}
+doctor-authorized = авторизован, зарегистрировано сервисов: {$count}
+doctor-udp-target = UDP адреса нельзя проверить
+doctor-plugin-target = адрес обслуживается плагином
+doctor-target-down = локальный адрес не принимает подключения
+doctor-timeout = нет ответа за {$secs} с
+doctor-summary = Успешно: {$passed}, с ошибками: {$failed}
+doctor-failed = Некоторые проверки не пройдены
+
+# Private services
+visitor-listening = Приватный сервис {$guid} доступен по адресу {$address}
+visitor-unsupported = Сервер не поддерживает приватные сервисы
+visitor-bind-failed = Не удалось открыть порт {$address}: {$error}
+p2p-no-reflection = Сервер встречи {$rendezvous} не отвечает
+p2p-no-answer = Агент сервиса не ответил на предложение прямого соединения
+p2p-declined = Агент сервиса отклонил прямое соединение
+p2p-punch-failed = Не удалось пробиться через NAT
+p2p-no-connection = Посетитель не открыл прямое соединение
+
+# Configuration
+config-source-env = переменная окружения {$var}
+config-source-file = файл конфигурации {$path}
+config-overridden = Сохранено, но значение переопределено {$var}
+config-valid = Конфигурация корректна
+config-invalid = Проблем в конфигурации: {$count}
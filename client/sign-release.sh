@@ -0,0 +1,29 @@
+#!/bin/sh
+# Signs a release binary for `clo upgrade`, needs OpenSSL 3.
+#
+# The release key is kept by the maintainers, it's never committed:
+#   openssl genpkey -algorithm ed25519 -out release.pem
+# Its public half goes to PUBLIC_KEY of client/src/upgrade.rs:
+#   sign-release.sh release.pem --public-key
+# Each binary is published with `<binary>.sig` next to it, the hex encoded
+# signature of "clo <version> sha256:<hex digest of the binary>":
+#   sign-release.sh release.pem 2.4.0 clo
+set -e
+
+if [ $# -eq 2 ] && [ "$2" = "--public-key" ]; then
+openssl pkey -in "$1" -pubout -outform DER | tail -c 32 | od -An -v -tx1 | tr -d ' \n'
+echo
+exit 0
+fi
+
+if [ $# -ne 3 ]; then
+echo "Usage: $0 <key.pem> <version> <binary> | $0 <key.pem> --public-key" >&2
+exit 1
+fi
+
+digest=$(openssl dgst -sha256 -r "$3" | cut -d ' ' -f 1)
+message=$(mktemp)
+trap 'rm -f "$message"' EXIT
+printf 'clo %s sha256:%s' "$2" "$digest" > "$message"
+openssl pkeyutl -sign -rawin -inkey "$1" -in "$message" | od -An -v -tx1 | tr -d ' \n' > "$3.sig"
+echo "Signed $3 as version $2"
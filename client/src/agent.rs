@@ -0,0 +1,418 @@
+use crate::client::run_client;
+use crate::config::{ClientConfig, MaskedString};
+use crate::status::{AgentStatus, StatusRegistry};
+use anyhow::{anyhow, bail, Context, Result};
+use common::protocol::message::Message;
+use common::protocol::{
+    ClientEndpoint, ConnectState, Endpoint, EndpointRemove, ErrorInfo, ErrorKind, ServerEndpoint,
+    Stop,
+};
+use parking_lot::{Mutex, RwLock};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::{debug, warn};
+
+// Time for the client to say goodbye to the server before it's aborted
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+// The client retries unreachable servers forever, callers need an answer
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Typed API to run the agent from other programs:
+///
+/// ```no_run
+/// # async fn run(endpoint: common::protocol::ClientEndpoint) -> anyhow::Result<()> {
+/// use client::agent::Agent;
+///
+/// let agent = Agent::builder().token("...").connect().await?;
+/// let published = agent.publish(endpoint).await?;
+/// println!("{}", published.url);
+/// agent.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Nothing is read from or written to disk unless a config file is given.
+pub struct Agent {
+    config: Arc<RwLock<ClientConfig>>,
+    command_tx: broadcast::Sender<Message>,
+    // Keeps the channel open, the client stops when nobody listens
+    _result_rx: broadcast::Receiver<Message>,
+    result_tx: broadcast::Sender<Message>,
+    published: Arc<Mutex<HashMap<String, ClientEndpoint>>>,
+    registry: Arc<StatusRegistry>,
+    timeout: Duration,
+    client: Option<JoinHandle<Result<()>>>,
+    republish: JoinHandle<()>,
+}
+
+/// Endpoint published with [`Agent::publish`]
+#[derive(Debug, Clone)]
+pub struct PublishedEndpoint {
+    pub guid: String,
+    pub url: String,
+    pub endpoint: ServerEndpoint,
+}
+
+#[derive(Default)]
+pub struct AgentBuilder {
+    server: Option<String>,
+    token: Option<String>,
+    credentials: Option<(String, MaskedString)>,
+    agent_id: Option<String>,
+    config: Option<ClientConfig>,
+    config_file: Option<PathBuf>,
+    timeout: Option<Duration>,
+}
+
+impl AgentBuilder {
+    /// Server URL, the one of the build by default
+    pub fn server(mut self, server: impl Into<String>) -> Self {
+        self.server = Some(server.into());
+        self
+    }
+
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Log in with email and password instead of a token
+    pub fn credentials(mut self, email: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((email.into(), MaskedString(password.into())));
+        self
+    }
+
+    /// Stable agent id, a random one is used by default
+    pub fn agent_id(mut self, agent_id: impl Into<String>) -> Self {
+        self.agent_id = Some(agent_id.into());
+        self
+    }
+
+    /// Start from this config instead of the default one
+    pub fn config(mut self, config: ClientConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Load the config from the file and save the issued token there
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Time to wait for the server in `connect` and `publish`, 30 seconds
+    /// by default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Connect to the server, the agent reconnects on its own afterwards
+    pub async fn connect(self) -> Result<Agent> {
+        let mut config = match (self.config, self.config_file) {
+            (_, Some(path)) => ClientConfig::from_file(&path, false, false)?,
+            (Some(config), None) => config,
+            (None, None) => ClientConfig::default(),
+        };
+        // Configs not loaded from a file have nowhere to be saved
+        if config.get_config_path().as_os_str().is_empty() {
+            config.readonly = true;
+        }
+        if let Some(server) = self.server {
+            config.server = server.parse().context("Invalid server URL")?;
+        }
+        if let Some(token) = self.token {
+            config.token = Some(token.as_str().into());
+        }
+        if let Some(agent_id) = self.agent_id {
+            config.agent_id = agent_id;
+        }
+        config.credentials = self.credentials;
+        if config.credentials.is_none() {
+            config.validate()?;
+        }
+        Agent::connect(config, self.timeout.unwrap_or(DEFAULT_TIMEOUT)).await
+    }
+}
+
+impl Agent {
+    pub fn builder() -> AgentBuilder {
+        AgentBuilder::default()
+    }
+
+    async fn connect(config: ClientConfig, timeout: Duration) -> Result<Self> {
+        let config = Arc::new(RwLock::new(config));
+        let (command_tx, command_rx) = broadcast::channel(1024);
+        let (result_tx, result_rx) = broadcast::channel(1024);
+        let mut events = result_tx.subscribe();
+        let published: Arc<Mutex<HashMap<String, ClientEndpoint>>> = Default::default();
+        let registry: Arc<StatusRegistry> = Default::default();
+
+        let republish = tokio::spawn(republish(
+            result_tx.subscribe(),
+            command_tx.clone(),
+            published.clone(),
+        ));
+        let client = tokio::spawn(run_client(
+            config.clone(),
+            registry.clone(),
+            command_rx,
+            result_tx.clone(),
+        ));
+
+        let agent = Self {
+            config,
+            command_tx,
+            _result_rx: result_rx,
+            result_tx,
+            published,
+            registry,
+            timeout,
+            client: Some(client),
+            republish,
+        };
+
+        // Dropping the agent on error stops the client
+        wait_for(&mut events, timeout, |msg| match msg {
+            Message::ConnectState(st) if *st == ConnectState::Connected as i32 => Ok(Some(())),
+            Message::Error(err) => match error_kind(err) {
+                ErrorKind::AuthFailed | ErrorKind::Fatal => Err(anyhow!("{}", err.message)),
+                _ => Ok(None),
+            },
+            _ => Ok(None),
+        })
+        .await?;
+        Ok(agent)
+    }
+
+    /// Publish the endpoint and wait until it's online
+    pub async fn publish(&self, endpoint: ClientEndpoint) -> Result<PublishedEndpoint> {
+        let mut events = self.events();
+        self.command_tx
+            .send(Message::EndpointStart(endpoint.clone()))
+            .context("Failed to send EndpointStart message")?;
+
+        let endpoint = wait_for(&mut events, self.timeout, |msg| match msg {
+            Message::EndpointAck(ack)
+                if ack.status.as_deref() == Some("online") && is_ack_of(ack, &endpoint) =>
+            {
+                Ok(Some(ack.clone()))
+            }
+            Message::Error(err) => publish_error(err),
+            _ => Ok(None),
+        })
+        .await?;
+
+        self.published
+            .lock()
+            .insert(endpoint.guid.clone(), endpoint.client.clone().unwrap());
+        Ok(PublishedEndpoint {
+            guid: endpoint.guid.clone(),
+            url: endpoint.as_url(),
+            endpoint,
+        })
+    }
+
+    /// Remove the endpoint from the server
+    pub async fn unpublish(&self, guid: &str) -> Result<()> {
+        self.published.lock().remove(guid);
+        let mut events = self.events();
+        self.command_tx
+            .send(Message::EndpointRemove(EndpointRemove {
+                guid: guid.to_string(),
+            }))
+            .context("Failed to send EndpointRemove message")?;
+
+        wait_for(&mut events, self.timeout, |msg| match msg {
+            Message::EndpointRemoveAck(ack) if ack.guid == guid => Ok(Some(())),
+            Message::Error(err) => publish_error(err),
+            _ => Ok(None),
+        })
+        .await
+    }
+
+    /// Messages of the client: connection states, acks, errors and progress
+    pub fn events(&self) -> broadcast::Receiver<Message> {
+        self.result_tx.subscribe()
+    }
+
+    /// Send a raw protocol command, the answer comes to [`Agent::events`]
+    pub fn send(&self, msg: Message) -> Result<()> {
+        self.command_tx
+            .send(msg)
+            .context("Failed to send command")?;
+        Ok(())
+    }
+
+    pub fn config(&self) -> Arc<RwLock<ClientConfig>> {
+        self.config.clone()
+    }
+
+    /// Connection state and endpoint stats of this agent, as in `clo status`
+    pub fn status(&self) -> AgentStatus {
+        self.registry.snapshot()
+    }
+
+    /// Close the control channel and wait for the client to stop
+    pub async fn shutdown(mut self) {
+        self.republish.abort();
+        self.command_tx.send(Message::Stop(Stop {})).ok();
+        if let Some(client) = self.client.take() {
+            stop(client).await;
+        }
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        self.republish.abort();
+        let Some(client) = self.client.take() else {
+            return;
+        };
+        self.command_tx.send(Message::Stop(Stop {})).ok();
+        match tokio::runtime::Handle::try_current() {
+            Ok(rt) => {
+                rt.spawn(stop(client));
+            }
+            Err(_) => client.abort(),
+        }
+    }
+}
+
+/// Next event of [`Agent::events`] for bindings, as a JSON object:
+///
+/// - `{"type": "ConnectState", "state": "Connected"}`
+/// - `{"type": "EndpointAck", "guid": "...", "status": "online", "url": "..."}`
+/// - `{"type": "Error", "kind": "Fatal", "message": "..."}`
+/// - `{"type": "Progress", "message": "...", "current": 10, "total": 100}`
+///
+/// Other messages are skipped. Returns `None` when the client is stopped.
+pub async fn next_event(events: &mut broadcast::Receiver<Message>) -> Option<Value> {
+    loop {
+        match events.recv().await {
+            Ok(msg) => {
+                if let Some(event) = event_json(&msg) {
+                    return Some(event);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+fn event_json(msg: &Message) -> Option<Value> {
+    let event = match msg {
+        Message::ConnectState(st) => json!({
+            "type": "ConnectState",
+            "state": format!("{:?}", ConnectState::try_from(*st).ok()?),
+        }),
+        Message::EndpointAck(ep) if ep.client.is_some() => json!({
+            "type": "EndpointAck",
+            "guid": ep.guid,
+            "status": ep.status,
+            "url": ep.as_redacted_url(),
+        }),
+        Message::Error(err) => json!({
+            "type": "Error",
+            "kind": format!("{:?}", error_kind(err)),
+            "message": err.message,
+        }),
+        Message::Progress(info) => json!({
+            "type": "Progress",
+            "message": info.message,
+            "current": info.current,
+            "total": info.total,
+        }),
+        _ => return None,
+    };
+    Some(event)
+}
+
+async fn stop(mut client: JoinHandle<Result<()>>) {
+    match time::timeout(SHUTDOWN_TIMEOUT, &mut client).await {
+        Ok(Ok(Err(err))) => debug!("Client stopped with error: {:#}", err),
+        Ok(_) => debug!("Client stopped"),
+        Err(_) => {
+            warn!("Client didn't stop in {:?}, aborting", SHUTDOWN_TIMEOUT);
+            client.abort();
+        }
+    }
+}
+
+// The server forgets the endpoints of a disconnected agent, the first
+// connect has nothing to publish yet
+async fn republish(
+    mut events: broadcast::Receiver<Message>,
+    command_tx: broadcast::Sender<Message>,
+    published: Arc<Mutex<HashMap<String, ClientEndpoint>>>,
+) {
+    loop {
+        match events.recv().await {
+            Ok(Message::ConnectState(st)) if st == ConnectState::Connected as i32 => {
+                let endpoints: Vec<_> = published.lock().values().cloned().collect();
+                for endpoint in endpoints {
+                    command_tx.send(Message::EndpointStart(endpoint)).ok();
+                }
+            }
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+// Read events until `f` picks the result
+async fn wait_for<R>(
+    events: &mut broadcast::Receiver<Message>,
+    timeout: Duration,
+    f: impl FnMut(&Message) -> Result<Option<R>>,
+) -> Result<R> {
+    time::timeout(timeout, recv_until(events, f))
+        .await
+        .map_err(|_| anyhow!("No answer from the server in {:?}", timeout))?
+}
+
+async fn recv_until<R>(
+    events: &mut broadcast::Receiver<Message>,
+    mut f: impl FnMut(&Message) -> Result<Option<R>>,
+) -> Result<R> {
+    loop {
+        match events.recv().await {
+            Ok(msg) => {
+                if let Some(res) = f(&msg)? {
+                    return Ok(res);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(n)) => debug!("Skipped {} events", n),
+            Err(broadcast::error::RecvError::Closed) => bail!("Client is stopped"),
+        }
+    }
+}
+
+// Network errors are retried by the client
+fn publish_error<R>(err: &ErrorInfo) -> Result<Option<R>> {
+    match error_kind(err) {
+        ErrorKind::HandshakeFailed => Ok(None),
+        _ => Err(anyhow!("{}", err.message)),
+    }
+}
+
+fn error_kind(err: &ErrorInfo) -> ErrorKind {
+    err.kind.try_into().unwrap_or(ErrorKind::Fatal)
+}
+
+// The server fills in defaults, so only the local address identifies the ack
+fn is_ack_of(ack: &ServerEndpoint, endpoint: &ClientEndpoint) -> bool {
+    ack.client.as_ref().is_some_and(|client| {
+        client.local_proto == endpoint.local_proto
+            && client.local_addr == endpoint.local_addr
+            && client.local_port == endpoint.local_port
+            && client.local_path == endpoint.local_path
+    })
+}
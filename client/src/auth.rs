@@ -0,0 +1,177 @@
+//! Login without the password in the command line.
+//!
+//! The device login is the device authorization grant (RFC 8628) against
+//! the server, or `--auth-url` if it's given. Both endpoints take a form
+//! and answer JSON:
+//!
+//! - `POST api/device/code` with `client_id=clo` and `agent_id` answers
+//!   `device_code`, `user_code`, `verification_uri`, `expires_in`, and
+//!   optionally `verification_uri_complete` and `interval` in seconds.
+//! - `POST api/device/token` with `grant_type`, `device_code` and
+//!   `client_id` answers `access_token` once the code is approved, or
+//!   `error` with `authorization_pending`, `slow_down`, `access_denied` or
+//!   `expired_token`. The status of error answers doesn't matter.
+
+use crate::config::{ClientConfig, MaskedString};
+use crate::shell::http_client;
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::io::BufRead;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::debug;
+use url::Url;
+
+// Endpoints of the device authorization grant, relative to the server
+const DEVICE_CODE_PATH: &str = "api/device/code";
+const DEVICE_TOKEN_PATH: &str = "api/device/token";
+const DEVICE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+const CLIENT_ID: &str = "clo";
+const DEFAULT_INTERVAL_SECS: u64 = 5;
+// Added to the polling interval when the server asks to slow down
+const SLOW_DOWN_SECS: u64 = 5;
+
+#[derive(Deserialize)]
+struct DeviceCodeResponse {
+    device_code: MaskedString,
+    user_code: String,
+    verification_uri: String,
+    verification_uri_complete: Option<String>,
+    expires_in: u64,
+    interval: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: Option<MaskedString>,
+    error: Option<String>,
+}
+
+/// Login of `clo login --device`: the user approves the code in a browser
+/// on any device while the agent polls for the token
+pub struct DeviceLogin {
+    client: reqwest::Client,
+    token_url: Url,
+    device_code: MaskedString,
+    pub user_code: String,
+    /// Page to enter the code on, with the code already filled in if supported
+    pub verification_uri: String,
+    interval: Duration,
+    expires_at: Instant,
+}
+
+impl DeviceLogin {
+    /// Request a code from the server, or from `auth_url` if it's given
+    pub async fn start(config: &ClientConfig, auth_url: Option<&str>) -> Result<Self> {
+        let base = match auth_url {
+            Some(url) => url.parse().context(crate::t!("invalid-url"))?,
+            None => config.server.clone(),
+        };
+        let base = with_trailing_slash(base);
+        let client = http_client(config)?;
+
+        let code: DeviceCodeResponse = post(
+            &client,
+            base.join(DEVICE_CODE_PATH)?,
+            &[
+                ("client_id", CLIENT_ID),
+                ("agent_id", config.agent_id.as_str()),
+            ],
+        )
+        .await
+        .context(crate::t!("device-unsupported"))?;
+
+        Ok(Self {
+            client,
+            token_url: base.join(DEVICE_TOKEN_PATH)?,
+            device_code: code.device_code,
+            user_code: code.user_code,
+            verification_uri: code
+                .verification_uri_complete
+                .unwrap_or(code.verification_uri),
+            interval: Duration::from_secs(code.interval.unwrap_or(DEFAULT_INTERVAL_SECS)),
+            expires_at: Instant::now() + Duration::from_secs(code.expires_in),
+        })
+    }
+
+    /// Poll until the code is approved, denied or expired
+    pub async fn wait(mut self) -> Result<MaskedString> {
+        loop {
+            tokio::time::sleep(self.interval).await;
+            if Instant::now() >= self.expires_at {
+                bail!(crate::t!("device-code-expired"));
+            }
+
+            let res: TokenResponse = post(
+                &self.client,
+                self.token_url.clone(),
+                &[
+                    ("grant_type", DEVICE_GRANT_TYPE),
+                    ("device_code", &*self.device_code),
+                    ("client_id", CLIENT_ID),
+                ],
+            )
+            .await?;
+
+            if let Some(token) = res.access_token {
+                return Ok(token);
+            }
+            match res.error.as_deref() {
+                Some("authorization_pending") => {}
+                Some("slow_down") => self.interval += Duration::from_secs(SLOW_DOWN_SECS),
+                Some("access_denied") => bail!(crate::t!("device-access-denied")),
+                Some("expired_token") => bail!(crate::t!("device-code-expired")),
+                err => bail!("Device login failed: {}", err.unwrap_or("no token")),
+            }
+            debug!("Waiting for device login approval");
+        }
+    }
+}
+
+/// Token of `clo login --token-file`, surrounding whitespace is ignored
+pub fn read_token_file(path: &Path) -> Result<MaskedString> {
+    let token = MaskedString(std::fs::read_to_string(path).with_context(
+        || crate::t!("error-reading-token-file", "path" => path.display().to_string()),
+    )?);
+    let token = token.trim();
+    if token.is_empty() {
+        bail!(crate::t!("token-file-empty", "path" => path.display().to_string()));
+    }
+    Ok(token.into())
+}
+
+/// Password of `clo login --password-stdin`: the first line without the line break
+pub fn read_password(reader: &mut impl BufRead) -> Result<MaskedString> {
+    let mut password = MaskedString::default();
+    reader
+        .read_line(&mut password.0)
+        .context(crate::t!("error-reading-password"))?;
+    Ok(password.trim_end_matches(['\r', '\n']).into())
+}
+
+// Errors come with 400 and a JSON body too, so the status is not checked
+async fn post<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: Url,
+    form: &[(&str, &str)],
+) -> Result<T> {
+    let res = client
+        .post(url.clone())
+        .form(form)
+        .send()
+        .await
+        .with_context(|| format!("Failed to POST to '{}'", url))?;
+    let status = res.status();
+    let body = res.bytes().await.context("Failed to read the response")?;
+    serde_json::from_slice(&body).with_context(|| format!("Unexpected response ({})", status))
+}
+
+// `Url::join` replaces the last segment of paths without a trailing slash
+fn with_trailing_slash(mut url: Url) -> Url {
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    url
+}
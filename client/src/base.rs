@@ -1,28 +1,35 @@
 use crate::client::run_client;
-use crate::commands::{Commands, ServiceAction};
+use crate::commands::{Commands, ConfigAction, ServiceAction};
 pub use crate::config::ClientConfig;
+use crate::error_page::set_maintenance;
+use crate::exec;
+use crate::health::{STATUS_ONLINE, STATUS_UNHEALTHY};
+use crate::i18n::LOCALIZER;
+use crate::logs::{endpoint_logs, tail};
 use crate::ping;
-use crate::service::{create_service_manager, ServiceConfig, ServiceStatus};
+use crate::service::{agent_service_config, create_service_manager, ServiceStatus};
 use crate::shell::get_cache_dir;
+use crate::status;
 use anyhow::{bail, Context, Result};
 use clap::Parser;
 use common::logging::{init_log, WorkerGuard};
 use common::protocol::message::Message;
 use common::protocol::{
-    ConnectState, EndpointClear, EndpointList, EndpointRemove, EndpointStartAll, EndpointStop,
-    ErrorKind, Stop,
+    ConnectState, EndpointClear, EndpointList, EndpointRemove, EndpointStartAll, EndpointStatus,
+    EndpointStop, ErrorKind, ServerEndpoint, Stop, UpgradeCheck, VisitorConnect,
 };
 use common::version::{LONG_VERSION, VERSION};
 use dirs::cache_dir;
 use indicatif::{ProgressBar, ProgressStyle};
 use parking_lot::RwLock;
-use std::env;
 use std::io::Write;
+use std::process::ExitStatus;
 use std::sync::Arc;
-use tokio::sync::broadcast;
-use tracing::debug;
+use tokio::sync::{broadcast, oneshot};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
 
-const CONFIG_FILE: &str = "client.toml";
+pub const CONFIG_FILE: &str = "client.toml";
 
 #[derive(Parser, Debug)]
 #[command(about, version(VERSION), long_version(LONG_VERSION))]
@@ -37,40 +44,24 @@ pub struct Cli {
     pub conf: Option<String>,
     #[clap(short, long, default_value = "false", help = "Read-only config mode")]
     pub readonly: bool,
+    #[clap(
+        long,
+        value_parser = ["en", "ru"],
+        help = "Language of the messages (detected from LANG by default)"
+    )]
+    pub lang: Option<String>,
+    #[cfg(feature = "tui")]
+    #[clap(
+        long,
+        default_value = "false",
+        help = "Show full-screen dashboard for publish and run"
+    )]
+    pub tui: bool,
 }
 
 fn handle_service_command(action: &ServiceAction, config: &ClientConfig) -> Result<()> {
-    // Get the current executable path
-    let exe_path = env::current_exe().context("Failed to get current executable path")?;
-
-    // Prepare config file argument
-    let mut args = Vec::new();
-    if let Some(path_str) = config.get_config_path().to_str() {
-        args.push("--conf".to_string());
-        args.push(path_str.to_string());
-    }
-
-    // Add the run command for the service
-    args.push("run".to_string());
-
-    // On Windows, add the service flag
-    #[cfg(target_os = "windows")]
-    {
-        args.push("--run-as-service".to_string());
-    }
-
     // Create service configuration
-    let service_config = ServiceConfig {
-        #[cfg(target_os = "macos")]
-        name: "ru.cloudpub.clo".to_string(),
-        #[cfg(not(target_os = "macos"))]
-        name: "cloudpub".to_string(),
-        display_name: "CloudPub Client".to_string(),
-        description: "CloudPub Client Service".to_string(),
-        executable_path: exe_path,
-        args,
-        config_path: Some(config.get_config_path().to_owned()),
-    };
+    let service_config = agent_service_config(config)?;
 
     // Create the appropriate service manager for the current platform
     let service_manager = create_service_manager(service_config);
@@ -78,27 +69,29 @@ fn handle_service_command(action: &ServiceAction, config: &ClientConfig) -> Resu
     match action {
         ServiceAction::Install => {
             service_manager.install()?;
-            println!("Service installed successfully");
+            println!("{}", crate::t!("service-installed"));
         }
         ServiceAction::Uninstall => {
             service_manager.uninstall()?;
-            println!("Service uninstalled successfully");
+            println!("{}", crate::t!("service-uninstalled"));
         }
         ServiceAction::Start => {
             service_manager.start()?;
-            println!("Service started successfully");
+            println!("{}", crate::t!("service-started"));
         }
         ServiceAction::Stop => {
             service_manager.stop()?;
-            println!("Service stopped successfully");
+            println!("{}", crate::t!("service-stopped-successfully"));
         }
         ServiceAction::Status => {
             let status = service_manager.status()?;
             match status {
-                ServiceStatus::Running => println!("Service is running"),
-                ServiceStatus::Stopped => println!("Service is stopped"),
-                ServiceStatus::NotInstalled => println!("Service is not installed"),
-                ServiceStatus::Unknown => println!("Service status is unknown"),
+                ServiceStatus::Running => println!("{}", crate::t!("service-status-running")),
+                ServiceStatus::Stopped => println!("{}", crate::t!("service-status-stopped")),
+                ServiceStatus::NotInstalled => {
+                    println!("{}", crate::t!("service-status-not-installed"))
+                }
+                ServiceStatus::Unknown => println!("{}", crate::t!("service-status-unknown")),
             }
         }
     }
@@ -116,30 +109,49 @@ pub fn init(args: &Cli, gui: bool) -> Result<(WorkerGuard, Arc<RwLock<ClientConf
 
     let log_file = log_dir.join("client.log");
 
+    // The dashboard owns the terminal, logs go only to the file then
+    #[cfg(feature = "tui")]
+    let verbose = args.verbose
+        && !(args.tui && matches!(args.command, Commands::Publish(_) | Commands::Run));
+    #[cfg(not(feature = "tui"))]
+    let verbose = args.verbose;
+
     let guard = init_log(
         &args.log_level,
         &log_file,
-        args.verbose,
+        verbose,
         10 * 1024 * 1024,
         2,
     )
     .context("Failed to initialize logging")?;
 
+    // The option wins over the config, the config over the environment
+    if let Some(lang) = args.lang.as_ref() {
+        LOCALIZER.set_locale(lang);
+    }
+
     let config = if let Some(path) = args.conf.as_ref() {
         ClientConfig::from_file(&path.into(), args.readonly, gui)?
     } else {
         ClientConfig::load(CONFIG_FILE, true, args.readonly, gui)?
     };
+    if let (None, Some(lang)) = (args.lang.as_ref(), config.lang.as_ref()) {
+        LOCALIZER.set_locale(lang);
+    }
     let config = Arc::new(RwLock::new(config));
     Ok((guard, config))
 }
 
 #[tokio::main]
 pub async fn cli_main(cli: Cli, config: Arc<RwLock<ClientConfig>>) -> Result<()> {
-    ctrlc::set_handler(move || {
-        std::process::exit(1);
-    })
-    .context("Error setting Ctrl-C handler")?;
+    // Launched application gets the signals first and the endpoint is unpublished after it exits
+    let exec = matches!(cli.command, Commands::Publish(ref args) if !args.exec.is_empty());
+    if !exec {
+        ctrlc::set_handler(move || {
+            std::process::exit(1);
+        })
+        .context("Error setting Ctrl-C handler")?;
+    }
 
     let (command_tx, command_rx) = broadcast::channel(1024);
     main_loop(cli, config, command_tx, command_rx, None, None).await
@@ -167,6 +179,26 @@ pub async fn main_loop(
     stdout: Option<broadcast::Sender<String>>,
     stderr: Option<broadcast::Sender<String>>,
 ) -> Result<()> {
+    let (result_tx, mut result_rx) = broadcast::channel(1024);
+
+    // The dashboard owns the terminal, so the output goes there
+    #[cfg(feature = "tui")]
+    let dashboard = match cli.command {
+        Commands::Publish(_) | Commands::Run if cli.tui => Some(
+            crate::dashboard::Dashboard::spawn(command_tx.clone(), result_tx.clone())?,
+        ),
+        _ => None,
+    };
+    #[cfg(feature = "tui")]
+    let (stdout, stderr) = match dashboard.as_ref() {
+        Some(dashboard) => (Some(dashboard.stdout()), Some(dashboard.stderr())),
+        None => (stdout, stderr),
+    };
+    #[cfg(feature = "tui")]
+    let tui = dashboard.is_some();
+    #[cfg(not(feature = "tui"))]
+    let tui = false;
+
     let write_stdout = |res: String| {
         if let Some(tx) = stdout.as_ref() {
             tx.send(res).ok();
@@ -183,26 +215,144 @@ pub async fn main_loop(
         }
     };
 
-    let (result_tx, mut result_rx) = broadcast::channel(1024);
-
     let mut pings = 1;
 
     match &mut cli.command {
-        Commands::Set(set_args) => {
+        Commands::Set(set_args)
+        | Commands::Config {
+            action: ConfigAction::Set(set_args),
+        } => {
             config.write().set(&set_args.key, &set_args.value)?;
+            if let Some(var) = config.read().env_var(&set_args.key) {
+                write_stderr(crate::t!("config-overridden", "var" => var));
+            }
+            return Ok(());
+        }
+        Commands::Get(get_args)
+        | Commands::Config {
+            action: ConfigAction::Get(get_args),
+        } => {
+            let config = config.read();
+            let value = config.get(&get_args.key)?;
+            if get_args.source {
+                let source = match config.env_var(&get_args.key) {
+                    Some(var) => crate::t!("config-source-env", "var" => var),
+                    None => crate::t!(
+                        "config-source-file",
+                        "path" => config.get_config_path().display().to_string()
+                    ),
+                };
+                write_stdout(format!("{} ({})", value, source));
+            } else {
+                write_stdout(value);
+            }
+            return Ok(());
+        }
+        Commands::Config {
+            action: ConfigAction::Unset { key },
+        } => {
+            config.write().unset(key)?;
+            if let Some(var) = config.read().env_var(key) {
+                write_stderr(crate::t!("config-overridden", "var" => var));
+            }
+            return Ok(());
+        }
+        Commands::Config {
+            action: ConfigAction::List,
+        } => {
+            let config = config.read();
+            for (key, value) in config.list()? {
+                match config.env_var(&key) {
+                    Some(var) => write_stdout(format!("{} = {} ({})", key, value, var)),
+                    None => write_stdout(format!("{} = {}", key, value)),
+                }
+            }
             return Ok(());
         }
-        Commands::Get(get_args) => {
-            let value = config.read().get(&get_args.key)?;
-            write_stdout(value);
+        Commands::Config {
+            action: ConfigAction::Validate,
+        } => {
+            let errors = config.read().errors();
+            if errors.is_empty() {
+                write_stdout(crate::t!("config-valid"));
+                return Ok(());
+            }
+            for err in &errors {
+                write_stderr(format!("- {}", err));
+            }
+            bail!(crate::t!("config-invalid", "count" => errors.len()));
+        }
+        Commands::Status(args) => {
+            let config = config.read().clone();
+            let agent = status::query(&config).await?;
+            if args.json {
+                write_stdout(serde_json::to_string_pretty(&agent)?);
+            } else {
+                write_stdout(status::render(&agent));
+            }
             return Ok(());
         }
+        Commands::Logs(args) => {
+            // Logs are local, the server is needed only to resolve the service name
+            if Uuid::parse_str(&args.target).is_ok() {
+                let logs = endpoint_logs(&args.target)?;
+                if !logs.is_empty() {
+                    tail(&logs, args.lines, args.follow, write_stdout).await?;
+                    return Ok(());
+                }
+            }
+            config.read().validate()?;
+        }
+        Commands::Maintenance(args) => {
+            // Flags are local, the server is needed only to resolve the service name
+            if Uuid::parse_str(&args.target).is_ok() {
+                write_stdout(maintenance(&args.target, &args.state)?);
+                return Ok(());
+            }
+            config.read().validate()?;
+        }
+        Commands::Doctor => {
+            let config = config.read().clone();
+            crate::doctor::run(&config, write_stdout).await?;
+            return Ok(());
+        }
+        Commands::Upgrade(args) => {
+            if args.rollback {
+                crate::upgrade::rollback()?;
+                write_stdout(crate::t!("upgrade-rolled-back"));
+                if crate::upgrade::restart_service(&config.read())? {
+                    write_stdout(crate::t!("upgrade-service-restarted"));
+                }
+                return Ok(());
+            }
+            config.read().validate()?;
+        }
         Commands::Purge => {
             let cache_dir = get_cache_dir("")?;
             debug!("Purge cache dir: {:?}", cache_dir.to_str().unwrap());
             std::fs::remove_dir_all(&cache_dir).ok();
             return Ok(());
         }
+        Commands::Login(args) if args.token_file.is_some() => {
+            let token = crate::auth::read_token_file(args.token_file.as_ref().unwrap())?;
+            config.write().token = Some(token);
+        }
+        Commands::Login(args) if args.device => {
+            let current = config.read().clone();
+            let login = crate::auth::DeviceLogin::start(&current, args.auth_url.as_deref()).await?;
+            write_stdout(crate::t!(
+                "device-login",
+                "url" => login.verification_uri.clone(),
+                "code" => login.user_code.clone()
+            ));
+            let spinner =
+                (!tui && stdout.is_none()).then(|| make_spinner(crate::t!("device-waiting")));
+            let res = login.wait().await;
+            if let Some(spinner) = spinner {
+                spinner.finish_and_clear();
+            }
+            config.write().token = Some(res?);
+        }
         Commands::Login(args) => {
             let email = match &args.email {
                 Some(email) => email.clone(),
@@ -221,11 +371,14 @@ pub async fn main_loop(
             };
 
             let password = match &args.password {
+                _ if args.password_stdin => {
+                    crate::auth::read_password(&mut std::io::stdin().lock())?
+                }
                 Some(pwd) => pwd.clone(),
                 None => {
                     // Try to read from environment first
                     if let Ok(pwd) = std::env::var("PASSWORD") {
-                        pwd
+                        pwd.into()
                     } else {
                         // If not in environment, prompt the user
                         if let Some(tx) = stdout.as_ref() {
@@ -234,7 +387,7 @@ pub async fn main_loop(
                             print!("{}", crate::t!("enter-password"));
                             std::io::stdout().flush().ok();
                         }
-                        rpassword::read_password().unwrap_or_default()
+                        rpassword::read_password().unwrap_or_default().into()
                     }
                 }
             };
@@ -251,6 +404,9 @@ pub async fn main_loop(
         }
         Commands::Register(publish_args) => {
             config.read().validate()?;
+            if !publish_args.exec.is_empty() {
+                bail!(crate::t!("error-exec-register"));
+            }
             publish_args.parse()?;
         }
         Commands::Publish(publish_args) => {
@@ -258,6 +414,7 @@ pub async fn main_loop(
             publish_args.parse()?;
         }
         Commands::Unpublish(_)
+        | Commands::Connect(_)
         | Commands::Run
         | Commands::Stop
         | Commands::Break
@@ -273,39 +430,153 @@ pub async fn main_loop(
 
     debug!("Config: {:?}", config);
 
-    tokio::spawn(run_client(config.clone(), command_rx, result_tx));
+    // Long running agents answer `clo status`
+    let _status_server = match cli.command {
+        Commands::Run | Commands::Publish(_) => {
+            let config = config.read().clone();
+            status::serve(&config).await.map_err(|err| warn!("{:#}", err)).ok()
+        }
+        _ => None,
+    };
+
+    tokio::spawn(run_client(
+        config.clone(),
+        status::REGISTRY.clone(),
+        command_rx,
+        result_tx.clone(),
+    ));
 
     let mut current_spinner = None;
     let mut progress_bar = None;
+    // Exit status of the application launched with `publish -- cmd`
+    let mut app_exit: Option<oneshot::Receiver<Result<ExitStatus>>> = None;
+    let mut app_status: Option<ExitStatus> = None;
+    // The endpoint of the application until it starts listening
+    let mut app_ready: Option<oneshot::Receiver<Result<ServerEndpoint>>> = None;
+    // The server offers the upgrade on every reconnect
+    let mut upgrading = false;
 
     loop {
-        match result_rx.recv().await? {
+        let msg = tokio::select! {
+            msg = result_rx.recv() => msg?,
+            ready = async { app_ready.as_mut().unwrap().await }, if app_ready.is_some() => {
+                app_ready = None;
+                match ready {
+                    Ok(Ok(endpoint)) => {
+                        command_tx.send(Message::EndpointStatus(EndpointStatus {
+                            guid: endpoint.guid.clone(),
+                            status: STATUS_ONLINE.to_string(),
+                        }))?;
+                        write_stdout(
+                            crate::t!("service-published", "endpoint" => endpoint.to_string()),
+                        );
+                    }
+                    Ok(Err(err)) => {
+                        // The application is killed and unpublished on timeout
+                        if let Some(app_exit) = app_exit.take() {
+                            app_exit.await.ok();
+                        }
+                        command_tx.send(Message::Stop(Stop {})).ok();
+                        return Err(err);
+                    }
+                    // Exited before listening, the exit status is reported instead
+                    Err(_) => {}
+                }
+                continue;
+            }
+            exit = async { app_exit.as_mut().unwrap().await }, if app_exit.is_some() => {
+                app_exit = None;
+                // The endpoint is stopped next, its ack ends the loop
+                app_status = Some(exit.context("Application supervisor failed")??);
+                continue;
+            }
+        };
+        match msg {
             Message::Error(err) => {
                 let kind: ErrorKind = err.kind.try_into().unwrap_or(ErrorKind::Fatal);
-                if kind == ErrorKind::Fatal || kind == ErrorKind::AuthFailed {
+                // Visitors not in the acl of the private service can't do anything
+                let denied = kind == ErrorKind::PermissionDenied
+                    && matches!(cli.command, Commands::Connect(_));
+                let upgrade =
+                    kind == ErrorKind::UpgradeFailed && matches!(cli.command, Commands::Upgrade(_));
+                if kind == ErrorKind::Fatal || kind == ErrorKind::AuthFailed || denied || upgrade {
                     command_tx.send(Message::Stop(Stop {})).ok();
                     bail!("{}", err.message);
                 }
+                // A failed service process must not take down other services
+                if kind == ErrorKind::ExecuteFailed || kind == ErrorKind::CrashLoop {
+                    write_stderr(err.message);
+                } else if kind == ErrorKind::UpgradeFailed {
+                    upgrading = false;
+                    write_stderr(err.message);
+                }
             }
 
+            Message::UpgradeCheckAck(ack) => match ack.upgrade {
+                _ if upgrading => {}
+                Some(info) if crate::upgrade::is_newer(&info.version) => {
+                    upgrading = true;
+                    crate::upgrade::spawn(
+                        info,
+                        config.clone(),
+                        command_tx.subscribe(),
+                        result_tx.clone(),
+                    );
+                }
+                _ => {
+                    write_stdout(crate::t!("upgrade-up-to-date", "version" => VERSION));
+                    break;
+                }
+            },
+
             Message::UpgradeAvailable(info) => match cli.command {
+                _ if upgrading => {}
+                Commands::Run if config.read().auto_upgrade && crate::upgrade::is_enabled() => {
+                    upgrading = true;
+                    write_stderr(crate::t!("upgrade-available", "version" => info.version.clone()));
+                    crate::upgrade::spawn(
+                        info,
+                        config.clone(),
+                        command_tx.subscribe(),
+                        result_tx.clone(),
+                    );
+                }
                 Commands::Publish(_) | Commands::Run => {
                     write_stderr(crate::t!("upgrade-available", "version" => info.version));
                 }
                 _ => {}
             },
 
+            Message::UpgradeInstalled(info) => {
+                write_stdout(crate::t!("upgrade-installed", "version" => info.version));
+                // The service restarts this process if it runs as the service
+                match crate::upgrade::restart_service(&config.read()) {
+                    Ok(true) => write_stdout(crate::t!("upgrade-service-restarted")),
+                    Ok(false) if matches!(cli.command, Commands::Run) => {
+                        write_stderr(crate::t!("upgrade-restart-required"))
+                    }
+                    Ok(false) => {}
+                    Err(err) => write_stderr(format!("{:#}", err)),
+                }
+                if let Commands::Upgrade(_) = cli.command {
+                    break;
+                }
+            }
+
             Message::EndpointAck(endpoint) => {
                 if endpoint.status == Some("online".to_string()) {
                     match cli.command {
                         Commands::Ping(ref args) => {
                             current_spinner = Some(make_spinner(crate::t!("measuring-speed")));
-                            let stats = ping::ping_test(endpoint, args.bare).await?;
+                            let config = config.read().clone();
+                            let report = ping::ping_test(endpoint, args, &config).await?;
                             current_spinner.take();
-                            if args.bare {
-                                write_stdout(stats.to_string());
+                            if args.json {
+                                write_stdout(serde_json::to_string_pretty(&report)?);
+                            } else if args.bare {
+                                write_stdout(report.bare());
                             } else {
-                                write_stdout(stats);
+                                write_stdout(report.to_string());
                             }
                             pings -= 1;
                             if pings == 0 {
@@ -318,6 +589,23 @@ pub async fn main_loop(
                             );
                             break;
                         }
+                        // Reconnects ack the endpoint again, launch the app only once
+                        Commands::Publish(ref args)
+                            if !args.exec.is_empty()
+                                && (app_exit.is_none() || app_ready.is_some()) =>
+                        {
+                            // Visitors get the unavailable page until the application listens
+                            command_tx.send(Message::EndpointStatus(EndpointStatus {
+                                guid: endpoint.guid.clone(),
+                                status: STATUS_UNHEALTHY.to_string(),
+                            }))?;
+                            if app_exit.is_none() {
+                                let (exit, ready) =
+                                    launch_app(&args.exec, &endpoint, command_tx.clone())?;
+                                app_exit = Some(exit);
+                                app_ready = Some(ready);
+                            }
+                        }
                         Commands::Publish(_) | Commands::Run => {
                             write_stdout(
                                 crate::t!("service-published", "endpoint" => endpoint.to_string()),
@@ -328,19 +616,49 @@ pub async fn main_loop(
                 }
             }
 
+            Message::VisitorAck(endpoint) => {
+                write_stdout(crate::t!("visitor-listening",
+                    "guid" => endpoint.guid,
+                    "address" => endpoint.bind_addr));
+            }
+
+            Message::EndpointStatus(status) => match cli.command {
+                Commands::Publish(_) | Commands::Run => {
+                    if status.status == STATUS_UNHEALTHY {
+                        write_stderr(crate::t!("service-unhealthy", "guid" => status.guid));
+                    } else {
+                        write_stderr(crate::t!("service-healthy", "guid" => status.guid));
+                    }
+                }
+                _ => {}
+            },
+
             Message::EndpointStopAck(ep) => {
                 write_stdout(crate::t!("service-stopped", "guid" => ep.guid));
-                break;
+                // Endpoints are stopped from the dashboard one by one,
+                // unless it's the one of the exited application
+                if !tui || app_status.is_some() {
+                    break;
+                }
             }
 
             Message::EndpointRemoveAck(ep) => {
                 write_stdout(crate::t!("service-removed", "guid" => ep.guid));
-                break;
+                if !tui {
+                    break;
+                }
             }
 
+            // The user quit the dashboard
+            Message::Stop(_) => break,
+
             Message::ConnectState(st) => match st.try_into().unwrap_or(ConnectState::Connecting) {
                 ConnectState::Connecting => {
-                    current_spinner = Some(make_spinner(crate::t!("connecting")));
+                    if tui {
+                        write_stderr(crate::t!("connecting"));
+                    } else {
+                        current_spinner = Some(make_spinner(crate::t!("connecting")));
+                    }
                 }
 
                 ConnectState::Connected => {
@@ -349,9 +667,12 @@ pub async fn main_loop(
                     }
 
                     match cli.command {
-                        Commands::Ls => {
+                        Commands::Ls | Commands::Logs(_) | Commands::Maintenance(_) => {
                             command_tx.send(Message::EndpointList(EndpointList {}))?;
                         }
+                        Commands::Upgrade(_) => {
+                            command_tx.send(Message::UpgradeCheck(UpgradeCheck {}))?;
+                        }
                         Commands::Clean => {
                             command_tx.send(Message::EndpointClear(EndpointClear {}))?;
                         }
@@ -364,6 +685,13 @@ pub async fn main_loop(
                         Commands::Register(ref endpoint) => {
                             command_tx.send(Message::EndpointStart(endpoint.parse()?))?;
                         }
+                        Commands::Connect(ref args) => {
+                            command_tx.send(Message::VisitorConnect(VisitorConnect {
+                                guid: args.guid.clone(),
+                                bind_addr: args.local.clone(),
+                                p2p: args.p2p,
+                            }))?;
+                        }
                         Commands::Unpublish(ref args) => {
                             if args.remove {
                                 command_tx.send(Message::EndpointRemove(EndpointRemove {
@@ -378,10 +706,12 @@ pub async fn main_loop(
                         Commands::Ping(ref args) => {
                             pings = args.num.unwrap_or(1);
                             for _i in 0..pings {
-                                ping::publish(command_tx.clone()).await?;
+                                ping::publish(command_tx.clone(), args.udp).await?;
                             }
                         }
                         Commands::Login(_) => {
+                            // Token of --token-file or --device isn't sent back by the server
+                            config.read().save().context("Failed to save config")?;
                             write_stdout(crate::t!("client-authorized"));
                             break;
                         }
@@ -395,6 +725,9 @@ pub async fn main_loop(
                 }
             },
 
+            // The dashboard draws its own progress bar
+            Message::Progress(_) if tui => {}
+
             Message::Progress(info) => {
                 if info.current == 0 {
                     let bar = ProgressBar::new(info.total as u64);
@@ -414,12 +747,28 @@ pub async fn main_loop(
             }
 
             Message::EndpointListAck(list) => {
+                if let Commands::Maintenance(ref args) = cli.command {
+                    let endpoint = find_endpoint(&list.endpoints, &args.target)?;
+                    write_stdout(maintenance(&endpoint.guid, &args.state)?);
+                    break;
+                }
+                if let Commands::Logs(ref args) = cli.command {
+                    let endpoint = find_endpoint(&list.endpoints, &args.target)?;
+                    let logs = endpoint_logs(&endpoint.guid)?;
+                    if logs.is_empty() {
+                        bail!(crate::t!("no-service-logs", "guid" => endpoint.guid.clone()));
+                    }
+                    command_tx.send(Message::Stop(Stop {})).ok();
+                    tail(&logs, args.lines, args.follow, write_stdout).await?;
+                    break;
+                }
                 if list.endpoints.is_empty() {
                     write_stdout(crate::t!("no-registered-services"));
                 } else {
                     let mut output = String::new();
                     for ep in &list.endpoints {
-                        output.push_str(&format!("{}: {}\n", ep.guid, ep));
+                        let status = ep.status.as_deref().unwrap_or("offline");
+                        output.push_str(&format!("{}: [{}] {}\n", ep.guid, status, ep));
                     }
                     write_stdout(output);
                 }
@@ -439,5 +788,79 @@ pub async fn main_loop(
 
     command_tx.send(Message::Stop(Stop {})).ok();
 
+    if let Some(app_exit) = app_exit {
+        app_status = Some(app_exit.await.context("Application supervisor failed")??);
+    }
+    if let Some(status) = app_status {
+        if !status.success() {
+            bail!(crate::t!("error-exec-failed", "status" => status.to_string()));
+        }
+    }
+
     Ok(())
 }
+
+// Service by its guid or name
+fn find_endpoint<'a>(endpoints: &'a [ServerEndpoint], target: &str) -> Result<&'a ServerEndpoint> {
+    endpoints
+        .iter()
+        .find(|ep| {
+            ep.guid == target
+                || ep.client.as_ref().and_then(|c| c.description.as_deref()) == Some(target)
+        })
+        .context(crate::t!("service-not-found", "name" => target.to_string()))
+}
+
+fn maintenance(guid: &str, state: &str) -> Result<String> {
+    let enabled = state == "on";
+    set_maintenance(guid, enabled)?;
+    Ok(if enabled {
+        crate::t!("maintenance-enabled", "guid" => guid.to_string())
+    } else {
+        crate::t!("maintenance-disabled", "guid" => guid.to_string())
+    })
+}
+
+// Launch the application and unpublish the endpoint once it exits or
+// doesn't start listening in time
+#[allow(clippy::type_complexity)]
+fn launch_app(
+    args: &[String],
+    endpoint: &ServerEndpoint,
+    command_tx: broadcast::Sender<Message>,
+) -> Result<(
+    oneshot::Receiver<Result<ExitStatus>>,
+    oneshot::Receiver<Result<ServerEndpoint>>,
+)> {
+    let mut child = exec::spawn(args, endpoint)?;
+    let (exit_tx, exit_rx) = oneshot::channel();
+    let (ready_tx, ready_rx) = oneshot::channel();
+
+    let endpoint = endpoint.clone();
+    let client = endpoint.client.clone().unwrap_or_default();
+    tokio::spawn(async move {
+        let guid = endpoint.guid.clone();
+        let status = tokio::select! {
+            status = exec::supervise(&mut child) => status,
+            res = exec::wait_for_port(&client) => match res {
+                Ok(()) => {
+                    info!("Application is listening on {}", client);
+                    ready_tx.send(Ok(endpoint)).ok();
+                    exec::supervise(&mut child).await
+                }
+                Err(err) => {
+                    ready_tx.send(Err(err)).ok();
+                    child.kill().await.ok();
+                    child.wait().await.context("Failed to wait on child")
+                }
+            },
+        };
+        info!("Application exited: {:?}", status);
+        exit_tx.send(status).ok();
+        command_tx
+            .send(Message::EndpointStop(EndpointStop { guid }))
+            .ok();
+    });
+
+    Ok((exit_rx, ready_rx))
+}
@@ -3,23 +3,29 @@ use backoff::backoff::Backoff;
 use backoff::future::retry_notify;
 use backoff::ExponentialBackoff;
 use bytes::{Bytes, BytesMut};
+use common::compression::copy_bidirectional_compressed;
 use common::config::TransportType;
 use common::protocol::message::Message;
 use common::protocol::{
-    read_message, write_message, AgentInfo, ConnectState, DataChannelInfo, EndpointRemove,
-    EndpointStop, ErrorInfo, ErrorKind, HeartBeat, Protocol, ServerEndpoint, UdpTraffic,
+    local_capabilities, read_message, write_message, AgentInfo, Capabilities, Capability,
+    Compression, ConnectState, DataChannelInfo, EndpointRemove, EndpointStop, ErrorInfo, ErrorKind,
+    HeartBeat, P2pAnswer, Protocol, ServerEndpoint, UdpTraffic, VisitorConnect,
 };
 use common::transport::{
-    AddrMaybeCached, SocketOpts, TcpTransport, TlsTransport, Transport, WebsocketTransport,
+    happy_eyeballs, AddrMaybeCached, SocketOpts, TcpTransport, TlsTransport, Transport,
+    WebsocketTransport,
+};
+use common::utils::{
+    format_host_port, get_platform, local_socket_addrs, udp_connect, unix_socket_path,
 };
-use common::utils::{get_platform, udp_connect};
 use common::version::VERSION;
 use parking_lot::RwLock;
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::io::{self, copy_bidirectional, AsyncReadExt};
-use tokio::net::{TcpStream, UdpSocket};
+use tokio::io::{self, copy_bidirectional, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
 use tokio::sync::{broadcast, mpsc};
 use tokio::time::{self, Duration, Instant};
 use tracing::{debug, error, info, instrument, trace, warn};
@@ -30,22 +36,33 @@ use common::constants::{
 };
 
 use crate::config::ClientConfig;
+use crate::error_page::{self, STATUS_BAD_GATEWAY, STATUS_SERVICE_UNAVAILABLE};
+use crate::health::{HealthMonitor, LocalChecks, PausedEndpoints, STATUS_ONLINE};
+use crate::p2p::{self, P2pLink, P2pTask};
 use crate::shell::SubProcess;
+use crate::status::{ChannelStats, CountingStream, StatusRegistry};
+use crate::visitor::Visitor;
 use machineid_rs::{Encryption, HWIDComponent, IdBuilder};
 use std::fmt::{self, Debug, Formatter};
+use uuid::Uuid;
 
 #[cfg(feature = "plugins")]
 use crate::plugins::registry::PluginRegistry;
 
-struct DataChannel<T: Transport> {
-    agent_id: String,
-    remote_addr: AddrMaybeCached,
-    connector: Arc<T>,
-    socket_opts: SocketOpts,
-    endpoint: ServerEndpoint,
+pub(crate) struct DataChannel<T: Transport> {
+    pub(crate) agent_id: String,
+    pub(crate) remote_addr: AddrMaybeCached,
+    pub(crate) hosts: HashMap<String, Vec<IpAddr>>,
+    pub(crate) connector: Arc<T>,
+    pub(crate) socket_opts: SocketOpts,
+    pub(crate) endpoint: ServerEndpoint,
+    pub(crate) capabilities: Capabilities,
+    // Channels of `clo connect` lead to a private endpoint of another agent
+    pub(crate) visitor: bool,
+    pub(crate) registry: Arc<StatusRegistry>,
 }
 
-type Service<T> = Arc<DataChannel<T>>;
+pub(crate) type Service<T> = Arc<DataChannel<T>>;
 
 type Services<T> = Arc<RwLock<HashMap<String, Service<T>>>>;
 
@@ -61,12 +78,30 @@ struct Client<T: Transport> {
     services: Services<T>,
     transport: Arc<T>,
     servers: HashMap<String, (SubProcess, u16)>,
+    health: HashMap<String, HealthMonitor>,
+    // Command checks are run only as this process published them
+    local_checks: LocalChecks,
+    paused: PausedEndpoints,
+    // Listeners of `clo connect` waiting for the server ack, with the p2p flag
+    pending_visitors: HashMap<String, (TcpListener, bool)>,
+    visitors: HashMap<String, Visitor>,
+    // Acked endpoints of this agent, offered to visitors for direct connections
+    endpoints: HashMap<String, ServerEndpoint>,
+    // UDP address reflector for direct connections, empty if disabled
+    rendezvous: String,
+    p2p_answers: HashMap<String, mpsc::Sender<P2pAnswer>>,
+    p2p: HashMap<String, P2pTask>,
     connected: bool,
+    capabilities: Capabilities,
+    registry: Arc<StatusRegistry>,
 }
 
 impl<T: 'static + Transport> Client<T> {
     // Create a Client from `[client]` config block
-    async fn from(config: Arc<RwLock<ClientConfig>>) -> Result<Client<T>> {
+    async fn from(
+        config: Arc<RwLock<ClientConfig>>,
+        registry: Arc<StatusRegistry>,
+    ) -> Result<Client<T>> {
         let transport = Arc::new(
             T::new(&config.clone().read().transport)
                 .with_context(|| "Failed to create the transport")?,
@@ -75,8 +110,19 @@ impl<T: 'static + Transport> Client<T> {
             config,
             services: Default::default(),
             servers: Default::default(),
+            health: Default::default(),
+            local_checks: Default::default(),
+            paused: Default::default(),
+            pending_visitors: Default::default(),
+            visitors: Default::default(),
+            endpoints: Default::default(),
+            rendezvous: String::new(),
+            p2p_answers: Default::default(),
+            p2p: Default::default(),
             transport,
             connected: false,
+            capabilities: Default::default(),
+            registry,
         })
     }
 
@@ -95,6 +141,7 @@ impl<T: 'static + Transport> Client<T> {
         let mut retry_backoff = run_control_chan_backoff(DEFAULT_CLIENT_RETRY_INTERVAL_SECS);
 
         let mut start = Instant::now();
+        self.registry.set_state(ConnectState::Connecting);
         result_tx
             .send(Message::ConnectState(ConnectState::Connecting.into()))
             .context("Can't send Connecting event")?;
@@ -129,6 +176,16 @@ impl<T: 'static + Transport> Client<T> {
             }
 
             services.write().clear();
+            self.registry.set_state(ConnectState::Connecting);
+            // Monitors report through the control channel, restart them on the next ack
+            self.health.clear();
+            self.paused.write().clear();
+            // Visitors connect again once the control channel is back
+            self.pending_visitors.clear();
+            self.visitors.clear();
+            self.endpoints.clear();
+            self.p2p_answers.clear();
+            self.p2p.clear();
 
             if start.elapsed() > Duration::from_secs(3) {
                 // The client runs for at least 3 secs and then disconnects
@@ -144,6 +201,9 @@ impl<T: 'static + Transport> Client<T> {
         }
 
         services.write().clear();
+        self.health.clear();
+        self.visitors.clear();
+        self.p2p.clear();
 
         Ok(())
     }
@@ -160,10 +220,13 @@ impl<T: 'static + Transport> Client<T> {
         let host = url.host_str().context("Failed to get host")?;
         let mut host_and_port = format!("{}:{}", host, port);
 
+        let hosts = config.read().transport.tcp.hosts.clone();
+
         let (mut conn, remote_addr) = loop {
+            // Resolve on every (re)connect, cached addresses may be stale
             let mut remote_addr = AddrMaybeCached::new(&host_and_port);
             remote_addr
-                .resolve()
+                .resolve_with_hosts(&hosts)
                 .await
                 .context("Failed to resolve server address")?;
 
@@ -177,46 +240,13 @@ impl<T: 'static + Transport> Client<T> {
             T::hint(&conn, SocketOpts::for_control_channel());
 
             // Send hello
-            let hwid = IdBuilder::new(Encryption::SHA256)
-                .add_component(HWIDComponent::OSName)
-                .add_component(HWIDComponent::SystemID)
-                .add_component(HWIDComponent::MachineName)
-                .add_component(HWIDComponent::CPUID)
-                .build("cloudpub")
-                .unwrap_or_default();
-
-            let hwid = config
-                .read()
-                .hwid
-                .as_ref()
-                .map(|s| s.to_string())
-                .unwrap_or(hwid);
-
-            let (email, password) = if let Some(ref cred) = config.read().credentials {
-                (cred.0.clone(), cred.1.clone())
-            } else {
-                (String::new(), String::new())
-            };
-
-            let token = config.read().token.clone().unwrap_or_default().to_string();
-
-            let agent_info = AgentInfo {
-                agent_id: config.read().agent_id.clone(),
-                token,
-                email,
-                password,
-                hostname: hostname::get()?.into_string().unwrap(),
-                version: VERSION.to_string(),
-                gui: config.read().gui,
-                platform: get_platform(),
-                hwid,
-                server_host_and_port: remote_addr.to_string(),
-            };
+            let agent_info = agent_info(&config.read(), remote_addr.to_string())?;
 
             debug!("Sending hello: {:?}", agent_info);
 
             let hello_send = Message::AgentHello(agent_info);
 
+            let hello_sent = Instant::now();
             write_message(&mut conn, &hello_send)
                 .await
                 .context("Failed to send hello message")?;
@@ -227,6 +257,16 @@ impl<T: 'static + Transport> Client<T> {
                 .context("Failed to read ack message")?
             {
                 Message::AgentAck(args) => {
+                    self.registry.set_connected(&host_and_port, hello_sent.elapsed());
+                    // Servers without capabilities support only the base protocol
+                    self.capabilities = Capabilities::negotiate(&args.capabilities);
+                    debug!("Negotiated capabilities: {:?}", self.capabilities);
+                    // A local rendezvous replaces the one of the server for testing
+                    self.rendezvous = config
+                        .read()
+                        .p2p_rendezvous
+                        .clone()
+                        .unwrap_or_else(|| args.rendezvous.clone());
                     if !args.token.is_empty() {
                         let mut c = config.write();
                         c.token = Some(args.token.as_str().into());
@@ -258,6 +298,9 @@ impl<T: 'static + Transport> Client<T> {
         let (command_tx2, mut command_rx2) = mpsc::channel::<Message>(1);
 
         let heartbeat_timeout = config.read().heartbeat_timeout;
+        // Own heartbeat awaiting the echo of the server, to measure the round trip
+        let mut ping_seq = 0;
+        let mut ping_sent: Option<Instant> = None;
 
         loop {
             let remote_addr = remote_addr.clone();
@@ -271,7 +314,15 @@ impl<T: 'static + Transport> Client<T> {
                     if let Ok(cmd) = cmd {
                         match cmd {
                             Message::EndpointStart(client) => {
+                                if client.private && !self.capabilities.supports(Capability::Visitor) {
+                                    result_tx.send(Message::Error(ErrorInfo {
+                                        kind: ErrorKind::PublishFailed.into(),
+                                        message: crate::t!("visitor-unsupported"),
+                                    })).context("Can't send Error event")?;
+                                    continue;
+                                }
                                 info!("Publishing service: {:?}", client);
+                                self.local_checks.insert(&client);
                                 let protocol: Protocol = client.local_proto.try_into().unwrap();
                                 let server_endpoint = ServerEndpoint {
                                     guid: String::new(),
@@ -303,6 +354,10 @@ impl<T: 'static + Transport> Client<T> {
                                 if let Some(mut srv) = self.servers.remove(&ep.guid) {
                                     srv.0.stop();
                                 }
+                                self.health.remove(&ep.guid);
+                                self.endpoints.remove(&ep.guid);
+                                self.registry.remove_endpoint(&ep.guid);
+                                self.paused.write().remove(&ep.guid);
                                 let msg = Message::EndpointStop(EndpointStop { guid: ep.guid });
                                 write_message(&mut conn, &msg).await.context("Failed to send message")?;
 
@@ -314,10 +369,63 @@ impl<T: 'static + Transport> Client<T> {
                                 if let Some(mut srv) = self.servers.remove(&ep.guid) {
                                     srv.0.stop();
                                 }
+                                self.health.remove(&ep.guid);
+                                self.endpoints.remove(&ep.guid);
+                                self.registry.remove_endpoint(&ep.guid);
+                                self.paused.write().remove(&ep.guid);
                                 let msg = Message::EndpointRemove(EndpointRemove { guid: ep.guid });
                                 write_message(&mut conn, &msg).await.context("Failed to send message")?;
                             }
 
+                            Message::VisitorConnect(visitor) => {
+                                if !self.capabilities.supports(Capability::Visitor) {
+                                    result_tx.send(Message::Error(ErrorInfo {
+                                        kind: ErrorKind::Fatal.into(),
+                                        message: crate::t!("visitor-unsupported"),
+                                    })).context("Can't send Error event")?;
+                                    continue;
+                                }
+                                info!("Connecting to private service: {:?}", visitor.guid);
+                                let listener = match TcpListener::bind(&visitor.bind_addr).await {
+                                    Ok(listener) => listener,
+                                    Err(err) => {
+                                        result_tx.send(Message::Error(ErrorInfo {
+                                            kind: ErrorKind::Fatal.into(),
+                                            message: crate::t!("visitor-bind-failed", "address" => visitor.bind_addr.clone(), "error" => err.to_string()),
+                                        })).context("Can't send Error event")?;
+                                        continue;
+                                    }
+                                };
+                                self.pending_visitors.insert(visitor.guid.clone(), (listener, visitor.p2p));
+                                // The listener address and p2p flag are local to this host
+                                let msg = Message::VisitorConnect(VisitorConnect { guid: visitor.guid, bind_addr: String::new(), p2p: false });
+                                write_message(&mut conn, &msg).await.context("Failed to send message")?;
+                            }
+
+                            Message::UpgradeCheck(check) => {
+                                if !self.capabilities.supports(Capability::UpgradeCheck) {
+                                    result_tx.send(Message::Error(ErrorInfo {
+                                        kind: ErrorKind::UpgradeFailed.into(),
+                                        message: crate::t!("upgrade-check-unsupported"),
+                                    })).context("Can't send Error event")?;
+                                    continue;
+                                }
+                                write_message(&mut conn, &Message::UpgradeCheck(check)).await.context("Failed to send message")?;
+                            }
+
+                            Message::EndpointStatus(status) => {
+                                // `publish -- cmd` holds the endpoint until the application listens
+                                if status.status == STATUS_ONLINE {
+                                    self.paused.write().remove(&status.guid);
+                                } else {
+                                    self.paused.write().insert(status.guid.clone());
+                                }
+                                self.registry.set_endpoint_status(&status.guid, &status.status);
+                                if self.capabilities.supports(Capability::EndpointStatus) {
+                                    write_message(&mut conn, &Message::EndpointStatus(status)).await.context("Failed to send message")?;
+                                }
+                            }
+
                             Message::Stop(_) => {
                                 info!("Stopping the client");
                                 break;
@@ -335,8 +443,30 @@ impl<T: 'static + Transport> Client<T> {
                     let val = val?;
                     match val {
                         Message::CreateDataChannel(mut endpoint) => {
-
                             let socket_opts = SocketOpts::nodelay(endpoint.client.as_ref().unwrap().nodelay);
+
+                            if self.paused.read().contains(&endpoint.guid) {
+                                warn!("Service {} is unhealthy, connection rejected", endpoint.guid);
+                                // Answer the visitor instead of leaving the server waiting
+                                let service = Arc::new(DataChannel {
+                                    agent_id: config.read().agent_id.clone(),
+                                    remote_addr,
+                                    hosts: hosts.clone(),
+                                    connector: transport.clone(),
+                                    socket_opts,
+                                    endpoint,
+                                    capabilities: self.capabilities.clone(),
+                                    visitor: false,
+                                    registry: self.registry.clone(),
+                                });
+                                tokio::spawn(async move {
+                                    if let Err(e) = reject_data_channel(service).await.context("Failed to reject the data channel") {
+                                        error!("{:?}", e);
+                                    }
+                                });
+                                continue;
+                            }
+
                             if let Some(s) = self.servers.get(&endpoint.guid) {
                                 let client = endpoint.client.as_mut().unwrap();
                                 client.local_port = s.1 as u32;
@@ -360,6 +490,7 @@ impl<T: 'static + Transport> Client<T> {
                                 };
                                 match res {
                                     Some(Ok(p)) => {
+                                        self.registry.set_process(&endpoint.guid, p.pid());
                                         self.servers.insert(endpoint.guid.clone(), (p, endpoint.client.as_ref().unwrap().local_port as u16));
                                     }
                                     Some(Err(err)) => {
@@ -381,9 +512,13 @@ impl<T: 'static + Transport> Client<T> {
                             let service = Arc::new(DataChannel {
                                 agent_id: config.read().agent_id.clone(),
                                 remote_addr,
+                                hosts: hosts.clone(),
                                 connector: transport.clone(),
                                 socket_opts,
                                 endpoint: endpoint.clone(),
+                                capabilities: self.capabilities.clone(),
+                                visitor: false,
+                                registry: self.registry.clone(),
                             });
                             self.services.write().insert(endpoint.guid.clone(), service.clone());
                             tokio::spawn(async move {
@@ -393,8 +528,88 @@ impl<T: 'static + Transport> Client<T> {
                                 debug!("Data channel shutdown");
                             });
                         },
+                        Message::HeartBeat(echo) if echo.seq != 0 => {
+                            if echo.seq == ping_seq {
+                                if let Some(sent) = ping_sent.take() {
+                                    self.registry.set_rtt(sent.elapsed());
+                                }
+                            }
+                        },
                         Message::HeartBeat(_) => {
-                            write_message(&mut conn, &Message::HeartBeat(HeartBeat{})).await.context("Failed to send heartbeat")?;
+                            self.registry.heartbeat();
+                            write_message(&mut conn, &Message::HeartBeat(HeartBeat::default())).await.context("Failed to send heartbeat")?;
+                            if self.capabilities.supports(Capability::HeartbeatEcho) {
+                                ping_seq += 1;
+                                ping_sent = Some(Instant::now());
+                                write_message(&mut conn, &Message::HeartBeat(HeartBeat { seq: ping_seq })).await.context("Failed to send heartbeat")?;
+                            }
+                        },
+                        Message::EndpointAck(endpoint) => {
+                            self.registry.upsert_endpoint(&endpoint);
+                            self.endpoints.insert(endpoint.guid.clone(), endpoint.clone());
+                            let check = endpoint.client.as_ref().and_then(|c| self.local_checks.resolve(c));
+                            if let Some(check) = check {
+                                if endpoint.status.as_deref() == Some(STATUS_ONLINE) && !self.health.contains_key(&endpoint.guid) {
+                                    debug!("Start health checks for {}", endpoint.guid);
+                                    // Older servers don't know the unhealthy status, keep it local
+                                    let report_tx = self.capabilities.supports(Capability::EndpointStatus).then(|| command_tx2.clone());
+                                    let monitor = HealthMonitor::spawn(endpoint.clone(), check, self.paused.clone(), self.registry.clone(), report_tx, result_tx.clone());
+                                    self.health.insert(endpoint.guid.clone(), monitor);
+                                }
+                            }
+                            result_tx.send(Message::EndpointAck(endpoint)).context("Can't send server message")?;
+                        },
+                        Message::VisitorAck(mut endpoint) => {
+                            let Some((listener, direct)) = self.pending_visitors.remove(&endpoint.guid) else {
+                                warn!("Unexpected visitor ack: {:?}", endpoint.guid);
+                                continue;
+                            };
+                            endpoint.bind_addr = listener.local_addr()?.to_string();
+                            let socket_opts = SocketOpts::nodelay(endpoint.client.as_ref().and_then(|c| c.nodelay));
+                            let service = Arc::new(DataChannel {
+                                agent_id: config.read().agent_id.clone(),
+                                remote_addr,
+                                hosts: hosts.clone(),
+                                connector: transport.clone(),
+                                socket_opts,
+                                endpoint: endpoint.clone(),
+                                capabilities: self.capabilities.clone(),
+                                visitor: true,
+                                registry: self.registry.clone(),
+                            });
+                            let link = if !direct {
+                                None
+                            } else if self.capabilities.supports(Capability::P2p) && !self.rendezvous.is_empty() {
+                                let link = P2pLink::new(Uuid::new_v4().simple().to_string());
+                                let (answer_tx, answer_rx) = mpsc::channel(1);
+                                self.p2p.retain(|_, task| !task.is_finished());
+                                self.p2p_answers.retain(|session, _| self.p2p.contains_key(session));
+                                self.p2p_answers.insert(link.session().to_string(), answer_tx);
+                                let task = p2p::initiate(endpoint.guid.clone(), self.rendezvous.clone(), link.clone(), command_tx2.clone(), answer_rx);
+                                self.p2p.insert(link.session().to_string(), P2pTask::spawn(task));
+                                Some(link)
+                            } else {
+                                warn!("Server doesn't support direct connections, using relay");
+                                None
+                            };
+                            self.visitors.insert(endpoint.guid.clone(), Visitor::spawn(listener, service, link));
+                            result_tx.send(Message::VisitorAck(endpoint)).context("Can't send server message")?;
+                        },
+                        Message::P2pOffer(offer) => {
+                            info!("Direct connection offer for {}", offer.guid);
+                            let endpoint = self.endpoints.get(&offer.guid).cloned();
+                            // Declined without the capability, the server should not relay it then
+                            let rendezvous = if self.capabilities.supports(Capability::P2p) { self.rendezvous.clone() } else { String::new() };
+                            self.p2p.retain(|_, task| !task.is_finished());
+                            let session = offer.session.clone();
+                            let task = p2p::respond(offer, endpoint, rendezvous, self.registry.clone(), command_tx2.clone());
+                            self.p2p.insert(session, P2pTask::spawn(task));
+                        },
+                        Message::P2pAnswer(answer) => {
+                            // Kept for the attempts to restore a closed direct connection
+                            if let Some(answer_tx) = self.p2p_answers.get(&answer.session) {
+                                answer_tx.try_send(answer).ok();
+                            }
                         },
                         v => {
                             result_tx.send(v).context("Can't send server message")?;
@@ -408,6 +623,7 @@ impl<T: 'static + Transport> Client<T> {
         }
 
         info!("Control channel shutdown");
+        self.registry.set_state(ConnectState::Disconnected);
         result_tx
             .send(Message::ConnectState(ConnectState::Disconnected.into()))
             .context("Can't send Disconnected event")?;
@@ -415,7 +631,9 @@ impl<T: 'static + Transport> Client<T> {
     }
 }
 
-async fn do_data_channel_handshake<T: Transport>(service: Service<T>) -> Result<T::Stream> {
+pub(crate) async fn do_data_channel_handshake<T: Transport>(
+    service: Service<T>,
+) -> Result<T::Stream> {
     // Retry at least every 100ms, at most for 10 seconds
     let backoff = ExponentialBackoff {
         max_interval: Duration::from_millis(100),
@@ -423,20 +641,25 @@ async fn do_data_channel_handshake<T: Transport>(service: Service<T>) -> Result<
         ..Default::default()
     };
 
+    let retry = AtomicBool::new(false);
+
     // Connect to remote_addr
     let mut conn: T::Stream = retry_notify(
         backoff,
         || async {
+            let mut remote_addr = service.remote_addr.clone();
+            // Re-resolve on retries in case the cached address went stale
+            if retry.swap(true, Ordering::Relaxed) {
+                if let Err(err) = remote_addr.resolve_with_hosts(&service.hosts).await {
+                    warn!("{:#}. Using cached address {}", err, &service.remote_addr);
+                    remote_addr = service.remote_addr.clone();
+                }
+            }
             service
                 .connector
-                .connect(&service.remote_addr)
+                .connect(&remote_addr)
                 .await
-                .with_context(|| {
-                    format!(
-                        "Failed to handshake data channel to {}",
-                        &service.remote_addr
-                    )
-                })
+                .with_context(|| format!("Failed to handshake data channel to {}", &remote_addr))
                 .map_err(backoff::Error::transient)
         },
         |e, duration| {
@@ -448,10 +671,33 @@ async fn do_data_channel_handshake<T: Transport>(service: Service<T>) -> Result<
 
     T::hint(&conn, service.socket_opts);
 
-    let hello = Message::DataChannelHello(DataChannelInfo {
+    // Offer compression configured for the endpoint, the server makes the final choice
+    // Visitors may not see the client part of a private endpoint
+    let compression = match service
+        .endpoint
+        .client
+        .as_ref()
+        .map(|c| c.compression())
+        .unwrap_or_default()
+    {
+        Compression::None => Vec::new(),
+        _ if !service.capabilities.supports(Capability::Compression) => {
+            debug!("Server doesn't support compression, falling back to plain channel");
+            Vec::new()
+        }
+        c => vec![c.into()],
+    };
+
+    let info = DataChannelInfo {
         agent_id: service.agent_id.clone(),
         guid: service.endpoint.guid.clone(),
-    });
+        compression,
+    };
+    let hello = if service.visitor {
+        Message::VisitorHello(info)
+    } else {
+        Message::DataChannelHello(info)
+    };
     write_message(&mut conn, &hello)
         .await
         .context("Failed to send data hello message")?;
@@ -472,8 +718,10 @@ async fn run_data_channel<T: Transport>(service: Service<T>) -> Result<()> {
     // Forward
         msg = read_message(&mut conn) => {
             match msg {
-                Ok(Message::StartForwardTcp(_)) => {
-                    run_data_channel_for_tcp::<T>(conn,  &local_addr, local_port).await.context("Failed to run TCP data channel")?;
+                Ok(Message::StartForwardTcp(start)) => {
+                    let stats = service.registry.channel_stats(&service.endpoint.guid);
+                    let _active = stats.open(&start.visitor_addr);
+                    run_data_channel_for_tcp(conn, &service.endpoint, start.compression(), stats.clone()).await.context("Failed to run TCP data channel")?;
                 }
                 Ok(Message::StartForwardUdp(_)) => {
                     run_data_channel_for_udp::<T>(conn, &local_addr, local_port).await.context("Failed to run UDP data channel")?;
@@ -490,24 +738,114 @@ async fn run_data_channel<T: Transport>(service: Service<T>) -> Result<()> {
     Ok(())
 }
 
+// Unhealthy service: HTTP visitors get the 503 page, others an immediate close
+async fn reject_data_channel<T: Transport>(service: Service<T>) -> Result<()> {
+    let mut conn = do_data_channel_handshake(service.clone())
+        .await
+        .context("Failed to handshake data channel")?;
+
+    match read_message(&mut conn).await? {
+        Message::StartForwardTcp(start) => {
+            let client = service.endpoint.client.as_ref().unwrap();
+            if client.local_proto() == Protocol::Http {
+                let stats = service.registry.channel_stats(&service.endpoint.guid);
+                let page = error_page::serve(STATUS_SERVICE_UNAVAILABLE, &client.error_page);
+                forward(conn, page, start.compression(), stats).await?;
+            } else {
+                conn.shutdown().await.ok();
+            }
+        }
+        Message::StartForwardUdp(_) => {}
+        msg => warn!("Unexpected data channel message: {:?}", msg),
+    }
+    Ok(())
+}
+
 // Simply copying back and forth for TCP
-async fn run_data_channel_for_tcp<T: Transport>(
-    mut conn: T::Stream,
-    local_addr: &str,
-    local_port: u16,
-) -> Result<()> {
+pub(crate) async fn run_data_channel_for_tcp<S>(
+    conn: S,
+    endpoint: &ServerEndpoint,
+    compression: Compression,
+    stats: Arc<ChannelStats>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
     debug!("New data channel starts forwarding");
 
-    let mut local = TcpStream::connect(format!("{}:{}", local_addr, local_port))
-        .await
-        .with_context(|| format!("Failed to local connect to {}:{}", local_addr, local_port))?;
+    let client = endpoint.client.as_ref().unwrap();
+    let (local_addr, local_port) = (client.local_addr.as_str(), client.local_port as u16);
 
-    tokio::select! {
-        _ = copy_bidirectional(&mut conn, &mut local) => {
-            debug!("Remote -> Local done");
-        },
+    // Only plain HTTP can be answered on behalf of the local service
+    let http = client.local_proto() == Protocol::Http;
+
+    if http && error_page::is_maintenance(&endpoint.guid) {
+        debug!("Service {} is in maintenance mode", endpoint.guid);
+        let page = error_page::serve(STATUS_SERVICE_UNAVAILABLE, &client.error_page);
+        return forward(conn, page, compression, stats).await;
     }
 
+    if let Some(path) = unix_socket_path(local_addr) {
+        #[cfg(unix)]
+        {
+            match tokio::net::UnixStream::connect(path).await {
+                Ok(local) => return forward(conn, local, compression, stats).await,
+                Err(err) if http => {
+                    warn!("Failed to local connect to {}: {}", local_addr, err);
+                    let page = error_page::serve(STATUS_BAD_GATEWAY, &client.error_page);
+                    return forward(conn, page, compression, stats).await;
+                }
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("Failed to local connect to {}", local_addr))
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        bail!(crate::t!("error-unix-socket-platform", "path" => path));
+    }
+
+    // Local target may resolve to both IPv6 and IPv4 (e.g. localhost)
+    let addrs = local_socket_addrs(local_addr, local_port).await?;
+    let local = happy_eyeballs::connect(&addrs).await.with_context(|| {
+        format!(
+            "Failed to local connect to {}",
+            format_host_port(local_addr, local_port)
+        )
+    });
+
+    match local {
+        Ok(local) => forward(conn, local, compression, stats).await,
+        Err(err) if http => {
+            warn!("{:#}", err);
+            let page = error_page::serve(STATUS_BAD_GATEWAY, &client.error_page);
+            forward(conn, page, compression, stats).await
+        }
+        Err(err) => Err(err),
+    }
+}
+
+pub(crate) async fn forward<S, L>(
+    mut conn: S,
+    local: L,
+    compression: Compression,
+    stats: Arc<ChannelStats>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    L: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let mut local = CountingStream::new(local, stats);
+    if compression == Compression::None {
+        copy_bidirectional(&mut conn, &mut local).await.ok();
+    } else {
+        debug!("Data channel uses {} compression", compression);
+        let stats = copy_bidirectional_compressed(conn, local, compression)
+            .await
+            .context("Failed to forward compressed data")?;
+        info!("Compressed data channel closed: {}", stats);
+    }
+    debug!("Remote -> Local done");
     Ok(())
 }
 
@@ -524,6 +862,10 @@ async fn run_data_channel_for_udp<T: Transport>(
 ) -> Result<()> {
     debug!("New data channel starts forwarding");
 
+    if unix_socket_path(local_addr).is_some() {
+        bail!(crate::t!("unix-socket-unsupported"));
+    }
+
     let port_map: UdpPortMap = Arc::new(tokio::sync::RwLock::new(HashMap::new()));
 
     // The channel stores UdpTraffic that needs to be sent to the server
@@ -571,7 +913,12 @@ async fn run_data_channel_for_udp<T: Transport>(
             // grabbing the writer lock
             let mut m = port_map.write().await;
 
-            match udp_connect(format!("{}:{}", local_addr, local_port)).await {
+            let local = match local_socket_addrs(local_addr, local_port).await {
+                Ok(addrs) => udp_connect(addrs[0]).await,
+                Err(e) => Err(e),
+            };
+
+            match local {
                 Ok(s) => {
                     let (inbound_tx, inbound_rx) = mpsc::channel(UDP_SENDQ_SIZE);
                     m.insert(packet.from, inbound_tx);
@@ -703,27 +1050,63 @@ async fn handle_endpoint_start(
     Ok(())
 }
 
+/// Hello message identifying this agent to the server
+pub fn agent_info(config: &ClientConfig, server_host_and_port: String) -> Result<AgentInfo> {
+    let hwid = IdBuilder::new(Encryption::SHA256)
+        .add_component(HWIDComponent::OSName)
+        .add_component(HWIDComponent::SystemID)
+        .add_component(HWIDComponent::MachineName)
+        .add_component(HWIDComponent::CPUID)
+        .build("cloudpub")
+        .unwrap_or_default();
+
+    let hwid = config.hwid.as_ref().map(|s| s.to_string()).unwrap_or(hwid);
+
+    let (email, password) = if let Some(ref cred) = config.credentials {
+        (cred.0.clone(), cred.1.to_string())
+    } else {
+        (String::new(), String::new())
+    };
+
+    let token = config.token.clone().unwrap_or_default().to_string();
+
+    Ok(AgentInfo {
+        agent_id: config.agent_id.clone(),
+        token,
+        email,
+        password,
+        hostname: hostname::get()?.into_string().unwrap(),
+        version: VERSION.to_string(),
+        gui: config.gui,
+        platform: get_platform(),
+        hwid,
+        server_host_and_port,
+        capabilities: local_capabilities(),
+    })
+}
+
 pub async fn run_client(
     config: Arc<RwLock<ClientConfig>>,
+    registry: Arc<StatusRegistry>,
     command_rx: broadcast::Receiver<Message>,
     result_tx: broadcast::Sender<Message>,
 ) -> Result<()> {
     let transport_type = config.read().transport.transport_type;
     match transport_type {
         TransportType::Tcp => {
-            let mut client = Client::<TcpTransport>::from(config)
+            let mut client = Client::<TcpTransport>::from(config, registry)
                 .await
                 .context("Failed to create TCP client")?;
             client.run(command_rx, result_tx).await
         }
         TransportType::Tls => {
-            let mut client = Client::<TlsTransport>::from(config)
+            let mut client = Client::<TlsTransport>::from(config, registry)
                 .await
                 .context("Failed to create TLS client")?;
             client.run(command_rx, result_tx).await
         }
         TransportType::Websocket => {
-            let mut client = Client::<WebsocketTransport>::from(config)
+            let mut client = Client::<WebsocketTransport>::from(config, registry)
                 .await
                 .context("Failed to create Websocket client")?;
             client.run(command_rx, result_tx).await
@@ -2,9 +2,14 @@ use anyhow::{bail, Context, Result};
 use clap::builder::TypedValueParser;
 use clap::{Args, Subcommand};
 use common::config::MaskedString;
-use common::protocol::{Acl, Auth, ClientEndpoint, DefaultPort, Header, Protocol, Role};
+use common::protocol::{
+    Acl, Auth, ClientEndpoint, Compression, DefaultPort, Header, HealthCheck, HealthCheckKind,
+    Protocol, Restart, RestartPolicy, Role,
+};
+use common::utils::{format_host_port, parse_scoped_ipv6, split_host_port, unix_socket_path};
 use serde::{Deserialize, Serialize};
 use std::net::ToSocketAddrs;
+use std::path::PathBuf;
 use std::str::FromStr;
 
 const ROLE_SEP: &str = ":";
@@ -25,12 +30,24 @@ pub enum Commands {
     Register(PublishArgs),
     #[clap(about = "Register service and run it")]
     Publish(PublishArgs),
+    #[clap(about = "Connect to a private service through a local port")]
+    Connect(ConnectArgs),
     #[clap(about = "Unregister service")]
     Unpublish(UnpublishArgs),
     #[clap(about = "List all registered services")]
     Ls,
     #[clap(about = "Clean all registered services")]
     Clean,
+    #[clap(about = "Show status of the running agent")]
+    Status(StatusArgs),
+    #[clap(about = "Show logs of the service process")]
+    Logs(LogsArgs),
+    #[clap(about = "Toggle maintenance mode of HTTP service")]
+    Maintenance(MaintenanceArgs),
+    #[clap(about = "Diagnose connection problems")]
+    Doctor,
+    #[clap(about = "Upgrade to the latest version")]
+    Upgrade(UpgradeArgs),
     #[clap(about = "Purge cache")]
     Purge,
     #[clap(about = "Ping server and measure roundtrip time")]
@@ -44,6 +61,29 @@ pub enum Commands {
         #[clap(subcommand)]
         action: ServiceAction,
     },
+    #[clap(about = "Manage the config with dotted keys, e.g. transport.tcp.proxy")]
+    Config {
+        #[clap(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigAction {
+    #[clap(about = "Get value of the key")]
+    Get(GetArgs),
+
+    #[clap(about = "Set value of the key")]
+    Set(SetArgs),
+
+    #[clap(about = "Remove optional key")]
+    Unset { key: String },
+
+    #[clap(about = "List all keys with their values, secrets are masked")]
+    List,
+
+    #[clap(about = "Check the config and report all problems")]
+    Validate,
 }
 
 #[derive(Subcommand, Debug, Clone)]
@@ -76,18 +116,52 @@ pub struct PingArg {
     pub num: Option<i32>,
     #[clap(short = 'B', long = "bare", help = "Output time in µs")]
     pub bare: bool,
+    #[clap(
+        long = "size",
+        default_value_t = 48,
+        value_parser = clap::value_parser!(u32).range(8..=65000),
+        help = "Message size in bytes"
+    )]
+    pub size: u32,
+    #[clap(long = "count", default_value_t = 100, help = "Number of messages")]
+    pub count: u32,
+    #[clap(long = "udp", help = "Measure UDP round trip, loss and jitter")]
+    pub udp: bool,
+    #[clap(long = "throughput", help = "Measure upload and download throughput")]
+    pub throughput: bool,
+    #[clap(
+        long = "duration",
+        default_value_t = 5,
+        help = "Duration of the throughput test in seconds"
+    )]
+    pub duration: u64,
+    #[clap(
+        long = "streams",
+        default_value_t = 1,
+        help = "Parallel streams of the throughput test"
+    )]
+    pub streams: u32,
+    #[clap(long = "histogram", help = "Show histogram of round trip times")]
+    pub histogram: bool,
+    #[clap(long = "json", help = "Output results as JSON")]
+    pub json: bool,
 }
 
 #[derive(Args, Debug, Serialize, Deserialize, Clone)]
 pub struct GetArgs {
     pub key: String,
+    #[clap(
+        long,
+        help = "Show whether the value is from the config or the environment"
+    )]
+    pub source: bool,
 }
 
 #[derive(Args, Debug, Clone)]
 pub struct PublishArgs {
     #[clap(help = "Protocol to use")]
     pub protocol: Protocol,
-    #[clap(help = "URL, socket address, port or file path")]
+    #[clap(help = "URL, socket address, port, unix:///path/to/socket or file path")]
     pub address: String,
     #[clap(short = 'U', long = "username", help = "Username")]
     pub username: Option<String>,
@@ -101,6 +175,60 @@ pub struct PublishArgs {
     pub acl: Vec<Acl>,
     #[clap(short='H', long="header", help = "HTTP headers", value_parser = HeaderParser)]
     pub headers: Vec<Header>,
+    #[clap(
+        short = 'Z',
+        long = "compress",
+        help = "Compress data channel traffic (zstd, deflate)"
+    )]
+    pub compression: Option<Compression>,
+    #[clap(
+        long = "health",
+        help = "Health check of the local service (tcp, http, cmd)"
+    )]
+    pub health: Option<HealthCheckKind>,
+    #[clap(long = "health-path", help = "HTTP path for the health check")]
+    pub health_path: Option<String>,
+    #[clap(
+        long = "health-status",
+        help = "Expected HTTP status (any 2xx or 3xx by default)"
+    )]
+    pub health_status: Option<u16>,
+    #[clap(
+        long = "health-cmd",
+        help = "Health check command, healthy if exits with 0"
+    )]
+    pub health_cmd: Option<String>,
+    #[clap(long = "health-interval", help = "Seconds between health checks")]
+    pub health_interval: Option<u32>,
+    #[clap(long = "health-timeout", help = "Health check timeout in seconds")]
+    pub health_timeout: Option<u32>,
+    #[clap(
+        long = "pause-unhealthy",
+        help = "Reject connections while the service is unhealthy"
+    )]
+    pub pause_unhealthy: bool,
+    #[clap(
+        long = "error-page",
+        help = "Template of the 502/503 page (.html or .json) for HTTP service"
+    )]
+    pub error_page: Option<String>,
+    #[clap(
+        long = "restart",
+        help = "Restart policy of the service process (never, on-failure, always)"
+    )]
+    pub restart: Option<RestartPolicy>,
+    #[clap(long = "max-restarts", help = "Restarts in a row before giving up")]
+    pub max_restarts: Option<u32>,
+    #[clap(
+        long = "private",
+        help = "Don't expose TCP service publicly, allow only `clo connect` of acl users"
+    )]
+    pub private: bool,
+    #[clap(
+        last = true,
+        help = "Command to launch with the service, e.g. -- npm run dev"
+    )]
+    pub exec: Vec<String>,
 }
 
 #[derive(Args, Debug, Serialize, Deserialize, Clone)]
@@ -115,6 +243,56 @@ pub struct UnpublishArgs {
     pub remove: bool,
 }
 
+#[derive(Args, Debug, Serialize, Deserialize, Clone)]
+pub struct ConnectArgs {
+    #[clap(help = "GUID of the private service")]
+    pub guid: String,
+    #[clap(
+        short,
+        long,
+        default_value = "127.0.0.1:0",
+        help = "Local address to listen on"
+    )]
+    pub local: String,
+    #[clap(long, help = "Try a direct peer-to-peer connection before the relay")]
+    pub p2p: bool,
+}
+
+#[derive(Args, Debug, Serialize, Deserialize, Clone)]
+pub struct UpgradeArgs {
+    #[clap(long, help = "Restore the version replaced by the last upgrade")]
+    pub rollback: bool,
+}
+
+#[derive(Args, Debug, Serialize, Deserialize, Clone)]
+pub struct StatusArgs {
+    #[clap(long, help = "Output status as JSON")]
+    pub json: bool,
+}
+
+#[derive(Args, Debug, Serialize, Deserialize, Clone)]
+pub struct LogsArgs {
+    #[clap(help = "Service GUID or name")]
+    pub target: String,
+    #[clap(short, long, help = "Keep printing new lines")]
+    pub follow: bool,
+    #[clap(
+        short = 'n',
+        long = "lines",
+        default_value = "50",
+        help = "Number of last lines to show"
+    )]
+    pub lines: usize,
+}
+
+#[derive(Args, Debug, Serialize, Deserialize, Clone)]
+pub struct MaintenanceArgs {
+    #[clap(help = "Service GUID or name")]
+    pub target: String,
+    #[clap(value_parser = ["on", "off"], help = "Maintenance mode state")]
+    pub state: String,
+}
+
 #[derive(Args, Debug, Serialize, Deserialize, Clone)]
 pub struct LoginArgs {
     #[clap(
@@ -123,26 +301,124 @@ pub struct LoginArgs {
     )]
     pub email: Option<String>,
     #[clap(
-        help = "Password (if not provided, will prompt or use CLO_PASSWORD env var)",
+        help = "Password (if not provided, will prompt or use CLO_PASSWORD env var), prefer --password-stdin",
         env = "CLO_PASSWORD",
         hide_env_values = true,
         required = false
     )]
-    pub password: Option<String>,
+    pub password: Option<MaskedString>,
+    #[clap(
+        long,
+        help = "Read the password from the first line of stdin",
+        requires = "email"
+    )]
+    pub password_stdin: bool,
+    #[clap(
+        long,
+        help = "Log in with a code confirmed in the browser",
+        conflicts_with_all = ["email", "password_stdin", "token_file"]
+    )]
+    pub device: bool,
+    #[clap(
+        long,
+        help = "Use the token from the file instead of the email and password",
+        conflicts_with_all = ["email", "password_stdin"]
+    )]
+    pub token_file: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "CLO_AUTH_URL",
+        hide = true,
+        help = "Base URL of the device login"
+    )]
+    pub auth_url: Option<String>,
 }
 
 impl PublishArgs {
+    fn health_check(&self) -> Option<HealthCheck> {
+        let kind = self.health.or(if self.health_cmd.is_some() {
+            Some(HealthCheckKind::Command)
+        } else if self.health_path.is_some() || self.health_status.is_some() {
+            Some(HealthCheckKind::Http)
+        } else if self.pause_unhealthy {
+            Some(HealthCheckKind::Tcp)
+        } else {
+            None
+        })?;
+        Some(HealthCheck {
+            kind: kind.into(),
+            path: self.health_path.clone().unwrap_or_default(),
+            expected_status: self.health_status.unwrap_or_default() as u32,
+            command: self.health_cmd.clone().unwrap_or_default(),
+            interval_secs: self.health_interval.unwrap_or_default(),
+            timeout_secs: self.health_timeout.unwrap_or_default(),
+            pause: self.pause_unhealthy,
+        })
+    }
+
+    fn restart(&self) -> Option<Restart> {
+        if self.restart.is_none() && self.max_restarts.is_none() {
+            return None;
+        }
+        Some(Restart {
+            policy: self.restart.unwrap_or_default().into(),
+            max_restarts: self.max_restarts.unwrap_or_default(),
+        })
+    }
+
+    fn error_page(&self) -> Result<String> {
+        match &self.error_page {
+            Some(path) => crate::error_page::install_template(path),
+            None => Ok(String::new()),
+        }
+    }
+
     pub fn parse(&self) -> Result<ClientEndpoint> {
+        if self.health == Some(HealthCheckKind::Command) && self.health_cmd.is_none() {
+            bail!(crate::t!("health-command-required"));
+        }
+        if self.private && self.protocol != Protocol::Tcp {
+            bail!(crate::t!("private-tcp-only"));
+        }
         let auth = self.auth.unwrap_or(if self.protocol == Protocol::Webdav {
             Auth::Basic
         } else {
             Auth::None
         });
-        if self.address.contains("://") {
+        if unix_socket_path(&self.address).is_some() {
+            if !matches!(
+                self.protocol,
+                Protocol::Http | Protocol::Https | Protocol::Tcp
+            ) {
+                bail!(crate::t!("unix-socket-unsupported"));
+            }
+            Ok(ClientEndpoint {
+                description: self.name.clone(),
+                local_proto: self.protocol.into(),
+                local_addr: self.address.clone(),
+                local_port: 0,
+                local_path: String::new(),
+                nodelay: Some(true),
+                auth: auth.into(),
+                acl: self.acl.clone(),
+                headers: self.headers.clone(),
+                compression: self.compression.unwrap_or(Compression::None).into(),
+                health_check: self.health_check(),
+                error_page: self.error_page()?,
+                restart: self.restart(),
+                private: self.private,
+                username: self.username.clone().unwrap_or_default(),
+                password: self.password.clone().unwrap_or_default().to_string(),
+            })
+        } else if self.address.contains("://") {
             let url = url::Url::parse(&self.address).context(crate::t!("invalid-url"))?;
             let local_proto =
                 Protocol::from_str(url.scheme()).context(crate::t!("invalid-protocol"))?;
-            let local_addr = url.host_str().unwrap().to_string();
+            // IPv6 hosts are stored without brackets
+            let local_addr = match url.host().context(crate::t!("invalid-url"))? {
+                url::Host::Ipv6(ip) => ip.to_string(),
+                host => host.to_string(),
+            };
             let local_port = url
                 .port()
                 .or_else(|| self.protocol.default_port())
@@ -170,8 +446,13 @@ impl PublishArgs {
                 auth: auth.into(),
                 acl: self.acl.clone(),
                 headers: self.headers.clone(),
+                compression: self.compression.unwrap_or(Compression::None).into(),
+                health_check: self.health_check(),
+                error_page: self.error_page()?,
+                restart: self.restart(),
+                private: self.private,
                 username,
-                password: password.0,
+                password: password.to_string(),
             })
         } else {
             let (local_addr, local_port, local_path) = match self.protocol {
@@ -187,30 +468,35 @@ impl PublishArgs {
                     if let Ok(port) = self.address.parse::<u16>() {
                         ("localhost".to_string(), port, String::new())
                     } else {
-                        let mut address = self.address.split('/').next().unwrap().to_string();
+                        let address = self.address.split('/').next().unwrap().to_string();
                         let path = self.address[address.len()..].to_string();
 
-                        if let Some(port) = self.protocol.default_port() {
-                            if !address.contains(":") {
-                                address.push(':');
-                                address.push_str(port.to_string().as_str());
-                            }
-                        }
+                        let (host, port) = split_host_port(&address).with_context(|| {
+                            crate::t!("invalid-address", "address" => address.clone())
+                        })?;
+                        let port = match port {
+                            Some(port) if port != 0 => port,
+                            _ => self
+                                .protocol
+                                .default_port()
+                                .context(crate::t!("port-required"))?,
+                        };
+                        let address = format_host_port(&host, port);
 
-                        match address.to_socket_addrs() {
-                            Ok(mut addrs) => {
-                                if addrs.next().is_some() {
-                                    // Split original address to addr and port
-                                    let parts = address.split(':').collect::<Vec<&str>>();
-                                    (parts[0].to_string(), parts[1].parse::<u16>().unwrap(), path)
-                                } else {
-                                    bail!(crate::t!("invalid-address", "address" => address));
+                        // Scoped IPv6 literals can't be checked with std resolver
+                        if parse_scoped_ipv6(&host, port).is_none() {
+                            match (host.as_str(), port).to_socket_addrs() {
+                                Ok(mut addrs) => {
+                                    if addrs.next().is_none() {
+                                        bail!(crate::t!("invalid-address", "address" => address));
+                                    }
                                 }
+                                Err(err) => bail!(
+                                    crate::t!("invalid-address-error", "error" => err.to_string(), "address" => address)
+                                ),
                             }
-                            Err(err) => bail!(
-                                crate::t!("invalid-address-error", "error" => err.to_string(), "address" => address)
-                            ),
                         }
+                        (host, port, path)
                     }
                 }
             };
@@ -225,6 +511,11 @@ impl PublishArgs {
                 auth: auth.into(),
                 acl: self.acl.clone(),
                 headers: self.headers.clone(),
+                compression: self.compression.unwrap_or(Compression::None).into(),
+                health_check: self.health_check(),
+                error_page: self.error_page()?,
+                restart: self.restart(),
+                private: self.private,
                 username: self.username.clone().unwrap_or("".to_string()),
                 password: self
                     .password
@@ -261,7 +552,7 @@ impl TypedValueParser for HeaderParser {
         if parts.len() != 2 {
             return Err(clap::Error::raw(
                 clap::error::ErrorKind::ValueValidation,
-                format!("Invalid Header format (should be 'name:value'): {}", value),
+                crate::t!("invalid-header", "value" => value.to_string()),
             ));
         }
         Ok(Header {
@@ -288,13 +579,13 @@ impl TypedValueParser for AclParser {
         if parts.len() != 2 {
             return Err(clap::Error::raw(
                 clap::error::ErrorKind::ValueValidation,
-                format!("Invalid Acl: {}", value),
+                crate::t!("invalid-acl", "value" => value.to_string()),
             ));
         }
         let role = Role::from_str(parts[1]).map_err(|_err| {
             clap::Error::raw(
                 clap::error::ErrorKind::ValueValidation,
-                format!("Invalid role: {}", parts[1]),
+                crate::t!("invalid-role", "role" => parts[1]),
             )
         })?;
         Ok(Acl {
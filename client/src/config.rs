@@ -1,4 +1,6 @@
+use crate::secrets;
 use anyhow::{bail, Context, Result};
+use common::config::{Redacted, TlsConfig};
 use common::constants::DEFAULT_HEARTBEAT_TIMEOUT_SECS;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Display, Formatter};
@@ -8,9 +10,11 @@ use lazy_static::lazy_static;
 use std::collections::HashMap;
 use std::fs::{self, create_dir_all};
 use std::path::PathBuf;
+use toml::Value;
 use tracing::debug;
 use url::Url;
 use uuid::Uuid;
+use zeroize::Zeroize;
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum Platform {
@@ -103,6 +107,31 @@ impl std::str::FromStr for Platform {
     }
 }
 
+// Environment variables overriding the config, `__` separates nested keys:
+// CLO_TRANSPORT__TYPE=tls sets `type` of `[transport]`
+const ENV_PREFIX: &str = "CLO_";
+const ENV_SEPARATOR: &str = "__";
+
+/// Value of the config set by an environment variable
+#[derive(Clone, PartialEq)]
+pub struct EnvOverride {
+    pub var: String,
+    /// Dotted path of the key, e.g. `transport.type`
+    pub path: String,
+    // Value in the file, written back on save if the key isn't changed
+    file: Option<Value>,
+    loaded: Option<Value>,
+}
+
+impl Eq for EnvOverride {}
+
+// Values may be secrets
+impl Debug for EnvOverride {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}", self.var, self.path)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct ClientConfig {
     // This fields are not persistent
@@ -113,7 +142,9 @@ pub struct ClientConfig {
     #[serde(skip)]
     pub gui: bool,
     #[serde(skip)]
-    pub credentials: Option<(String, String)>,
+    pub credentials: Option<(String, MaskedString)>,
+    #[serde(skip)]
+    env: Vec<EnvOverride>,
     // Next fields are persistent
     pub agent_id: String,
     pub server: Url,
@@ -125,6 +156,14 @@ pub struct ClientConfig {
     pub minecraft_server: Option<String>,
     pub minecraft_java_opts: Option<String>,
     pub hwid: Option<String>,
+    // UDP address reflector for direct connections instead of the server one
+    pub p2p_rendezvous: Option<String>,
+    // Install new versions pushed by the server while running as a service,
+    // only in builds with the release key
+    #[serde(default)]
+    pub auto_upgrade: bool,
+    // Language of the messages, detected from the environment if not set
+    pub lang: Option<String>,
     pub transport: TransportConfig,
 }
 
@@ -144,16 +183,53 @@ impl ClientConfig {
 
         let s: String = fs::read_to_string(path)
             .with_context(|| format!("Failed to read the config {:?}", path))?;
-        let mut cfg: Self = toml::from_str(&s).with_context(|| {
+        let mut value: Value = toml::from_str(&s).with_context(|| {
             "Configuration is invalid. Please refer to the configuration specification."
         })?;
+        let mut env = apply_env(&mut value)?;
+        let mut cfg: Self = value.try_into().with_context(|| {
+            if env.is_empty() {
+                "Configuration is invalid. Please refer to the configuration specification."
+            } else {
+                "Configuration or CLO_* environment variables are invalid."
+            }
+        })?;
+
+        // Variables not matching any key, like CLO_PASSWORD, aren't overrides
+        let loaded = Value::try_from(&cfg).context("Failed to serialize the config")?;
+        env.retain_mut(|o| {
+            o.loaded = get_path(&loaded, &o.path).cloned();
+            o.loaded.is_some() || o.file.is_some()
+        });
+        debug!("Config overrides: {:?}", env);
 
         cfg.config_path = path.clone();
         cfg.readonly = readonly;
         cfg.gui = gui;
+        cfg.env = env;
+
+        if cfg.env_var("token").is_none() {
+            let stored = cfg.token.take();
+            if let Some(stored) = stored {
+                cfg.token = secrets::open(path, &cfg.agent_id, &stored);
+                // Tokens of older versions are in plain text
+                if !secrets::is_sealed(&stored) && !readonly {
+                    cfg.save().context("Failed to protect the token")?;
+                }
+            }
+        }
         Ok(cfg)
     }
 
+    /// Environment variable overriding the key of `clo set`, if any
+    pub fn env_var(&self, key: &str) -> Option<&str> {
+        let path = key_path(key);
+        self.env
+            .iter()
+            .find(|o| o.path == path)
+            .map(|o| o.var.as_str())
+    }
+
     pub fn get_config_dir(user_dir: bool) -> Result<PathBuf> {
         let dir = if user_dir {
             let mut dir = dirs::config_dir().context("Can't get config_dir")?;
@@ -174,8 +250,34 @@ impl ClientConfig {
     pub fn save(&self) -> Result<()> {
         if self.readonly {
             debug!("Skipping saving the config in readonly mode");
+        } else if self.env.is_empty() {
+            let mut stored = self.clone();
+            stored.token = secrets::seal(&self.config_path, &self.agent_id, self.token.as_ref())?;
+            let s = toml::to_string_pretty(&stored).context("Failed to serialize the config")?;
+            fs::write(&self.config_path, s).context("Failed to write the config")?;
         } else {
-            let s = toml::to_string_pretty(self).context("Failed to serialize the config")?;
+            let mut stored = Value::try_from(self).context("Failed to serialize the config")?;
+            let mut seal_token = true;
+            // Values of the environment stay out of the file, unless changed after loading
+            for o in &self.env {
+                if get_path(&stored, &o.path) == o.loaded.as_ref() {
+                    set_path(&mut stored, &o.path, o.file.clone())?;
+                    seal_token &= o.path != "token";
+                }
+            }
+            if seal_token {
+                // The serialized plain token doesn't outlive the sealing
+                if let Some(Value::String(token)) = stored.get_mut("token") {
+                    token.zeroize();
+                }
+                let token = secrets::seal(&self.config_path, &self.agent_id, self.token.as_ref())?;
+                set_path(
+                    &mut stored,
+                    "token",
+                    token.map(|t| Value::String(t.to_string())),
+                )?;
+            }
+            let s = toml::to_string_pretty(&stored).context("Failed to serialize the config")?;
             fs::write(&self.config_path, s).context("Failed to write the config")?;
         }
         Ok(())
@@ -240,7 +342,25 @@ impl ClientConfig {
                     self.minecraft_java_opts = Some(value.to_string())
                 }
             }
-            _ => bail!("Unknown key: {}", key),
+            "auto_upgrade" => self.auto_upgrade = value.parse().context("Invalid boolean value")?,
+            "lang" => {
+                if value.is_empty() {
+                    self.lang = None
+                } else {
+                    let lang = crate::i18n::supported_locale(value).with_context(
+                        || crate::t!("error-unsupported-language", "lang" => value),
+                    )?;
+                    self.lang = Some(lang)
+                }
+            }
+            "p2p_rendezvous" => {
+                if value.is_empty() {
+                    self.p2p_rendezvous = None
+                } else {
+                    self.p2p_rendezvous = Some(value.to_string())
+                }
+            }
+            _ => self.set_value(key_path(key), value)?,
         }
         self.save()?;
         Ok(())
@@ -263,12 +383,105 @@ impl ClientConfig {
             "1c_publish_dir" => Ok(self.one_c_publish_dir.clone().unwrap_or_default()),
             "minecraft_server" => Ok(self.minecraft_server.clone().unwrap_or_default()),
             "minecraft_java_opts" => Ok(self.minecraft_java_opts.clone().unwrap_or_default()),
+            "p2p_rendezvous" => Ok(self.p2p_rendezvous.clone().unwrap_or_default()),
+            "auto_upgrade" => Ok(self.auto_upgrade.to_string()),
+            "lang" => Ok(self.lang.clone().unwrap_or_default()),
             "unsafe_tls" => Ok(self.transport.tls.as_ref().map_or("".to_string(), |tls| {
                 tls.danger_ignore_certificate_verification
                     .map_or("".to_string(), |v| v.to_string())
             })),
-            _ => bail!("Unknown key: {}", key),
+            _ => self.get_value(key_path(key)),
+        }
+    }
+
+    /// Remove the key, optional keys only
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        let path = key_path(key);
+        let mut value = Value::try_from(&*self).context("Failed to serialize the config")?;
+        if get_path(&value, path).is_none() && get_path(&KEYS, path).is_none() {
+            bail!("Unknown key: {}", key);
+        }
+        set_path(&mut value, path, None)?;
+        self.replace_with(value)
+            .with_context(|| format!("Key {} is required", key))?;
+        self.save()
+    }
+
+    /// All keys with their values, secrets are masked
+    pub fn list(&self) -> Result<Vec<(String, String)>> {
+        let value = Value::try_from(self).context("Failed to serialize the config")?;
+        let mut paths = Vec::new();
+        leaf_paths(&KEYS, "", &mut paths);
+        leaf_paths(&value, "", &mut paths);
+        paths.sort();
+        paths.dedup();
+        Ok(paths
+            .into_iter()
+            .map(|path| {
+                let shown = match get_path(&value, &path) {
+                    None => String::new(),
+                    Some(Value::String(s)) if SECRET_KEYS.contains(&path.as_str()) => {
+                        format!("{:?}", Redacted(s))
+                    }
+                    Some(value) => display_value(value),
+                };
+                (path, shown)
+            })
+            .collect())
+    }
+
+    /// All problems of the config, unlike `validate` which stops at the first one
+    pub fn errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.token.is_none() {
+            errors.push(crate::t!("error-auth-missing"));
         }
+        if let Some(lang) = self.lang.as_ref() {
+            if crate::i18n::supported_locale(lang).is_none() {
+                errors.push(crate::t!("error-unsupported-language", "lang" => lang.as_str()));
+            }
+        }
+        errors.extend(self.transport.errors(false));
+        errors.extend(self.transport.missing_files());
+        errors
+    }
+
+    // Value at the dotted path, parsed in the type of the key
+    fn set_value(&mut self, path: &str, raw: &str) -> Result<()> {
+        let mut value = Value::try_from(&*self).context("Failed to serialize the config")?;
+        let hint = get_path(&value, path).or_else(|| get_path(&KEYS, path));
+        let new = parse_value(raw, hint).with_context(|| format!("Invalid value of {}", path))?;
+        let set = new.is_some();
+        set_path(&mut value, path, new)?;
+        self.replace_with(value)
+            .with_context(|| format!("Invalid value of {}", path))?;
+        // The top level ignores unknown keys
+        let value = Value::try_from(&*self).context("Failed to serialize the config")?;
+        if set && get_path(&value, path).is_none() {
+            bail!("Unknown key: {}", path);
+        }
+        Ok(())
+    }
+
+    fn get_value(&self, path: &str) -> Result<String> {
+        let value = Value::try_from(self).context("Failed to serialize the config")?;
+        match get_path(&value, path) {
+            Some(value) => Ok(display_value(value)),
+            None if get_path(&KEYS, path).is_some() => Ok(String::new()),
+            None => bail!("Unknown key: {}", path),
+        }
+    }
+
+    // Persistent fields from the value, the others are kept
+    fn replace_with(&mut self, value: Value) -> Result<()> {
+        let mut cfg: Self = value.try_into()?;
+        cfg.config_path = std::mem::take(&mut self.config_path);
+        cfg.readonly = self.readonly;
+        cfg.gui = self.gui;
+        cfg.credentials = self.credentials.take();
+        cfg.env = std::mem::take(&mut self.env);
+        *self = cfg;
+        Ok(())
     }
 
     pub fn validate(&self) -> Result<()> {
@@ -276,6 +489,9 @@ impl ClientConfig {
             bail!("{}", crate::t!("error-auth-missing"));
         }
         TransportConfig::validate(&self.transport, false)?;
+        if let Some(err) = self.transport.missing_files().into_iter().next() {
+            bail!(err);
+        }
         Ok(())
     }
 }
@@ -285,7 +501,8 @@ impl Default for ClientConfig {
         Self {
             agent_id: Uuid::new_v4().to_string(),
             config_path: PathBuf::new(),
-            server: crate::t!("server").parse().unwrap(),
+            // The server depends on the build, not on the selected language
+            server: crate::i18n::build_message("server").parse().unwrap(),
             token: None,
             heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT_SECS,
             one_c_home: None,
@@ -297,7 +514,159 @@ impl Default for ClientConfig {
             readonly: false,
             gui: false,
             hwid: None,
+            p2p_rendezvous: None,
+            auto_upgrade: false,
+            lang: None,
             credentials: None,
+            env: Vec::new(),
+        }
+    }
+}
+
+// Keys of `MaskedString` values
+const SECRET_KEYS: &[&str] = &["token", "transport.tls.pkcs12_password"];
+
+lazy_static! {
+    // Every key of the config with a value of its type, including the optional ones
+    static ref KEYS: Value = {
+        let mut cfg = ClientConfig {
+            token: Some(Default::default()),
+            one_c_home: Some(Default::default()),
+            one_c_platform: Some(Default::default()),
+            one_c_publish_dir: Some(Default::default()),
+            minecraft_server: Some(Default::default()),
+            minecraft_java_opts: Some(Default::default()),
+            hwid: Some(Default::default()),
+            p2p_rendezvous: Some(Default::default()),
+            lang: Some(Default::default()),
+            ..Default::default()
+        };
+        cfg.transport.tcp.proxy = "socks5://localhost".parse().ok();
+        cfg.transport.tls = Some(TlsConfig {
+            hostname: Some(Default::default()),
+            trusted_root: Some(Default::default()),
+            pkcs12: Some(Default::default()),
+            pkcs12_password: Some(Default::default()),
+            danger_ignore_certificate_verification: Some(false),
+        });
+        cfg.transport.websocket = Some(Default::default());
+        Value::try_from(&cfg).expect("Failed to serialize the config")
+    };
+}
+
+// Path in the file of the key of `clo set`
+fn key_path(key: &str) -> &str {
+    match key {
+        "1c_home" => "one_c_home",
+        "1c_platform" => "one_c_platform",
+        "1c_publish_dir" => "one_c_publish_dir",
+        "unsafe_tls" => "transport.tls.danger_ignore_certificate_verification",
+        _ => key,
+    }
+}
+
+fn leaf_paths(value: &Value, prefix: &str, paths: &mut Vec<String>) {
+    let Some(table) = value.as_table() else {
+        paths.push(prefix.to_string());
+        return;
+    };
+    for (key, value) in table {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        leaf_paths(value, &path, paths);
+    }
+}
+
+// Strings without quotes, the rest in TOML
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn get_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| value.get(key))
+}
+
+// Set or remove the value, creating the tables on the way
+fn set_path(value: &mut Value, path: &str, new: Option<Value>) -> Result<()> {
+    let (parents, key) = match path.rsplit_once('.') {
+        Some((parents, key)) => (parents.split('.').collect(), key),
+        None => (Vec::new(), path),
+    };
+    let mut table = value.as_table_mut().context("Config is not a table")?;
+    for parent in parents {
+        if new.is_none() && !table.contains_key(parent) {
+            return Ok(());
         }
+        table = table
+            .entry(parent)
+            .or_insert_with(|| Value::Table(Default::default()))
+            .as_table_mut()
+            .with_context(|| format!("Key {} is not a table", parent))?;
+    }
+    match new {
+        Some(new) => table.insert(key.to_string(), new),
+        None => table.remove(key),
+    };
+    Ok(())
+}
+
+// Value of the variable in the type of the current one. Strings are not
+// quoted, other new keys are TOML, and an empty value removes the key.
+fn parse_value(raw: &str, current: Option<&Value>) -> Result<Option<Value>> {
+    if raw.is_empty() {
+        return Ok(None);
+    }
+    let value = match current {
+        Some(Value::String(_)) => Value::String(raw.to_string()),
+        Some(Value::Boolean(_)) => Value::Boolean(raw.parse().context("Invalid boolean value")?),
+        Some(Value::Integer(_)) => Value::Integer(raw.parse().context("Invalid integer value")?),
+        Some(Value::Float(_)) => Value::Float(raw.parse().context("Invalid number")?),
+        Some(_) => parse_toml(raw)?,
+        None if raw == "true" || raw == "false" || raw.starts_with(['[', '{']) => parse_toml(raw)?,
+        None => Value::String(raw.to_string()),
+    };
+    Ok(Some(value))
+}
+
+fn parse_toml(raw: &str) -> Result<Value> {
+    let mut table: toml::value::Table =
+        toml::from_str(&format!("value = {}", raw)).context("Invalid TOML value")?;
+    table.remove("value").context("Invalid TOML value")
+}
+
+fn apply_env(value: &mut Value) -> Result<Vec<EnvOverride>> {
+    let mut vars: Vec<(String, String)> = std::env::vars_os()
+        .filter_map(|(var, raw)| Some((var.into_string().ok()?, raw.into_string().ok()?)))
+        .filter(|(var, _)| var.starts_with(ENV_PREFIX))
+        .collect();
+    vars.sort();
+
+    let mut overrides = Vec::new();
+    for (var, raw) in vars {
+        let path = var[ENV_PREFIX.len()..]
+            .to_lowercase()
+            .replace(ENV_SEPARATOR, ".");
+        // Other CLO_ variables belong to the command line, e.g. CLO_PASSWORD
+        let Some(key) = get_path(&KEYS, &path) else {
+            debug!("Ignore {}: not a config key", var);
+            continue;
+        };
+        let file = get_path(value, &path).cloned();
+        let hint = file.as_ref().unwrap_or(key);
+        let new = parse_value(&raw, Some(hint)).with_context(|| format!("Invalid value of {}", var))?;
+        set_path(value, &path, new).with_context(|| format!("Invalid key of {}", var))?;
+        overrides.push(EnvOverride {
+            var,
+            path,
+            file,
+            loaded: None,
+        });
     }
+    Ok(overrides)
 }
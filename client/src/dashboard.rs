@@ -0,0 +1,472 @@
+use crate::status::{AgentStatus, EndpointInfo, REGISTRY};
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use common::protocol::message::Message;
+use common::protocol::{EndpointRemove, EndpointStop, ErrorKind, ProgressInfo, Stop};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{
+    Block, Cell, Gauge, List, ListItem, Paragraph, Row, Sparkline, Table, TableState,
+};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::{error, warn};
+
+const MAX_EVENTS: usize = 200;
+const MAX_ERRORS: usize = 50;
+const HISTORY_LEN: usize = 120;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Full-screen view of the running agent. The terminal is restored when
+/// the dashboard is dropped.
+pub struct Dashboard {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    stdout: broadcast::Sender<String>,
+    stderr: broadcast::Sender<String>,
+}
+
+impl Dashboard {
+    /// Take over the terminal. Keybindings are sent to the client with
+    /// `command_tx`, quitting is reported to `main_loop` as `Stop` in `result_tx`.
+    pub fn spawn(
+        command_tx: broadcast::Sender<Message>,
+        result_tx: broadcast::Sender<Message>,
+    ) -> Result<Self> {
+        let (stdout, stdout_rx) = broadcast::channel(1024);
+        let (stderr, stderr_rx) = broadcast::channel(1024);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let mut terminal = ratatui::try_init().context("Failed to initialize terminal")?;
+        let mut app = App::new(command_tx, result_tx.subscribe(), stdout_rx, stderr_rx);
+        let stop_flag = stop.clone();
+        let thread = std::thread::spawn(move || {
+            if let Err(err) = app.run(&mut terminal, &stop_flag) {
+                error!("Dashboard failed: {:#}", err);
+            }
+            ratatui::restore();
+            // Unblock main_loop if the user quit
+            result_tx.send(Message::Stop(Stop {})).ok();
+        });
+
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Sink for the lines `main_loop` would print to stdout
+    pub fn stdout(&self) -> broadcast::Sender<String> {
+        self.stdout.clone()
+    }
+
+    /// Sink for the lines `main_loop` would print to stderr
+    pub fn stderr(&self) -> broadcast::Sender<String> {
+        self.stderr.clone()
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+struct App {
+    command_tx: broadcast::Sender<Message>,
+    result_rx: broadcast::Receiver<Message>,
+    stdout_rx: broadcast::Receiver<String>,
+    stderr_rx: broadcast::Receiver<String>,
+    status: AgentStatus,
+    selected: TableState,
+    // Bytes per second of each endpoint, the last value is the newest
+    history: HashMap<String, VecDeque<u64>>,
+    totals: HashMap<String, u64>,
+    events: VecDeque<Line<'static>>,
+    errors: VecDeque<String>,
+    progress: Option<ProgressInfo>,
+}
+
+impl App {
+    fn new(
+        command_tx: broadcast::Sender<Message>,
+        result_rx: broadcast::Receiver<Message>,
+        stdout_rx: broadcast::Receiver<String>,
+        stderr_rx: broadcast::Receiver<String>,
+    ) -> Self {
+        Self {
+            command_tx,
+            result_rx,
+            stdout_rx,
+            stderr_rx,
+            status: REGISTRY.snapshot(),
+            selected: TableState::default().with_selected(Some(0)),
+            history: HashMap::new(),
+            totals: HashMap::new(),
+            events: VecDeque::new(),
+            errors: VecDeque::new(),
+            progress: None,
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal, stop: &AtomicBool) -> Result<()> {
+        let mut sampled = Instant::now();
+        self.sample();
+
+        while !stop.load(Ordering::Relaxed) {
+            self.drain();
+            if sampled.elapsed() >= SAMPLE_INTERVAL {
+                sampled = Instant::now();
+                self.sample();
+            }
+
+            terminal.draw(|frame| self.render(frame))?;
+
+            if !event::poll(POLL_INTERVAL)? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => break,
+                    // Raw mode swallows SIGINT
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+                    KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+                    KeyCode::Down | KeyCode::Char('j') => self.select(1),
+                    KeyCode::Char('s') => {
+                        if let Some(ep) = self.current() {
+                            let guid = ep.guid.clone();
+                            self.command_tx
+                                .send(Message::EndpointStop(EndpointStop { guid }))
+                                .ok();
+                        }
+                    }
+                    KeyCode::Char('r') => {
+                        if let Some(ep) = self.current() {
+                            let guid = ep.guid.clone();
+                            self.command_tx
+                                .send(Message::EndpointRemove(EndpointRemove { guid }))
+                                .ok();
+                        }
+                    }
+                    KeyCode::Char('c') => {
+                        if let Some(url) = self.current().map(|ep| ep.url.clone()) {
+                            copy_to_clipboard(&url);
+                            self.push_event(
+                                crate::t!("dashboard-url-copied", "url" => url),
+                                Style::default(),
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Pick up everything received since the last frame
+    fn drain(&mut self) {
+        loop {
+            match self.result_rx.try_recv() {
+                Ok(Message::Error(err)) => {
+                    let kind: ErrorKind = err.kind.try_into().unwrap_or(ErrorKind::Fatal);
+                    if self.errors.len() == MAX_ERRORS {
+                        self.errors.pop_front();
+                    }
+                    self.errors
+                        .push_back(format!("{:?}: {}", kind, err.message));
+                }
+                Ok(Message::Progress(info)) => {
+                    if info.current >= info.total {
+                        self.progress = None;
+                    } else {
+                        self.progress = Some(info);
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::TryRecvError::Lagged(n)) => {
+                    warn!("Dashboard skipped {} messages", n);
+                }
+                Err(_) => break,
+            }
+        }
+        while let Ok(line) = self.stdout_rx.try_recv() {
+            self.push_event(line, Style::default());
+        }
+        while let Ok(line) = self.stderr_rx.try_recv() {
+            self.push_event(line, Style::default().fg(Color::Yellow));
+        }
+    }
+
+    fn push_event(&mut self, text: String, style: Style) {
+        for line in text.lines() {
+            if self.events.len() == MAX_EVENTS {
+                self.events.pop_front();
+            }
+            self.events.push_back(Line::styled(line.to_string(), style));
+        }
+    }
+
+    fn sample(&mut self) {
+        self.status = REGISTRY.snapshot();
+        let mut totals = HashMap::new();
+        for ep in &self.status.endpoints {
+            let total = ep.bytes_sent + ep.bytes_received;
+            let last = self.totals.get(&ep.guid).copied().unwrap_or(total);
+            let history = self.history.entry(ep.guid.clone()).or_default();
+            if history.len() == HISTORY_LEN {
+                history.pop_front();
+            }
+            history.push_back(total.saturating_sub(last));
+            totals.insert(ep.guid.clone(), total);
+        }
+        // Forget removed endpoints
+        self.history.retain(|guid, _| totals.contains_key(guid));
+        self.totals = totals;
+
+        let count = self.status.endpoints.len();
+        match self.selected.selected() {
+            Some(i) if i >= count => self.selected.select(count.checked_sub(1)),
+            None if count > 0 => self.selected.select(Some(0)),
+            _ => {}
+        }
+    }
+
+    fn select(&mut self, delta: isize) {
+        let count = self.status.endpoints.len();
+        if count == 0 {
+            return;
+        }
+        let current = self.selected.selected().unwrap_or(0) as isize;
+        let next = (current + delta).clamp(0, count as isize - 1);
+        self.selected.select(Some(next as usize));
+    }
+
+    fn current(&self) -> Option<&EndpointInfo> {
+        self.selected
+            .selected()
+            .and_then(|i| self.status.endpoints.get(i))
+    }
+
+    fn render(&mut self, frame: &mut Frame) {
+        let progress_height = if self.progress.is_some() { 3 } else { 0 };
+        let [header, endpoints, details, log, progress, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(self.status.endpoints.len() as u16 + 3),
+            Constraint::Min(6),
+            Constraint::Min(6),
+            Constraint::Length(progress_height),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let unknown = || "-".to_string();
+        frame.render_widget(
+            Paragraph::new(crate::t!("dashboard-header",
+                "version" => self.status.version.clone(),
+                "state" => self.status.state.clone(),
+                "server" => self.status.server.clone(),
+                "rtt" => self.status.rtt_ms.map(|v| v.to_string()).unwrap_or_else(unknown)))
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+            header,
+        );
+
+        self.render_endpoints(frame, endpoints);
+
+        let [connections, throughput] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(details);
+        self.render_connections(frame, connections);
+        self.render_throughput(frame, throughput);
+
+        let [events, errors] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(log);
+        render_tail(
+            frame,
+            events,
+            crate::t!("dashboard-events"),
+            self.events.iter().cloned(),
+            self.events.len(),
+        );
+        render_tail(
+            frame,
+            errors,
+            crate::t!("dashboard-errors"),
+            self.errors
+                .iter()
+                .map(|e| Line::styled(e.clone(), Style::default().fg(Color::Red))),
+            self.errors.len(),
+        );
+
+        if let Some(info) = self.progress.as_ref() {
+            let ratio = info.current as f64 / info.total.max(1) as f64;
+            frame.render_widget(
+                Gauge::default()
+                    .block(Block::bordered().title(info.message.clone()))
+                    .gauge_style(Style::default().fg(Color::Cyan))
+                    .ratio(ratio.clamp(0.0, 1.0))
+                    .label(format!("{}/{}", info.current, info.total)),
+                progress,
+            );
+        }
+
+        frame.render_widget(
+            Paragraph::new(crate::t!("dashboard-keys")).style(Style::default().fg(Color::DarkGray)),
+            footer,
+        );
+    }
+
+    fn render_endpoints(&mut self, frame: &mut Frame, area: Rect) {
+        let header = Row::new([
+            crate::t!("dashboard-column-service"),
+            crate::t!("dashboard-column-url"),
+            crate::t!("dashboard-column-target"),
+            crate::t!("dashboard-column-status"),
+            crate::t!("dashboard-column-connections"),
+            crate::t!("dashboard-column-sent"),
+            crate::t!("dashboard-column-received"),
+        ])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+
+        let rows = self.status.endpoints.iter().map(|ep| {
+            let color = match ep.status.as_str() {
+                "online" => Color::Green,
+                "unhealthy" => Color::Red,
+                _ => Color::Yellow,
+            };
+            Row::new([
+                Cell::from(ep.name.clone().unwrap_or_else(|| ep.guid.clone())),
+                Cell::from(ep.url.clone()),
+                Cell::from(ep.target.clone()),
+                Cell::from(Span::styled(ep.status.clone(), Style::default().fg(color))),
+                Cell::from(ep.active_channels.to_string()),
+                Cell::from(format_bytes(ep.bytes_sent)),
+                Cell::from(format_bytes(ep.bytes_received)),
+            ])
+        });
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Fill(2),
+                Constraint::Fill(1),
+                Constraint::Length(10),
+                Constraint::Length(7),
+                Constraint::Length(10),
+                Constraint::Length(10),
+            ],
+        )
+        .header(header)
+        .block(Block::bordered().title(crate::t!("dashboard-endpoints")))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        frame.render_stateful_widget(table, area, &mut self.selected);
+    }
+
+    fn render_connections(&self, frame: &mut Frame, area: Rect) {
+        let mut items: Vec<ListItem> = Vec::new();
+        if let Some(ep) = self.current() {
+            items.extend(ep.visitors.iter().map(|v| ListItem::new(v.clone())));
+            // Servers that don't report visitor addresses
+            let unknown = ep.active_channels.saturating_sub(ep.visitors.len() as u64);
+            if unknown > 0 {
+                items.push(ListItem::new(
+                    crate::t!("dashboard-unknown-visitors", "count" => unknown),
+                ));
+            }
+        }
+        if items.is_empty() {
+            items.push(
+                ListItem::new(crate::t!("dashboard-no-visitors"))
+                    .style(Style::default().fg(Color::DarkGray)),
+            );
+        }
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title(crate::t!("dashboard-connections"))),
+            area,
+        );
+    }
+
+    fn render_throughput(&self, frame: &mut Frame, area: Rect) {
+        let history = self
+            .current()
+            .and_then(|ep| self.history.get(&ep.guid))
+            .cloned()
+            .unwrap_or_default();
+        let width = area.width.saturating_sub(2) as usize;
+        let data: Vec<u64> = history
+            .iter()
+            .skip(history.len().saturating_sub(width))
+            .copied()
+            .collect();
+        let title = format!(
+            "{} {}/s",
+            crate::t!("dashboard-throughput"),
+            format_bytes(data.last().copied().unwrap_or(0))
+        );
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(title))
+                .style(Style::default().fg(Color::Cyan))
+                .data(&data),
+            area,
+        );
+    }
+}
+
+// Render the newest lines that fit into the area
+fn render_tail<'a>(
+    frame: &mut Frame,
+    area: Rect,
+    title: String,
+    lines: impl Iterator<Item = Line<'a>>,
+    count: usize,
+) {
+    let height = area.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = lines.skip(count.saturating_sub(height)).collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(title)),
+        area,
+    );
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+// OSC 52 asks the terminal to set the clipboard, which also works over SSH
+fn copy_to_clipboard(text: &str) {
+    let mut stdout = std::io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", STANDARD.encode(text))
+        .and_then(|_| stdout.flush())
+        .ok();
+}
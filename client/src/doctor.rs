@@ -0,0 +1,301 @@
+use crate::client::agent_info;
+use crate::config::ClientConfig;
+use crate::exec::is_listening;
+use anyhow::{anyhow, bail, Context, Result};
+use common::config::{TlsConfig, TransportType};
+use common::protocol::message::Message;
+use common::protocol::{read_message, write_message, EndpointList, Protocol, ServerEndpoint};
+use common::transport::rustls::peer_certificates;
+use common::transport::{
+    tcp_connect_with_proxy, AddrMaybeCached, TcpTransport, TlsTransport, Transport,
+    WebsocketTransport,
+};
+use common::utils::host_port_pair;
+use std::future::Future;
+use tokio::net::TcpStream;
+use tokio::time::{self, Duration};
+use uuid::Uuid;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REDIRECTS: usize = 3;
+
+struct Report<F: Fn(String)> {
+    write: F,
+    passed: usize,
+    failed: usize,
+}
+
+impl<F: Fn(String)> Report<F> {
+    fn pass(&mut self, step: String, detail: String) {
+        self.passed += 1;
+        (self.write)(format!("[ OK ] {}: {}", step, detail));
+    }
+
+    fn fail(&mut self, step: String, err: &anyhow::Error) {
+        self.failed += 1;
+        (self.write)(format!("[FAIL] {}: {:#}", step, err));
+    }
+
+    fn skip(&mut self, step: String, reason: String) {
+        (self.write)(format!("[SKIP] {}: {}", step, reason));
+    }
+
+    fn detail(&self, line: String) {
+        (self.write)(format!("       {}", line));
+    }
+
+    fn check<T>(
+        &mut self,
+        step: String,
+        res: Result<T>,
+        detail: impl FnOnce(&T) -> String,
+    ) -> Option<T> {
+        match res {
+            Ok(v) => {
+                self.pass(step, detail(&v));
+                Some(v)
+            }
+            Err(err) => {
+                self.fail(step, &err);
+                None
+            }
+        }
+    }
+}
+
+async fn timed<T>(fut: impl Future<Output = Result<T>>) -> Result<T> {
+    time::timeout(STEP_TIMEOUT, fut)
+        .await
+        .with_context(|| crate::t!("doctor-timeout", "secs" => STEP_TIMEOUT.as_secs()))?
+}
+
+/// Check every hop from DNS to the local services and print a report.
+/// Fails if any check failed.
+pub async fn run<F: Fn(String)>(config: &ClientConfig, write: F) -> Result<()> {
+    let mut report = Report {
+        write,
+        passed: 0,
+        failed: 0,
+    };
+
+    let transport = &config.transport;
+    let host = config.server.host_str().context("Failed to get host")?;
+    let port = config.server.port().unwrap_or(443);
+    let host_and_port = format!("{}:{}", host, port);
+
+    (report.write)(crate::t!("doctor-header",
+        "server" => host_and_port.clone(),
+        "transport" => format!("{:?}", transport.transport_type).to_lowercase()));
+
+    // DNS
+    let mut addr = AddrMaybeCached::new(&host_and_port);
+    let resolved = addr
+        .resolve_with_hosts(&transport.tcp.hosts)
+        .await
+        .map(|_| addr.socket_addrs.clone());
+    let Some(addrs) = report.check(
+        crate::t!("doctor-step-dns", "host" => host),
+        resolved,
+        |addrs| {
+            addrs
+                .iter()
+                .map(|a| a.ip().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        },
+    ) else {
+        return summary(report);
+    };
+
+    // TCP reach of each resolved address
+    for socket_addr in &addrs {
+        let step = crate::t!("doctor-step-tcp", "address" => socket_addr.to_string());
+        if transport.tcp.proxy.is_some() {
+            report.skip(step, crate::t!("doctor-behind-proxy"));
+            continue;
+        }
+        let res = timed(async { Ok(TcpStream::connect(socket_addr).await?) }).await;
+        report.check(step, res, |_| crate::t!("doctor-reachable"));
+    }
+
+    // Proxy CONNECT
+    match transport.tcp.proxy.as_ref() {
+        Some(proxy) => {
+            let step = crate::t!("doctor-step-proxy", "proxy" => proxy.to_string());
+            let res = timed(tcp_connect_with_proxy(&addr, Some(proxy))).await;
+            report.check(step, res, |_| crate::t!("doctor-reachable"));
+        }
+        None => report.skip(
+            crate::t!("doctor-step-proxy", "proxy" => "-"),
+            crate::t!("doctor-no-proxy"),
+        ),
+    }
+
+    // TLS handshake
+    let tls_config = transport.tls.clone().unwrap_or_default();
+    let host_name = match tls_config.hostname.clone() {
+        Some(name) => name,
+        None => host_port_pair(&host_and_port)?.0.to_string(),
+    };
+    let step = crate::t!("doctor-step-tls", "host" => host_name.clone());
+    if transport.uses_tls() {
+        let fetch = |config: TlsConfig| {
+            let addr = addr.clone();
+            let proxy = transport.tcp.proxy.clone();
+            let host_name = host_name.clone();
+            timed(async move {
+                let conn = tcp_connect_with_proxy(&addr, proxy.as_ref()).await?;
+                peer_certificates(conn, &config, &host_name).await
+            })
+        };
+        match fetch(tls_config.clone()).await {
+            Ok(chain) => {
+                report.pass(step, crate::t!("doctor-certificate-verified"));
+                print_chain(&report, &chain);
+            }
+            Err(err) => {
+                report.fail(step, &err);
+                // Show what the server presented to explain the verification error
+                let insecure = TlsConfig {
+                    danger_ignore_certificate_verification: Some(true),
+                    ..tls_config
+                };
+                if let Ok(chain) = fetch(insecure).await {
+                    print_chain(&report, &chain);
+                }
+            }
+        }
+    } else {
+        report.skip(step, crate::t!("doctor-no-tls"));
+    }
+
+    // WebSocket upgrade
+    let scheme = if transport.uses_tls() { "wss" } else { "ws" };
+    let url = format!("{}://{}/endpoint/v2", scheme, host_and_port);
+    let step = crate::t!("doctor-step-websocket", "url" => url);
+    if transport.transport_type == TransportType::Websocket {
+        let res = timed(async {
+            let ws = WebsocketTransport::new(transport)?;
+            ws.connect(&addr).await
+        })
+        .await;
+        report.check(step, res, |_| crate::t!("doctor-upgraded"));
+    } else {
+        report.skip(step, crate::t!("doctor-no-websocket"));
+    }
+
+    // Hello and the services of the agent
+    let endpoints = match transport.transport_type {
+        TransportType::Tcp => session::<TcpTransport, F>(config, addr, &mut report).await,
+        TransportType::Tls => session::<TlsTransport, F>(config, addr, &mut report).await,
+        TransportType::Websocket => {
+            session::<WebsocketTransport, F>(config, addr, &mut report).await
+        }
+    };
+
+    for endpoint in endpoints.unwrap_or_default() {
+        check_service(&endpoint, &mut report).await;
+    }
+
+    summary(report)
+}
+
+fn summary<F: Fn(String)>(report: Report<F>) -> Result<()> {
+    (report.write)(String::new());
+    (report.write)(crate::t!("doctor-summary",
+        "passed" => report.passed,
+        "failed" => report.failed));
+    if report.failed > 0 {
+        bail!(crate::t!("doctor-failed"));
+    }
+    Ok(())
+}
+
+fn print_chain<F: Fn(String)>(report: &Report<F>, chain: &[Vec<u8>]) {
+    for (i, der) in chain.iter().enumerate() {
+        match X509Certificate::from_der(der) {
+            Ok((_, cert)) => report.detail(crate::t!("doctor-certificate",
+                "index" => i,
+                "subject" => cert.subject().to_string(),
+                "issuer" => cert.issuer().to_string(),
+                "expires" => cert.validity().not_after.to_string())),
+            Err(err) => report.detail(format!("#{}: {}", i, err)),
+        }
+    }
+}
+
+// Authorize with the current token and fetch the registered services
+async fn session<T: Transport, F: Fn(String)>(
+    config: &ClientConfig,
+    mut addr: AddrMaybeCached,
+    report: &mut Report<F>,
+) -> Option<Vec<ServerEndpoint>> {
+    let step = crate::t!("doctor-step-hello");
+    if config.token.is_none() {
+        report.skip(step, crate::t!("doctor-no-token"));
+        return None;
+    }
+
+    // A hello with the id of a running agent would take over its session
+    let mut probe = config.clone();
+    probe.agent_id = Uuid::new_v4().to_string();
+
+    let res = timed(async {
+        let transport = T::new(&config.transport)?;
+        let mut redirects = 0;
+        let mut conn = loop {
+            let mut conn = transport.connect(&addr).await?;
+            let hello = agent_info(&probe, addr.to_string())?;
+            write_message(&mut conn, &Message::AgentHello(hello)).await?;
+            match read_message(&mut conn).await? {
+                Message::AgentAck(_) => break conn,
+                Message::Redirect(r) if redirects < MAX_REDIRECTS => {
+                    redirects += 1;
+                    report.detail(crate::t!("doctor-redirect", "host" => r.host_and_port.clone()));
+                    addr = AddrMaybeCached::new(&r.host_and_port);
+                    addr.resolve_with_hosts(&config.transport.tcp.hosts).await?;
+                }
+                Message::Error(err) => bail!("{}", err.message),
+                msg => bail!("Unexpected ack message: {:?}", msg),
+            }
+        };
+
+        write_message(&mut conn, &Message::EndpointList(EndpointList {})).await?;
+        loop {
+            // Skip heartbeats and other notifications
+            if let Message::EndpointListAck(list) = read_message(&mut conn).await? {
+                break Ok(list.endpoints);
+            }
+        }
+    })
+    .await;
+
+    report.check(
+        step,
+        res,
+        |endpoints| crate::t!("doctor-authorized", "count" => endpoints.len()),
+    )
+}
+
+async fn check_service<F: Fn(String)>(endpoint: &ServerEndpoint, report: &mut Report<F>) {
+    let Some(client) = endpoint.client.as_ref() else {
+        return;
+    };
+    let step = crate::t!("doctor-step-service",
+        "guid" => endpoint.guid.clone(),
+        "target" => client.to_string());
+    match client.local_proto() {
+        Protocol::Udp => report.skip(step, crate::t!("doctor-udp-target")),
+        Protocol::OneC | Protocol::Minecraft | Protocol::Webdav => {
+            report.skip(step, crate::t!("doctor-plugin-target"))
+        }
+        _ => {
+            let res = match time::timeout(STEP_TIMEOUT, is_listening(client)).await {
+                Ok(true) => Ok(()),
+                _ => Err(anyhow!(crate::t!("doctor-target-down"))),
+            };
+            report.check(step, res, |_| crate::t!("doctor-reachable"));
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::shell::{check_guid, get_cache_dir};
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::time::{self, Duration};
+use tracing::{debug, warn};
+
+const MAINTENANCE_DIR: &str = "maintenance";
+// Templates are served only from here, the endpoint refers to them by name
+const TEMPLATES_DIR: &str = "error_pages";
+// Enough for the request head of any sane browser
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const HEAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub const STATUS_BAD_GATEWAY: u16 = 502;
+pub const STATUS_SERVICE_UNAVAILABLE: u16 = 503;
+
+const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{status} {reason}</title>
+<style>
+body { font-family: sans-serif; color: #333; text-align: center; padding-top: 15vh; }
+h1 { font-size: 3em; margin-bottom: 0; }
+</style>
+</head>
+<body>
+<h1>{status}</h1>
+<p>{message}</p>
+<p><small>CloudPub</small></p>
+</body>
+</html>
+"#;
+
+fn maintenance_flag(guid: &str) -> Result<PathBuf> {
+    check_guid(guid)?;
+    Ok(get_cache_dir(MAINTENANCE_DIR)?.join(guid))
+}
+
+/// Maintenance mode is a flag file, so it can be toggled from another process
+pub fn is_maintenance(guid: &str) -> bool {
+    maintenance_flag(guid).map(|p| p.exists()).unwrap_or(false)
+}
+
+pub fn set_maintenance(guid: &str, enabled: bool) -> Result<()> {
+    let flag = maintenance_flag(guid)?;
+    if enabled {
+        std::fs::write(&flag, b"").context("Failed to enable maintenance mode")?;
+    } else if flag.exists() {
+        std::fs::remove_file(&flag).context("Failed to disable maintenance mode")?;
+    }
+    Ok(())
+}
+
+/// Copy the template of `--error-page` into the templates dir and return
+/// its name there, named by the content so republishing doesn't pile up copies
+pub fn install_template(path: &str) -> Result<String> {
+    let body = std::fs::read(path)
+        .with_context(|| crate::t!("error-page-not-found", "path" => path.to_string()))?;
+    let digest: String = Sha256::digest(&body)[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let name = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}.{}", digest, ext),
+        None => digest,
+    };
+    std::fs::write(get_cache_dir(TEMPLATES_DIR)?.join(&name), body)
+        .context("Failed to store error page template")?;
+    Ok(name)
+}
+
+// The name comes with the endpoint from the server, so anything resolving
+// outside of the templates dir is refused
+fn template_path(template: &str) -> Result<PathBuf> {
+    let dir = get_cache_dir(TEMPLATES_DIR)?.canonicalize()?;
+    let path = dir.join(template).canonicalize()?;
+    if !path.starts_with(&dir) {
+        bail!("Error page {} is outside of {}", template, dir.display());
+    }
+    Ok(path)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        STATUS_BAD_GATEWAY => "Bad Gateway",
+        STATUS_SERVICE_UNAVAILABLE => "Service Unavailable",
+        _ => "Error",
+    }
+}
+
+fn content_type(template: &str) -> &'static str {
+    match Path::new(template).extension().and_then(|e| e.to_str()) {
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "text/html; charset=utf-8",
+    }
+}
+
+async fn render(status: u16, template: &str) -> (String, &'static str) {
+    let message = if status == STATUS_SERVICE_UNAVAILABLE {
+        crate::t!("error-page-maintenance")
+    } else {
+        crate::t!("error-page-upstream-down")
+    };
+
+    let body = if template.is_empty() {
+        DEFAULT_TEMPLATE.to_string()
+    } else {
+        let body = match template_path(template) {
+            Ok(path) => tokio::fs::read_to_string(path).await.map_err(Into::into),
+            Err(err) => Err(err),
+        };
+        match body {
+            Ok(body) => body,
+            Err(err) => {
+                warn!("Failed to read error page {}: {:#}", template, err);
+                DEFAULT_TEMPLATE.to_string()
+            }
+        }
+    };
+
+    let body = body
+        .replace("{status}", &status.to_string())
+        .replace("{reason}", reason(status))
+        .replace("{message}", &message);
+
+    (body, content_type(template))
+}
+
+/// Returns a stream which behaves like an upstream answering every request
+/// with the given status, so the response goes through the regular
+/// (possibly compressed) forwarding path.
+pub fn serve(status: u16, template: &str) -> DuplexStream {
+    let (local, mut upstream) = tokio::io::duplex(MAX_HEAD_SIZE);
+    let template = template.to_string();
+
+    tokio::spawn(async move {
+        // Consume the request head, browsers may reset the connection if it's left unread
+        let mut head = Vec::new();
+        let mut buf = [0u8; 1024];
+        let read_head = async {
+            while head.len() < MAX_HEAD_SIZE && !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                match upstream.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => head.extend_from_slice(&buf[..n]),
+                }
+            }
+        };
+        time::timeout(HEAD_TIMEOUT, read_head).await.ok();
+
+        let (body, content_type) = render(status, &template).await;
+        let response = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+            status,
+            reason(status),
+            content_type,
+            body.len(),
+            body
+        );
+        if let Err(err) = upstream.write_all(response.as_bytes()).await {
+            debug!("Failed to send error page: {}", err);
+        }
+        upstream.shutdown().await.ok();
+    });
+
+    local
+}
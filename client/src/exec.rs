@@ -0,0 +1,122 @@
+use anyhow::{bail, Context, Result};
+use common::protocol::{ClientEndpoint, Endpoint, ServerEndpoint};
+use common::transport::happy_eyeballs;
+use common::utils::{local_socket_addrs, unix_socket_path};
+use std::process::{ExitStatus, Stdio};
+use tokio::process::{Child, Command};
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, info};
+
+/// Environment variable with the public URL of the published service
+pub const URL_ENV: &str = "CLOUDPUB_URL";
+
+const PORT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const PORT_WAIT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Start the user application. It shares stdio with the client, so its
+/// output goes straight to the terminal.
+pub fn spawn(args: &[String], endpoint: &ServerEndpoint) -> Result<Child> {
+    if args.is_empty() {
+        bail!("Command is empty");
+    }
+
+    info!("Launching application: {}", args.join(" "));
+
+    // Let the shell resolve scripts like npm.cmd
+    #[cfg(windows)]
+    let mut cmd = {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").args(args);
+        cmd
+    };
+    #[cfg(not(windows))]
+    let mut cmd = {
+        let mut cmd = Command::new(&args[0]);
+        cmd.args(&args[1..]);
+        cmd
+    };
+
+    cmd.env(URL_ENV, endpoint.as_url())
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| crate::t!("error-exec-spawn", "command" => args.join(" ")))
+}
+
+/// Wait for the child to exit, forwarding termination signals to it
+pub async fn supervise(child: &mut Child) -> Result<ExitStatus> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut sigint = signal(SignalKind::interrupt())?;
+        let mut sigterm = signal(SignalKind::terminate())?;
+        let mut sighup = signal(SignalKind::hangup())?;
+
+        loop {
+            let signo = tokio::select! {
+                status = child.wait() => return status.context("Failed to wait on child"),
+                _ = sigint.recv() => libc::SIGINT,
+                _ = sigterm.recv() => libc::SIGTERM,
+                _ = sighup.recv() => libc::SIGHUP,
+            };
+            let Some(pid) = child.id() else {
+                continue;
+            };
+            // SAFETY: pid belongs to our own child which is not reaped yet
+            let own_group = unsafe { libc::getpgid(pid as libc::pid_t) != libc::getpgrp() };
+            // The terminal sends Ctrl-C and hangups to the whole foreground
+            // group, so a child in our group already got them
+            if signo != libc::SIGTERM && !own_group {
+                continue;
+            }
+            debug!("Forwarding signal {} to {}", signo, pid);
+            // SAFETY: same child, still not reaped
+            unsafe {
+                libc::kill(pid as libc::pid_t, signo);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::select! {
+            status = child.wait() => status.context("Failed to wait on child"),
+            _ = tokio::signal::ctrl_c() => {
+                debug!("Terminating child process");
+                child.start_kill().ok();
+                child.wait().await.context("Failed to wait on child")
+            }
+        }
+    }
+}
+
+/// Poll the local target until it accepts connections
+pub async fn wait_for_port(client: &ClientEndpoint) -> Result<()> {
+    let started = Instant::now();
+    loop {
+        if is_listening(client).await {
+            return Ok(());
+        }
+        if started.elapsed() > PORT_WAIT_TIMEOUT {
+            bail!(crate::t!("error-exec-port-timeout", "address" => client.to_string()));
+        }
+        time::sleep(PORT_POLL_INTERVAL).await;
+    }
+}
+
+/// Check that the local target accepts connections
+pub async fn is_listening(client: &ClientEndpoint) -> bool {
+    if let Some(_path) = unix_socket_path(&client.local_addr) {
+        #[cfg(unix)]
+        return tokio::net::UnixStream::connect(_path).await.is_ok();
+        #[cfg(not(unix))]
+        return false;
+    }
+    match local_socket_addrs(&client.local_addr, client.local_port as u16).await {
+        Ok(addrs) => happy_eyeballs::connect(&addrs).await.is_ok(),
+        Err(_) => false,
+    }
+}
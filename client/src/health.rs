@@ -0,0 +1,305 @@
+use crate::status::StatusRegistry;
+use anyhow::{bail, Context, Result};
+use common::protocol::message::Message;
+use common::protocol::{
+    ClientEndpoint, EndpointStatus, HealthCheck, HealthCheckKind, Protocol, ServerEndpoint,
+};
+use common::transport::happy_eyeballs;
+use common::utils::{format_host_port, local_socket_addrs, unix_socket_path};
+use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+use tracing::{debug, info, warn};
+
+pub const STATUS_ONLINE: &str = "online";
+pub const STATUS_UNHEALTHY: &str = "unhealthy";
+
+const DEFAULT_INTERVAL_SECS: u32 = 10;
+const DEFAULT_TIMEOUT_SECS: u32 = 5;
+// Consecutive failed probes before the endpoint is reported unhealthy
+const FAILURE_THRESHOLD: u32 = 3;
+
+/// Guids of endpoints which don't accept new connections until they recover
+pub type PausedEndpoints = Arc<RwLock<HashSet<String>>>;
+
+/// Health checks with commands of the endpoints this process published.
+/// The server echoes checks back in acks, but a command from it is never run.
+#[derive(Default)]
+pub struct LocalChecks(HashMap<String, HealthCheck>);
+
+impl LocalChecks {
+    /// Remember the command check of an endpoint sent in `EndpointStart`
+    pub fn insert(&mut self, client: &ClientEndpoint) {
+        if let Some(check) = &client.health_check {
+            if check.kind() == HealthCheckKind::Command {
+                self.0.insert(target_key(client), check.clone());
+            }
+        }
+    }
+
+    /// Health check of an acked endpoint, command checks only from local state
+    pub fn resolve(&self, client: &ClientEndpoint) -> Option<HealthCheck> {
+        let check = client.health_check.clone()?;
+        if check.kind() != HealthCheckKind::Command {
+            return Some(check);
+        }
+        let local = self.0.get(&target_key(client)).cloned();
+        if local.is_none() {
+            warn!(
+                "Ignoring health check command of {} not published by this agent",
+                format_host_port(&client.local_addr, client.local_port as u16)
+            );
+        }
+        local
+    }
+}
+
+// The server fills in defaults, so only the local target identifies the ack
+fn target_key(client: &ClientEndpoint) -> String {
+    format!(
+        "{}|{}|{}|{}",
+        client.local_proto, client.local_addr, client.local_port, client.local_path
+    )
+}
+
+/// Periodically probes the local target of an endpoint, stops on drop
+pub struct HealthMonitor {
+    handle: JoinHandle<()>,
+}
+
+impl HealthMonitor {
+    /// Start monitoring. Status changes are sent upstream via `report_tx`
+    /// if the server supports it and always to `result_tx`.
+    pub fn spawn(
+        endpoint: ServerEndpoint,
+        check: HealthCheck,
+        paused: PausedEndpoints,
+        registry: Arc<StatusRegistry>,
+        report_tx: Option<mpsc::Sender<Message>>,
+        result_tx: broadcast::Sender<Message>,
+    ) -> Self {
+        let handle = tokio::spawn(async move {
+            monitor(endpoint, check, paused, registry, report_tx, result_tx).await;
+        });
+        Self { handle }
+    }
+}
+
+impl Drop for HealthMonitor {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn monitor(
+    endpoint: ServerEndpoint,
+    check: HealthCheck,
+    paused: PausedEndpoints,
+    registry: Arc<StatusRegistry>,
+    report_tx: Option<mpsc::Sender<Message>>,
+    result_tx: broadcast::Sender<Message>,
+) {
+    let client = endpoint.client.clone().unwrap_or_default();
+    let interval = match check.interval_secs {
+        0 => DEFAULT_INTERVAL_SECS,
+        v => v,
+    };
+    let timeout = match check.timeout_secs {
+        0 => DEFAULT_TIMEOUT_SECS,
+        v => v,
+    };
+
+    let mut ticker = time::interval(Duration::from_secs(interval as u64));
+    let mut failures = 0;
+    let mut healthy = true;
+
+    loop {
+        ticker.tick().await;
+
+        let res = match time::timeout(Duration::from_secs(timeout as u64), probe(&check, &client))
+            .await
+        {
+            Ok(res) => res,
+            Err(_) => Err(anyhow::anyhow!("Health check timed out after {}s", timeout)),
+        };
+
+        match res {
+            Ok(()) => {
+                failures = 0;
+                if healthy {
+                    continue;
+                }
+                info!("Service {} is healthy again", endpoint.guid);
+                healthy = true;
+                paused.write().remove(&endpoint.guid);
+            }
+            Err(err) => {
+                failures += 1;
+                debug!(
+                    "Health check of {} failed ({}/{}): {:#}",
+                    endpoint.guid, failures, FAILURE_THRESHOLD, err
+                );
+                if !healthy || failures < FAILURE_THRESHOLD {
+                    continue;
+                }
+                warn!("Service {} is unhealthy: {:#}", endpoint.guid, err);
+                healthy = false;
+                if check.pause {
+                    paused.write().insert(endpoint.guid.clone());
+                }
+            }
+        }
+
+        registry.set_endpoint_status(
+            &endpoint.guid,
+            if healthy {
+                STATUS_ONLINE
+            } else {
+                STATUS_UNHEALTHY
+            },
+        );
+
+        let status = Message::EndpointStatus(EndpointStatus {
+            guid: endpoint.guid.clone(),
+            status: if healthy {
+                STATUS_ONLINE
+            } else {
+                STATUS_UNHEALTHY
+            }
+            .to_string(),
+        });
+        if let Some(tx) = report_tx.as_ref() {
+            if tx.send(status.clone()).await.is_err() {
+                // Control channel is gone
+                break;
+            }
+        }
+        result_tx.send(status).ok();
+    }
+}
+
+/// Run a single health check against the local target of the endpoint.
+/// Command checks must come from [`LocalChecks`].
+pub async fn probe(check: &HealthCheck, client: &ClientEndpoint) -> Result<()> {
+    match check.kind() {
+        HealthCheckKind::None => Ok(()),
+        HealthCheckKind::Command => probe_command(&check.command).await,
+        HealthCheckKind::Tcp => connect(client, |_| async { Ok(()) }).await,
+        HealthCheckKind::Http => {
+            if client.local_proto() == Protocol::Https {
+                // Plain HTTP can't be spoken to a TLS target, reachability is the best we can do
+                debug!("HTTP health check is not supported for HTTPS targets, using TCP");
+                return connect(client, |_| async { Ok(()) }).await;
+            }
+            let host = format_host_port(&client.local_addr, client.local_port as u16);
+            connect(client, |stream| probe_http(stream, host, check)).await
+        }
+    }
+}
+
+async fn connect<F, Fut>(client: &ClientEndpoint, f: F) -> Result<()>
+where
+    F: FnOnce(Box<dyn AsyncReadWrite>) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    if let Some(path) = unix_socket_path(&client.local_addr) {
+        #[cfg(unix)]
+        {
+            let stream = tokio::net::UnixStream::connect(path)
+                .await
+                .with_context(|| format!("Failed to connect to {}", client.local_addr))?;
+            return f(Box::new(stream)).await;
+        }
+        #[cfg(not(unix))]
+        bail!(crate::t!("error-unix-socket-platform", "path" => path));
+    }
+
+    let addrs = local_socket_addrs(&client.local_addr, client.local_port as u16).await?;
+    let stream = happy_eyeballs::connect(&addrs).await.with_context(|| {
+        format!(
+            "Failed to connect to {}",
+            format_host_port(&client.local_addr, client.local_port as u16)
+        )
+    })?;
+    f(Box::new(stream)).await
+}
+
+trait AsyncReadWrite: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncReadWrite for T {}
+
+async fn probe_http(
+    mut stream: Box<dyn AsyncReadWrite>,
+    host: String,
+    check: &HealthCheck,
+) -> Result<()> {
+    let path = if check.path.is_empty() {
+        "/"
+    } else {
+        check.path.as_str()
+    };
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: cloudpub-health\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status_line)
+        .await
+        .context("Failed to read HTTP response")?;
+
+    // HTTP/1.1 200 OK
+    let status: u32 = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .with_context(|| format!("Invalid HTTP response: {:?}", status_line.trim()))?;
+
+    let ok = match check.expected_status {
+        0 => (200..400).contains(&status),
+        expected => status == expected,
+    };
+    if !ok {
+        bail!("Unexpected HTTP status {} for {}", status, path);
+    }
+    Ok(())
+}
+
+async fn probe_command(command: &str) -> Result<()> {
+    if command.is_empty() {
+        bail!("Health check command is empty");
+    }
+
+    #[cfg(windows)]
+    let mut cmd = {
+        let mut cmd = tokio::process::Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    };
+    #[cfg(not(windows))]
+    let mut cmd = {
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    };
+
+    let status = cmd
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await
+        .with_context(|| format!("Failed to execute {}", command))?;
+
+    if !status.success() {
+        bail!("Command {} exited with {}", command, status);
+    }
+    Ok(())
+}
@@ -1,10 +1,25 @@
 use fluent::{FluentBundle, FluentResource};
 use fluent_bundle::FluentArgs;
+use std::env;
 use std::sync::Mutex;
+use tracing::warn;
 use unic_langid::LanguageIdentifier;
 
+// Both locales are embedded, the language is selected at runtime
+const LOCALES: [(&str, &str); 2] = [
+    ("en", include_str!("../locales/en.ftl")),
+    ("ru", include_str!("../locales/ru.ftl")),
+];
+
+// Language of the build, also decides the default server
+const BUILD_LOCALE: &str = env!("CLO_DEFAULT_LOCALE");
+
+// English has every key, so it ends every fallback chain
+const FALLBACK_LOCALE: &str = "en";
+
 pub struct Localizer {
-    bundle: Mutex<FluentBundle<FluentResource>>,
+    // Bundles in the lookup order, the selected language first
+    bundles: Mutex<Vec<FluentBundle<FluentResource>>>,
 }
 
 unsafe impl Sync for Localizer {}
@@ -18,50 +33,99 @@ impl Default for Localizer {
 
 impl Localizer {
     pub fn new() -> Self {
-        let locale_content = include_str!(concat!(env!("OUT_DIR"), "/locale.ftl"));
-        let resource = FluentResource::try_new(locale_content.to_string())
-            .expect("Failed to parse locale resource");
-
-        let langid: LanguageIdentifier = if cfg!(locale = "ru") {
-            "ru".parse().expect("Failed to parse language identifier")
-        } else {
-            "en".parse().expect("Failed to parse language identifier")
-        };
-
-        let mut bundle = FluentBundle::new(vec![langid]);
-        bundle.set_use_isolating(false); // Disable BiDi isolation
-        bundle
-            .add_resource(resource)
-            .expect("Failed to add resource to bundle");
-
+        let locale = detect_locale().unwrap_or_else(|| BUILD_LOCALE.to_string());
         Self {
-            bundle: Mutex::new(bundle),
+            bundles: Mutex::new(chain(&locale)),
         }
     }
 
+    /// Switch the language, unknown ones fall back to the build language
+    pub fn set_locale(&self, locale: &str) {
+        *self.bundles.lock().unwrap() = chain(locale);
+    }
+
     pub fn get(&self, key: &str) -> String {
         self.get_with_args(key, None)
     }
 
     pub fn get_with_args(&self, key: &str, args: Option<&FluentArgs>) -> String {
-        let bundle = self.bundle.lock().unwrap();
-        let msg = bundle
-            .get_message(key)
-            .unwrap_or_else(|| panic!("Message '{}' not found", key));
+        let bundles = self.bundles.lock().unwrap();
+        for bundle in bundles.iter() {
+            let Some(pattern) = bundle.get_message(key).and_then(|msg| msg.value()) else {
+                continue;
+            };
 
-        let pattern = msg
-            .value()
-            .unwrap_or_else(|| panic!("Message '{}' has no value", key));
+            let mut errors = vec![];
+            let formatted = bundle.format_pattern(pattern, args, &mut errors);
 
-        let mut errors = vec![];
-        let formatted = bundle.format_pattern(pattern, args, &mut errors);
+            if !errors.is_empty() {
+                eprintln!("Localization errors for key '{}': {:?}", key, errors);
+            }
 
-        if !errors.is_empty() {
-            eprintln!("Localization errors for key '{}': {:?}", key, errors);
+            return formatted.to_string();
         }
+        warn!("Message '{}' not found", key);
+        key.to_string()
+    }
+}
+
+/// Language of the build, independent of the selected one
+pub fn build_message(key: &str) -> String {
+    let bundle = bundle(BUILD_LOCALE).expect("Build locale is not embedded");
+    let pattern = bundle
+        .get_message(key)
+        .and_then(|msg| msg.value())
+        .unwrap_or_else(|| panic!("Message '{}' not found", key));
+    bundle
+        .format_pattern(pattern, None, &mut vec![])
+        .to_string()
+}
 
-        formatted.to_string()
+/// Language requested by the environment, like `ru_RU.UTF-8` in `LANG`
+pub fn detect_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| supported_locale(&value))
+}
+
+/// Supported language of a locale name, e.g. `ru` of `ru-RU` or `ru_RU.UTF-8`
+pub fn supported_locale(name: &str) -> Option<String> {
+    let lang = name
+        .split(['_', '-', '.', '@'])
+        .next()?
+        .to_ascii_lowercase();
+    LOCALES
+        .iter()
+        .any(|(locale, _)| *locale == lang)
+        .then_some(lang)
+}
+
+fn chain(locale: &str) -> Vec<FluentBundle<FluentResource>> {
+    let locale = supported_locale(locale).unwrap_or_else(|| BUILD_LOCALE.to_string());
+    let mut order: Vec<&str> = Vec::new();
+    for locale in [locale.as_str(), BUILD_LOCALE, FALLBACK_LOCALE] {
+        if !order.contains(&locale) {
+            order.push(locale);
+        }
     }
+    order.into_iter().filter_map(bundle).collect()
+}
+
+fn bundle(locale: &str) -> Option<FluentBundle<FluentResource>> {
+    let (_, content) = LOCALES.iter().find(|(l, _)| *l == locale)?;
+    let resource =
+        FluentResource::try_new(content.to_string()).expect("Failed to parse locale resource");
+
+    let langid: LanguageIdentifier = locale.parse().expect("Failed to parse language identifier");
+
+    let mut bundle = FluentBundle::new(vec![langid]);
+    bundle.set_use_isolating(false); // Disable BiDi isolation
+    bundle
+        .add_resource(resource)
+        .expect("Failed to add resource to bundle");
+    Some(bundle)
 }
 
 lazy_static::lazy_static! {
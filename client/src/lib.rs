@@ -1,12 +1,26 @@
-pub use {anyhow, clap, parking_lot, serde, tokio, tracing};
+pub use {anyhow, clap, common, parking_lot, serde, tokio, tracing};
 
+pub mod agent;
+pub mod auth;
 pub mod base;
 pub mod client;
 pub mod commands;
 pub mod config;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+pub mod doctor;
+pub mod error_page;
+pub mod exec;
+pub mod health;
 pub mod i18n;
+pub mod logs;
+pub mod p2p;
 pub mod ping;
 #[cfg(feature = "plugins")]
 pub mod plugins;
+pub mod secrets;
 pub mod service;
 pub mod shell;
+pub mod status;
+pub mod upgrade;
+pub mod visitor;
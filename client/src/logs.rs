@@ -0,0 +1,149 @@
+use crate::shell::{check_guid, get_cache_dir};
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use rolling_file::{BasicRollingFileAppender, RollingConditionBasic};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tokio::time::{self, Duration};
+use tracing::warn;
+
+pub const LOGS_SUBDIR: &str = "logs";
+
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+const MAX_LOG_FILES: usize = 2;
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Output of the supervised process of the endpoint
+pub fn process_log_path(guid: &str) -> Result<PathBuf> {
+    check_guid(guid)?;
+    Ok(get_cache_dir(LOGS_SUBDIR)?.join(format!("{}.log", guid)))
+}
+
+/// Apache access log of the endpoint
+pub fn access_log_path(guid: &str) -> Result<PathBuf> {
+    check_guid(guid)?;
+    Ok(get_cache_dir(LOGS_SUBDIR)?.join(format!("{}.access.log", guid)))
+}
+
+/// Apache error log of the endpoint
+pub fn error_log_path(guid: &str) -> Result<PathBuf> {
+    check_guid(guid)?;
+    Ok(get_cache_dir(LOGS_SUBDIR)?.join(format!("{}.error.log", guid)))
+}
+
+/// All existing log files of the endpoint
+pub fn endpoint_logs(guid: &str) -> Result<Vec<PathBuf>> {
+    Ok([
+        process_log_path(guid)?,
+        error_log_path(guid)?,
+        access_log_path(guid)?,
+    ]
+    .into_iter()
+    .filter(|p| p.exists())
+    .collect())
+}
+
+/// Rotating log file for the output of a supervised process
+pub struct ProcessLog {
+    appender: Mutex<BasicRollingFileAppender>,
+}
+
+impl ProcessLog {
+    pub fn new(path: &Path) -> Result<Self> {
+        let appender = BasicRollingFileAppender::new(
+            path,
+            RollingConditionBasic::new().max_size(MAX_LOG_SIZE),
+            MAX_LOG_FILES,
+        )
+        .with_context(|| format!("Failed to create log file {:?}", path))?;
+        Ok(Self {
+            appender: Mutex::new(appender),
+        })
+    }
+
+    pub fn write_line(&self, line: &str) {
+        let mut appender = self.appender.lock();
+        if let Err(err) = writeln!(appender, "{}", line).and_then(|_| appender.flush()) {
+            warn!("Failed to write process log: {}", err);
+        }
+    }
+}
+
+fn last_lines(path: &Path, count: usize) -> Result<(Vec<String>, u64)> {
+    let file = std::fs::File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let len = file.metadata()?.len();
+    let mut lines = VecDeque::with_capacity(count);
+    for line in BufReader::new(file).lines() {
+        let line = line.unwrap_or_default();
+        if lines.len() == count {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+    Ok((lines.into(), len))
+}
+
+fn read_from(path: &Path, offset: u64) -> Result<(String, u64)> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let len = file.metadata()?.len();
+    // The file was rotated, start over
+    let offset = if len < offset { 0 } else { offset };
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    // Leave an incomplete last line for the next read
+    let complete = buf.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+    buf.truncate(complete);
+    Ok((
+        String::from_utf8_lossy(&buf).to_string(),
+        offset + buf.len() as u64,
+    ))
+}
+
+/// Print the last `count` lines of the files and optionally keep printing new ones
+pub async fn tail<F: Fn(String)>(
+    paths: &[PathBuf],
+    count: usize,
+    follow: bool,
+    write: F,
+) -> Result<()> {
+    let header = |path: &Path| format!("==> {} <==", path.display());
+    let mut offsets = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        let (lines, len) = last_lines(path, count)?;
+        if paths.len() > 1 {
+            write(header(path));
+        }
+        for line in lines {
+            write(line);
+        }
+        offsets.push(len);
+    }
+
+    let mut last = None;
+    while follow {
+        time::sleep(FOLLOW_INTERVAL).await;
+        for (i, path) in paths.iter().enumerate() {
+            let (data, offset) = match read_from(path, offsets[i]) {
+                Ok(v) => v,
+                // Rotation in progress
+                Err(_) => continue,
+            };
+            offsets[i] = offset;
+            if data.is_empty() {
+                continue;
+            }
+            if paths.len() > 1 && last != Some(i) {
+                write(header(path));
+            }
+            last = Some(i);
+            for line in data.lines() {
+                write(line.to_string());
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,556 @@
+//! Direct connections between agents.
+//!
+//! The server relays the offer of the visitor and the answer of the publishing
+//! agent, and reflects the public UDP address of each side. Both agents then
+//! punch a hole through their NATs and run QUIC over the same socket. The
+//! publisher presents a self-signed certificate pinned by the visitor through
+//! the answer, every stream starts with the session secret of the offer.
+//! The socket is dual-stack, so peers are reached over IPv4 and IPv6.
+
+use crate::client::run_data_channel_for_tcp;
+use crate::status::StatusRegistry;
+use anyhow::{bail, Context, Result};
+use common::protocol::message::Message;
+use common::protocol::{Compression, P2pAnswer, P2pOffer, ServerEndpoint};
+use parking_lot::RwLock;
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{Connection, Endpoint, EndpointConfig, RecvStream, SendStream, TokioRuntime};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{CertificateError, DigitallySignedStruct, SignatureScheme};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt, Join};
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, info, warn};
+
+const MAGIC_REFLECT: &[u8] = b"CLOR";
+const MAGIC_PUNCH: &[u8] = b"CLOP";
+// Name in the self-signed certificate, the certificate itself is pinned
+const SERVER_NAME: &str = "cloudpub-p2p";
+
+const REFLECT_ATTEMPTS: usize = 3;
+const REFLECT_TIMEOUT: Duration = Duration::from_millis(500);
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(10);
+const PUNCH_INTERVAL: Duration = Duration::from_millis(100);
+const PUNCH_TIMEOUT: Duration = Duration::from_secs(5);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+// Attempts to restore a closed direct connection before giving up on it
+const RETRY_ATTEMPTS: usize = 3;
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Stream over a direct connection
+pub type P2pStream = Join<RecvStream, SendStream>;
+
+/// Direct connection of a visitor to the publishing agent, if established
+pub struct P2pLink {
+    session: String,
+    connection: RwLock<Option<Connection>>,
+}
+
+impl P2pLink {
+    pub fn new(session: String) -> Arc<Self> {
+        Arc::new(Self {
+            session,
+            connection: Default::default(),
+        })
+    }
+
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    /// Open a stream to the private endpoint, `None` if there is no direct
+    /// connection and the relay should be used
+    pub async fn open(&self) -> Option<Result<P2pStream>> {
+        let connection = self.connection.read().clone()?;
+        Some(open_stream(&connection, &self.session).await)
+    }
+}
+
+/// Background task of a direct connection attempt
+pub struct P2pTask {
+    handle: JoinHandle<()>,
+}
+
+impl P2pTask {
+    pub fn spawn<F>(fut: F) -> Self
+    where
+        F: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        let handle = tokio::spawn(async move {
+            if let Err(err) = fut.await {
+                warn!("{:#}. Using relay", err);
+            }
+        });
+        Self { handle }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+impl Drop for P2pTask {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Visitor side: offer a direct connection to the publisher of `guid` and
+/// keep it in `link` while it's alive. A closed connection is established
+/// again, the relay is used in between.
+pub async fn initiate(
+    guid: String,
+    rendezvous: String,
+    link: Arc<P2pLink>,
+    offer_tx: mpsc::Sender<Message>,
+    mut answer_rx: mpsc::Receiver<P2pAnswer>,
+) -> Result<()> {
+    // The first attempt tells whether direct connections work at all
+    let (mut _quic, mut connection) =
+        connect(&guid, &rendezvous, &link, &offer_tx, &mut answer_rx).await?;
+    loop {
+        *link.connection.write() = Some(connection.clone());
+        let reason = connection.closed().await;
+        link.connection.write().take();
+        warn!("Direct connection closed: {}. Using relay", reason);
+
+        let mut attempt = 0;
+        (_quic, connection) = loop {
+            time::sleep(RETRY_INTERVAL).await;
+            attempt += 1;
+            debug!("Restoring direct connection, attempt {}", attempt);
+            match connect(&guid, &rendezvous, &link, &offer_tx, &mut answer_rx).await {
+                Ok(res) => break res,
+                Err(err) if attempt >= RETRY_ATTEMPTS => return Err(err),
+                Err(err) => warn!("{:#}", err),
+            }
+        };
+    }
+}
+
+async fn connect(
+    guid: &str,
+    rendezvous: &str,
+    link: &P2pLink,
+    offer_tx: &mpsc::Sender<Message>,
+    answer_rx: &mut mpsc::Receiver<P2pAnswer>,
+) -> Result<(Endpoint, Connection)> {
+    let (socket, candidates) = gather(rendezvous, &link.session).await?;
+
+    // Late answer of a timed out attempt
+    while answer_rx.try_recv().is_ok() {}
+
+    let offer = Message::P2pOffer(P2pOffer {
+        session: link.session.clone(),
+        guid: guid.to_string(),
+        candidates: candidates.iter().map(|a| a.to_string()).collect(),
+    });
+    offer_tx
+        .send(offer)
+        .await
+        .context("Failed to send direct connection offer")?;
+
+    let answer = time::timeout(ANSWER_TIMEOUT, answer_rx.recv())
+        .await
+        .context(crate::t!("p2p-no-answer"))?
+        .context(crate::t!("p2p-no-answer"))?;
+    if answer.certificate.is_empty() {
+        bail!(crate::t!("p2p-declined"));
+    }
+
+    let peer = punch(
+        &socket,
+        &link.session,
+        &parse_candidates(&answer.candidates),
+    )
+    .await?;
+    debug!("Hole punched to {}", peer);
+
+    let quic = Endpoint::new(
+        EndpointConfig::default(),
+        None,
+        socket.into_std()?,
+        Arc::new(TokioRuntime),
+    )
+    .context("Failed to create QUIC endpoint")?;
+    let connection = quic
+        .connect_with(client_config(&answer.certificate)?, peer, SERVER_NAME)?
+        .await
+        .context("Failed to establish direct connection")?;
+
+    info!("Direct connection to {} established", peer);
+    Ok((quic, connection))
+}
+
+/// Publisher side: answer the offer for `endpoint` and serve the streams of
+/// the visitor. An empty `rendezvous` declines the offer.
+pub async fn respond(
+    offer: P2pOffer,
+    endpoint: Option<ServerEndpoint>,
+    rendezvous: String,
+    registry: Arc<StatusRegistry>,
+    answer_tx: mpsc::Sender<Message>,
+) -> Result<()> {
+    // Only private endpoints are reachable by visitors
+    let endpoint = match endpoint {
+        Some(endpoint)
+            if !rendezvous.is_empty() && endpoint.client.as_ref().is_some_and(|c| c.private) =>
+        {
+            endpoint
+        }
+        _ => {
+            debug!("Declining direct connection to {}", offer.guid);
+            let answer = Message::P2pAnswer(P2pAnswer {
+                session: offer.session,
+                ..Default::default()
+            });
+            answer_tx.send(answer).await.ok();
+            return Ok(());
+        }
+    };
+
+    let (socket, candidates) = gather(&rendezvous, &offer.session).await?;
+    let cert = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])
+        .context("Failed to generate certificate")?;
+    let certificate = cert.cert.der().clone();
+    let key = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+
+    let answer = Message::P2pAnswer(P2pAnswer {
+        session: offer.session.clone(),
+        candidates: candidates.iter().map(|a| a.to_string()).collect(),
+        certificate: certificate.to_vec(),
+    });
+    answer_tx
+        .send(answer)
+        .await
+        .context("Failed to send direct connection answer")?;
+
+    let peer = punch(
+        &socket,
+        &offer.session,
+        &parse_candidates(&offer.candidates),
+    )
+    .await?;
+    debug!("Hole punched to {}", peer);
+
+    let mut server_config = quinn::ServerConfig::with_single_cert(vec![certificate], key.into())
+        .context("Failed to create QUIC server config")?;
+    server_config.transport_config(transport_config());
+    let quic = Endpoint::new(
+        EndpointConfig::default(),
+        Some(server_config),
+        socket.into_std()?,
+        Arc::new(TokioRuntime),
+    )
+    .context("Failed to create QUIC endpoint")?;
+
+    let incoming = time::timeout(PUNCH_TIMEOUT, quic.accept())
+        .await
+        .context(crate::t!("p2p-no-connection"))?
+        .context(crate::t!("p2p-no-connection"))?;
+    let connection = incoming
+        .await
+        .context("Failed to accept direct connection")?;
+    // Nobody else is expected on this socket
+    quic.set_server_config(None);
+
+    info!("Direct connection from {} accepted", peer);
+    let endpoint = Arc::new(endpoint);
+    loop {
+        let (send, recv) = match connection.accept_bi().await {
+            Ok(stream) => stream,
+            Err(err) => {
+                info!("Direct connection from {} closed: {}", peer, err);
+                return Ok(());
+            }
+        };
+        let endpoint = endpoint.clone();
+        let session = offer.session.clone();
+        let connection = connection.clone();
+        let registry = registry.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve_stream(send, recv, &endpoint, &session, peer, &registry).await {
+                warn!("{:#}", err);
+                connection.close(0u32.into(), b"");
+            }
+        });
+    }
+}
+
+async fn serve_stream(
+    send: SendStream,
+    mut recv: RecvStream,
+    endpoint: &ServerEndpoint,
+    session: &str,
+    peer: SocketAddr,
+    registry: &StatusRegistry,
+) -> Result<()> {
+    let len = recv.read_u8().await? as usize;
+    let mut secret = vec![0; len];
+    recv.read_exact(&mut secret).await?;
+    if secret != session.as_bytes() {
+        bail!("Direct connection from {} is not authorized", peer);
+    }
+
+    let stats = registry.channel_stats(&endpoint.guid);
+    let _active = stats.open(&peer.to_string());
+    // QUIC is encrypted and compressed streams would not pay off on a direct path
+    run_data_channel_for_tcp(
+        io::join(recv, send),
+        endpoint,
+        Compression::None,
+        stats.clone(),
+    )
+    .await
+}
+
+async fn open_stream(connection: &Connection, session: &str) -> Result<P2pStream> {
+    let (mut send, recv) = connection
+        .open_bi()
+        .await
+        .context("Failed to open direct stream")?;
+    send.write_u8(session.len() as u8).await?;
+    send.write_all(session.as_bytes()).await?;
+    Ok(io::join(recv, send))
+}
+
+/// Bind a UDP socket and collect the addresses the peer may reach it at:
+/// the public one reflected by the rendezvous and the local ones
+async fn gather(rendezvous: &str, session: &str) -> Result<(UdpSocket, Vec<SocketAddr>)> {
+    let socket = bind()?;
+    let dual_stack = socket.local_addr()?.is_ipv6();
+    let port = socket.local_addr()?.port();
+
+    let server = lookup_host(rendezvous)
+        .await
+        .with_context(|| format!("Failed to resolve rendezvous {}", rendezvous))?
+        .find(|a| dual_stack || a.is_ipv4())
+        .with_context(|| format!("No usable address of rendezvous {}", rendezvous))?;
+
+    let mut candidates = Vec::new();
+    match reflect_addr(&socket, server, session).await {
+        Ok(addr) => candidates.push(addr),
+        Err(err) => warn!("{:#}", err),
+    }
+
+    // The interface routed to the rendezvous, agents may share a network
+    let unspecified: IpAddr = match server {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    if let Ok(probe) = UdpSocket::bind((unspecified, 0)).await {
+        if probe.connect(server).await.is_ok() {
+            if let Ok(local) = probe.local_addr() {
+                candidates.push(SocketAddr::new(local.ip(), port));
+            }
+        }
+    }
+    candidates.push(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port));
+    if dual_stack {
+        candidates.push(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), port));
+    }
+
+    let mut unique = Vec::new();
+    for addr in candidates {
+        let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+        if !addr.ip().is_unspecified() && !unique.contains(&addr) {
+            unique.push(addr);
+        }
+    }
+    debug!("Direct connection candidates: {:?}", unique);
+    Ok((socket, unique))
+}
+
+// Dual-stack socket to reach peers of both families, IPv4 if the host has no IPv6
+fn bind() -> Result<UdpSocket> {
+    let dual_stack = || -> io::Result<Socket> {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        // Off by default on Windows only
+        socket.set_only_v6(false)?;
+        socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)).into())?;
+        Ok(socket)
+    };
+    let socket = match dual_stack() {
+        Ok(socket) => socket,
+        Err(err) => {
+            debug!("No IPv6 for direct connections: {}", err);
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)).into())?;
+            socket
+        }
+    };
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+// Address of the peer as seen by the socket: dual-stack sockets reach IPv4
+// peers at mapped addresses, IPv4 sockets can't reach IPv6 ones
+fn peer_addr(socket: &UdpSocket, addr: SocketAddr) -> Option<SocketAddr> {
+    let dual_stack = socket.local_addr().ok()?.is_ipv6();
+    match addr {
+        SocketAddr::V4(v4) if dual_stack => {
+            Some(SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port()))
+        }
+        SocketAddr::V6(_) if !dual_stack => None,
+        addr => Some(addr),
+    }
+}
+
+async fn reflect_addr(socket: &UdpSocket, server: SocketAddr, session: &str) -> Result<SocketAddr> {
+    let server = peer_addr(socket, server).context("Rendezvous is not reachable")?;
+    let request = [MAGIC_REFLECT, session.as_bytes()].concat();
+    let mut buf = [0u8; 128];
+    for _ in 0..REFLECT_ATTEMPTS {
+        socket.send_to(&request, server).await?;
+        let Ok(res) = time::timeout(REFLECT_TIMEOUT, socket.recv_from(&mut buf)).await else {
+            continue;
+        };
+        let (len, from) = res?;
+        if from != server || !buf[..len].starts_with(MAGIC_REFLECT) {
+            continue;
+        }
+        let addr = std::str::from_utf8(&buf[MAGIC_REFLECT.len()..len])?;
+        return addr.parse().context("Invalid reflected address");
+    }
+    bail!(crate::t!("p2p-no-reflection", "rendezvous" => server.to_string()))
+}
+
+/// Exchange probes with every candidate of the peer until one of them answers
+async fn punch(socket: &UdpSocket, session: &str, candidates: &[SocketAddr]) -> Result<SocketAddr> {
+    let candidates: Vec<_> = candidates
+        .iter()
+        .filter_map(|addr| peer_addr(socket, *addr))
+        .collect();
+    if candidates.is_empty() {
+        bail!(crate::t!("p2p-punch-failed"));
+    }
+    let probe = [MAGIC_PUNCH, session.as_bytes()].concat();
+    let deadline = Instant::now() + PUNCH_TIMEOUT;
+    let mut interval = time::interval(PUNCH_INTERVAL);
+    let mut buf = [0u8; 128];
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                for addr in &candidates {
+                    socket.send_to(&probe, addr).await.ok();
+                }
+            }
+            res = socket.recv_from(&mut buf) => {
+                let (len, from) = res?;
+                if buf[..len] == probe[..] {
+                    // The peer may still be waiting for a probe through the hole
+                    socket.send_to(&probe, from).await.ok();
+                    return Ok(from);
+                }
+            }
+            _ = time::sleep_until(deadline) => bail!(crate::t!("p2p-punch-failed")),
+        }
+    }
+}
+
+fn parse_candidates(candidates: &[String]) -> Vec<SocketAddr> {
+    candidates.iter().filter_map(|a| a.parse().ok()).collect()
+}
+
+/// UDP address reflector of the server rendezvous
+pub async fn reflect(socket: UdpSocket) -> Result<()> {
+    let mut buf = [0u8; 128];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf).await?;
+        if buf[..len].starts_with(MAGIC_REFLECT) {
+            debug!("Reflecting {}", from);
+            let reply = [MAGIC_REFLECT, from.to_string().as_bytes()].concat();
+            socket.send_to(&reply, from).await.ok();
+        }
+    }
+}
+
+fn transport_config() -> Arc<quinn::TransportConfig> {
+    let mut config = quinn::TransportConfig::default();
+    config.keep_alive_interval(Some(KEEPALIVE_INTERVAL));
+    config.max_idle_timeout(IDLE_TIMEOUT.try_into().ok());
+    Arc::new(config)
+}
+
+fn client_config(certificate: &[u8]) -> Result<quinn::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+            certificate: certificate.to_vec(),
+            provider,
+        }))
+        .with_no_client_auth();
+    let mut config = quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(crypto)?));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+// Accepts only the certificate received in the answer
+#[derive(Debug)]
+struct PinnedVerifier {
+    certificate: Vec<u8>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.certificate.as_slice() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
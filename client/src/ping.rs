@@ -1,13 +1,43 @@
+use crate::commands::PingArg;
+use crate::config::ClientConfig;
 use anyhow::{Context, Result};
+use common::config::TransportType;
 use common::protocol::message::Message;
-use common::protocol::{ClientEndpoint, ServerEndpoint};
-use common::utils::find_free_tcp_port;
+use common::protocol::{ClientEndpoint, Protocol, ServerEndpoint};
+use common::transport::rustls::peer_certificates;
+use common::transport::{tcp_connect_with_proxy, AddrMaybeCached, Transport, WebsocketTransport};
+use common::utils::{find_free_tcp_port, find_free_udp_port, host_port_pair, udp_connect};
+use futures::future::try_join_all;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::broadcast;
-use tokio::time::sleep;
-use tracing::{debug, error, info};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{broadcast, oneshot};
+use tokio::time::{sleep, timeout, timeout_at};
+use tracing::{debug, error, info, warn};
+
+// First byte of a ponger connection selects the test
+const MODE_ECHO: u8 = b'E';
+const MODE_UPLOAD: u8 = b'U';
+const MODE_DOWNLOAD: u8 = b'D';
+
+const CHUNK_SIZE: usize = 64 * 1024;
+// UDP datagrams start with the sequence number
+const UDP_SEQ_SIZE: usize = 8;
+const UDP_WARM_UP_SEQ: u64 = u64::MAX;
+const UDP_WARM_UP_TIMEOUT: Duration = Duration::from_secs(5);
+const UDP_REPLY_TIMEOUT: Duration = Duration::from_millis(200);
+// Wait for late replies before counting losses
+const UDP_LINGER: Duration = Duration::from_secs(1);
+const THROUGHPUT_GRACE: Duration = Duration::from_secs(30);
+const HISTOGRAM_BOUNDS_US: [u64; 14] = [
+    100, 200, 500, 1_000, 2_000, 5_000, 10_000, 20_000, 50_000, 100_000, 200_000, 500_000,
+    1_000_000, 2_000_000,
+];
+const HISTOGRAM_WIDTH: u64 = 40;
 
 #[derive(Debug, Clone)]
 pub struct Settings {
@@ -17,6 +47,68 @@ pub struct Settings {
     pub sleep_time: u64,
 }
 
+impl From<&PingArg> for Settings {
+    fn from(args: &PingArg) -> Self {
+        Settings {
+            warm_up_count: 10,
+            msg_count: args.count as u64,
+            msg_size: args.size as u64,
+            // Pace datagrams, nothing retransmits them
+            sleep_time: if args.udp { 10 } else { 0 },
+        }
+    }
+}
+
+/// Time spent in each step of connecting to the server
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Handshake {
+    pub connect_ms: f64,
+    pub tls_ms: Option<f64>,
+    pub websocket_ms: Option<f64>,
+    /// First round trip of a visitor, including the data channel setup
+    pub data_channel_ms: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct RttStats {
+    pub min_us: u64,
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+    /// Mean difference between consecutive round trips
+    pub jitter_us: u64,
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct HistogramBucket {
+    /// Upper bound of the bucket, `None` for the last one
+    pub le_us: Option<u64>,
+    pub count: u64,
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Throughput {
+    pub streams: u32,
+    pub duration_secs: u64,
+    pub upload_bps: f64,
+    pub download_bps: f64,
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct PingReport {
+    pub protocol: String,
+    pub msg_size: u64,
+    pub sent: u64,
+    pub received: u64,
+    pub loss_percent: f64,
+    pub handshake: Option<Handshake>,
+    pub rtt: Option<RttStats>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub histogram: Vec<HistogramBucket>,
+    pub throughput: Option<Throughput>,
+}
+
 pub async fn start(port: u16) -> Result<broadcast::Sender<()>> {
     // Create a channel for stop signal
     let (stop_tx, _) = broadcast::channel(1);
@@ -63,95 +155,239 @@ pub async fn start(port: u16) -> Result<broadcast::Sender<()>> {
     Ok(stop_tx)
 }
 
-pub async fn publish(command_tx: broadcast::Sender<Message>) -> Result<()> {
-    let port = find_free_tcp_port()
+pub async fn start_udp(port: u16) -> Result<broadcast::Sender<()>> {
+    let (stop_tx, _) = broadcast::channel(1);
+    let mut stop_rx = stop_tx.subscribe();
+
+    let udp_addr = format!("127.0.0.1:{}", port);
+    info!("Starting UDP ponger on {}", udp_addr);
+    let socket = UdpSocket::bind(&udp_addr)
         .await
-        .context("Failed to find free TCP port")?;
-    // Create TCP publish args
-    let client = ClientEndpoint {
-        local_proto: common::protocol::Protocol::Tcp.into(),
-        local_addr: "localhost".to_string(),
-        local_port: port as u32,
-        description: Some("TCP Ponger".to_string()),
-        ..Default::default()
+        .context("Failed to bind UDP ponger")?;
+
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 65536];
+        loop {
+            tokio::select! {
+                _ = stop_rx.recv() => {
+                    info!("UDP ponger received stop signal");
+                    break;
+                }
+                res = socket.recv_from(&mut buf) => {
+                    match res {
+                        Ok((n, from)) => {
+                            if let Err(e) = socket.send_to(&buf[..n], from).await {
+                                error!("Error writing to UDP socket: {}", e);
+                            }
+                        }
+                        Err(e) => {
+                            error!("Error reading from UDP socket: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(stop_tx)
+}
+
+pub async fn publish(command_tx: broadcast::Sender<Message>, udp: bool) -> Result<()> {
+    let client = if udp {
+        let port = find_free_udp_port()
+            .await
+            .context("Failed to find free UDP port")?;
+        info!("Publishing UDP service on port {}", port);
+        let mut client = ClientEndpoint::default();
+        client.local_proto = Protocol::Udp.into();
+        client.local_addr = "127.0.0.1".to_string();
+        client.local_port = port as u32;
+        client.description = Some("UDP Ponger".to_string());
+        client
+    } else {
+        let port = find_free_tcp_port()
+            .await
+            .context("Failed to find free TCP port")?;
+        info!("Publishing TCP service on port {}", port);
+        let mut client = ClientEndpoint::default();
+        client.local_proto = Protocol::Tcp.into();
+        client.local_addr = "localhost".to_string();
+        client.local_port = port as u32;
+        client.description = Some("TCP Ponger".to_string());
+        client
     };
 
-    info!("Publishing TCP service on port {}", port);
     command_tx.send(Message::EndpointStart(client))?;
     Ok(())
 }
 
-pub async fn ping_test(endpoint: ServerEndpoint, bare: bool) -> Result<String> {
+pub async fn ping_test(
+    endpoint: ServerEndpoint,
+    args: &PingArg,
+    config: &ClientConfig,
+) -> Result<PingReport> {
     info!("Running ping test on {}", endpoint);
     let addr = format!("{}:{}", endpoint.remote_addr, endpoint.remote_port);
+    let client = endpoint
+        .client
+        .as_ref()
+        .context("Missing client endpoint")?;
+    let local_port = client.local_port as u16;
+    let settings = Settings::from(args);
+
+    let mut report = PingReport {
+        protocol: client.local_proto().to_string(),
+        msg_size: settings.msg_size,
+        ..Default::default()
+    };
 
-    let _stop_tx = start(endpoint.client.as_ref().unwrap().local_port as u16)
+    let mut handshake = measure_handshake(config)
         .await
-        .context("Failed to start ping service")?;
+        .map_err(|err| warn!("Failed to measure handshake: {:#}", err))
+        .unwrap_or_default();
+
+    let (times, first) = if client.local_proto() == Protocol::Udp {
+        let _stop_tx = start_udp(local_port)
+            .await
+            .context("Failed to start ping service")?;
+        ping_udp(&addr, &settings).await?
+    } else {
+        let _stop_tx = start(local_port)
+            .await
+            .context("Failed to start ping service")?;
+
+        // Wait for the server to start
+        sleep(Duration::from_millis(100)).await;
 
-    // Wait for the server to start
-    sleep(Duration::from_millis(100)).await;
+        let client = TcpStream::connect(&addr)
+            .await
+            .context(format!("Failed to connect to {}", addr))?;
+        let (times, first) = ping_tcp(client, &settings).await;
 
-    let settings = Settings {
-        warm_up_count: 10,
-        msg_count: 100,
-        msg_size: 48,
-        sleep_time: 0,
+        if args.throughput {
+            let duration = Duration::from_secs(args.duration);
+            report.throughput = Some(measure_throughput(&addr, args.streams, duration).await?);
+        }
+        (times, first)
     };
 
-    let client = TcpStream::connect(&addr)
-        .await
-        .context(format!("Failed to connect to {}", addr))?;
-    let mut times = ping_tcp(client, &settings).await;
+    handshake.data_channel_ms = first.map(millis);
+    report.handshake = Some(handshake);
+    report.sent = settings.msg_count;
+    report.received = times.iter().flatten().count() as u64;
+    if report.sent > 0 {
+        report.loss_percent = (report.sent - report.received) as f64 * 100.0 / report.sent as f64;
+    }
 
-    if times.is_empty() {
-        return Ok(crate::t!("error-measurement"));
+    let mut times: Vec<u64> = times.into_iter().flatten().collect();
+    if !times.is_empty() {
+        let jitter = jitter(&times);
+        times.sort();
+        if args.histogram {
+            report.histogram = histogram(&times);
+        }
+        report.rtt = Some(rtt_stats(&times, jitter));
     }
 
-    times.sort();
+    Ok(report)
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+// Repeat the steps of the control channel connection one by one
+async fn measure_handshake(config: &ClientConfig) -> Result<Handshake> {
+    let transport = &config.transport;
+    let host = config.server.host_str().context("Failed to get host")?;
+    let host_and_port = format!("{}:{}", host, config.server.port().unwrap_or(443));
+
+    let mut addr = AddrMaybeCached::new(&host_and_port);
+    addr.resolve_with_hosts(&transport.tcp.hosts).await?;
 
-    if bare {
-        let p50 = times.len() as f64 * 0.5;
-        Ok(format!("{}", times[p50 as usize] / 1_000))
+    let started = Instant::now();
+    let conn = tcp_connect_with_proxy(&addr, transport.tcp.proxy.as_ref()).await?;
+    let connect = started.elapsed();
+
+    let tls = if transport.uses_tls() {
+        let tls_config = transport.tls.clone().unwrap_or_default();
+        let host_name = match tls_config.hostname.clone() {
+            Some(name) => name,
+            None => host_port_pair(&host_and_port)?.0.to_string(),
+        };
+        let started = Instant::now();
+        peer_certificates(conn, &tls_config, &host_name).await?;
+        Some(started.elapsed())
     } else {
-        Ok(format_stats(times))
-    }
+        None
+    };
+
+    let websocket = if transport.transport_type == TransportType::Websocket {
+        let ws = WebsocketTransport::new(transport)?;
+        let started = Instant::now();
+        ws.connect(&addr).await?;
+        Some(
+            started
+                .elapsed()
+                .saturating_sub(connect + tls.unwrap_or_default()),
+        )
+    } else {
+        None
+    };
+
+    Ok(Handshake {
+        connect_ms: millis(connect),
+        tls_ms: tls.map(millis),
+        websocket_ms: websocket.map(millis),
+        data_channel_ms: None,
+    })
 }
 
-// TCP implementation
-async fn ping_tcp(mut client: TcpStream, settings: &Settings) -> Vec<u32> {
-    let msg_string = "x".to_string().repeat(settings.msg_size as usize);
-    let msg: &[u8] = msg_string.as_bytes();
-    let mut recv_buf: [u8; 65000] = [0; 65000];
+// TCP implementation, returns round trips in nanoseconds and the first one
+async fn ping_tcp(
+    mut client: TcpStream,
+    settings: &Settings,
+) -> (Vec<Option<u64>>, Option<Duration>) {
+    let msg = vec![b'x'; settings.msg_size as usize];
+    let mut recv_buf = vec![0u8; msg.len()];
 
     let mut times = Vec::with_capacity(settings.msg_count as usize);
 
+    if let Err(e) = client.write_all(&[MODE_ECHO]).await {
+        error!("Sending ping failed: {}", e);
+        return (times, None);
+    }
+
+    // The first round trip waits for the data channel
+    let start = Instant::now();
+    if send_single_ping_tcp(&mut client, &msg, &mut recv_buf).await != msg.len() {
+        return (times, None);
+    }
+    let first = start.elapsed();
+
     // Warm-up phase
-    for _ in 0..settings.warm_up_count {
-        send_single_ping_tcp(&mut client, msg, &mut recv_buf).await;
+    for _ in 1..settings.warm_up_count {
+        send_single_ping_tcp(&mut client, &msg, &mut recv_buf).await;
     }
 
     // Measurement phase
     for _ in 0..settings.msg_count {
         let start = Instant::now();
-        let bytes_read = send_single_ping_tcp(&mut client, msg, &mut recv_buf).await;
+        let bytes_read = send_single_ping_tcp(&mut client, &msg, &mut recv_buf).await;
         let end = Instant::now();
 
-        if bytes_read == 0 {
-            return times;
-        }
-
         if bytes_read != msg.len() {
-            return times;
+            break;
         }
 
-        let duration = end.duration_since(start).subsec_nanos();
-        times.push(duration);
+        // as_nanos doesn't wrap for round trips over a second
+        times.push(Some(end.duration_since(start).as_nanos() as u64));
 
         sleep(Duration::from_millis(settings.sleep_time)).await;
     }
 
-    times
+    (times, Some(first))
 }
 
 async fn send_single_ping_tcp(client: &mut TcpStream, msg: &[u8], recv_buf: &mut [u8]) -> usize {
@@ -176,51 +412,342 @@ async fn send_single_ping_tcp(client: &mut TcpStream, msg: &[u8], recv_buf: &mut
     bytes_read
 }
 
+// UDP implementation, lost datagrams are `None`
+async fn ping_udp(addr: &str, settings: &Settings) -> Result<(Vec<Option<u64>>, Option<Duration>)> {
+    let socket = Arc::new(udp_connect(addr).await?);
+    let mut msg = vec![b'x'; (settings.msg_size as usize).max(UDP_SEQ_SIZE)];
+    let mut buf = vec![0u8; 65536];
+
+    // The first datagram makes the server open the data channel
+    let start = Instant::now();
+    msg[..UDP_SEQ_SIZE].copy_from_slice(&UDP_WARM_UP_SEQ.to_be_bytes());
+    let first = loop {
+        if start.elapsed() > UDP_WARM_UP_TIMEOUT {
+            return Ok((Vec::new(), None));
+        }
+        socket.send(&msg).await?;
+        if let Ok(Ok(_)) = timeout(UDP_REPLY_TIMEOUT, socket.recv(&mut buf)).await {
+            break start.elapsed();
+        }
+    };
+
+    let (stop_tx, mut stop_rx) = oneshot::channel::<()>();
+    let receiver = tokio::spawn({
+        let socket = socket.clone();
+        async move {
+            let mut received = HashMap::new();
+            loop {
+                tokio::select! {
+                    _ = &mut stop_rx => break,
+                    res = socket.recv(&mut buf) => match res {
+                        Ok(n) if n >= UDP_SEQ_SIZE => {
+                            let seq = u64::from_be_bytes(buf[..UDP_SEQ_SIZE].try_into().unwrap());
+                            received.entry(seq).or_insert_with(Instant::now);
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            error!("Error reading from UDP socket: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            received
+        }
+    });
+
+    let mut sent = Vec::with_capacity(settings.msg_count as usize);
+    for seq in 0..settings.msg_count {
+        msg[..UDP_SEQ_SIZE].copy_from_slice(&seq.to_be_bytes());
+        sent.push(Instant::now());
+        socket.send(&msg).await?;
+        sleep(Duration::from_millis(settings.sleep_time)).await;
+    }
+
+    sleep(UDP_LINGER).await;
+    stop_tx.send(()).ok();
+    let received = receiver.await.context("UDP receiver failed")?;
+
+    let times = sent
+        .iter()
+        .enumerate()
+        .map(|(seq, sent)| {
+            received
+                .get(&(seq as u64))
+                .map(|recv| recv.duration_since(*sent).as_nanos() as u64)
+        })
+        .collect();
+    Ok((times, Some(first)))
+}
+
+async fn measure_throughput(addr: &str, streams: u32, duration: Duration) -> Result<Throughput> {
+    let limit = duration + THROUGHPUT_GRACE;
+
+    let uploaded: u64 = timeout(
+        limit,
+        try_join_all((0..streams).map(|_| upload_stream(addr, duration))),
+    )
+    .await
+    .context("Upload test timed out")??
+    .into_iter()
+    .sum();
+
+    let downloaded: u64 = timeout(
+        limit,
+        try_join_all((0..streams).map(|_| download_stream(addr, duration))),
+    )
+    .await
+    .context("Download test timed out")??
+    .into_iter()
+    .sum();
+
+    let secs = duration.as_secs_f64().max(f64::EPSILON);
+    Ok(Throughput {
+        streams,
+        duration_secs: duration.as_secs(),
+        upload_bps: uploaded as f64 * 8.0 / secs,
+        download_bps: downloaded as f64 * 8.0 / secs,
+    })
+}
+
+// Send until the ponger reports how much it received during the test
+async fn upload_stream(addr: &str, duration: Duration) -> Result<u64> {
+    let mut conn = TcpStream::connect(addr).await?;
+    conn.write_all(&[MODE_UPLOAD]).await?;
+    conn.write_u64(duration.as_millis() as u64).await?;
+    let (mut reader, mut writer) = conn.split();
+    let chunk = vec![0u8; CHUNK_SIZE];
+    // Reading the counter is not cancel safe, keep the same future
+    let received = reader.read_u64();
+    tokio::pin!(received);
+    loop {
+        tokio::select! {
+            received = &mut received => return Ok(received?),
+            res = writer.write_all(&chunk) => res?,
+        }
+    }
+}
+
+async fn download_stream(addr: &str, duration: Duration) -> Result<u64> {
+    let mut conn = TcpStream::connect(addr).await?;
+    conn.write_all(&[MODE_DOWNLOAD]).await?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut received = 0;
+    let deadline = tokio::time::Instant::now() + duration;
+    while let Ok(res) = timeout_at(deadline, conn.read(&mut buf)).await {
+        match res? {
+            0 => break,
+            n => received += n as u64,
+        }
+    }
+    Ok(received)
+}
+
 async fn pong_tcp(mut sock: TcpStream) {
+    let res = match sock.read_u8().await {
+        Ok(MODE_ECHO) => pong_echo(&mut sock).await,
+        Ok(MODE_UPLOAD) => pong_upload(&mut sock).await,
+        Ok(MODE_DOWNLOAD) => pong_download(&mut sock).await,
+        Ok(mode) => {
+            error!("Unknown ping mode: {}", mode);
+            return;
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        debug!("Ponger connection closed: {}", e);
+    }
+}
+
+async fn pong_echo(sock: &mut TcpStream) -> std::io::Result<()> {
     let mut buf: [u8; 65000] = [0; 65000];
 
     loop {
-        let total_read = match sock.read(&mut buf).await {
-            Ok(0) => return, // Connection closed
-            Ok(n) => n,
-            Err(e) => {
-                error!("Error reading from TCP socket: {}", e);
-                return;
-            }
+        let total_read = match sock.read(&mut buf).await? {
+            0 => return Ok(()), // Connection closed
+            n => n,
         };
 
         // Send the response
-        if let Err(e) = sock.write_all(&buf[0..total_read]).await {
-            error!("Error writing to TCP socket: {}", e);
-            return;
+        sock.write_all(&buf[0..total_read]).await?;
+    }
+}
+
+// Count what arrives within the test duration and report it back
+async fn pong_upload(sock: &mut TcpStream) -> std::io::Result<()> {
+    let duration = Duration::from_millis(sock.read_u64().await?);
+    let deadline = tokio::time::Instant::now() + duration;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut received = 0;
+    while let Ok(res) = timeout_at(deadline, sock.read(&mut buf)).await {
+        match res? {
+            0 => return Ok(()),
+            n => received += n as u64,
+        }
+    }
+    sock.write_u64(received).await
+}
+
+// Send until the visitor closes the connection
+async fn pong_download(sock: &mut TcpStream) -> std::io::Result<()> {
+    let chunk = vec![0u8; CHUNK_SIZE];
+    loop {
+        sock.write_all(&chunk).await?;
+    }
+}
+
+fn jitter(times: &[u64]) -> u64 {
+    if times.len() < 2 {
+        return 0;
+    }
+    let total: u64 = times.windows(2).map(|w| w[0].abs_diff(w[1])).sum();
+    total / (times.len() as u64 - 1)
+}
+
+fn rtt_stats(sorted: &[u64], jitter: u64) -> RttStats {
+    let percentile = |q: f64| sorted[((sorted.len() as f64 * q) as usize).min(sorted.len() - 1)];
+    RttStats {
+        min_us: sorted[0] / 1_000,
+        p50_us: percentile(0.5) / 1_000,
+        p95_us: percentile(0.95) / 1_000,
+        p99_us: percentile(0.99) / 1_000,
+        max_us: sorted[sorted.len() - 1] / 1_000,
+        jitter_us: jitter / 1_000,
+    }
+}
+
+fn histogram(sorted: &[u64]) -> Vec<HistogramBucket> {
+    let mut buckets: Vec<HistogramBucket> = HISTOGRAM_BOUNDS_US
+        .iter()
+        .map(|le| HistogramBucket {
+            le_us: Some(*le),
+            count: 0,
+        })
+        .chain(std::iter::once(HistogramBucket::default()))
+        .collect();
+    for ns in sorted {
+        let us = ns / 1_000;
+        let i = HISTOGRAM_BOUNDS_US
+            .iter()
+            .position(|le| us <= *le)
+            .unwrap_or(HISTOGRAM_BOUNDS_US.len());
+        buckets[i].count += 1;
+    }
+    // Trim empty buckets at both ends
+    let first = buckets.iter().position(|b| b.count > 0).unwrap_or(0);
+    let last = buckets.iter().rposition(|b| b.count > 0).unwrap_or(0);
+    buckets.drain(first..=last).collect()
+}
+
+// Convert microseconds to appropriate time units for better readability
+fn format_duration(us: u64) -> String {
+    if us < 1_000 {
+        format!("{} µs", us)
+    } else if us < 1_000_000 {
+        format!("{:.2} ms", us as f64 / 1_000.0)
+    } else {
+        format!("{:.2} s", us as f64 / 1_000_000.0)
+    }
+}
+
+fn format_ms(ms: f64) -> String {
+    format_duration((ms * 1_000.0) as u64)
+}
+
+fn format_bps(bps: f64) -> String {
+    if bps < 1_000_000.0 {
+        format!("{:.1} kbit/s", bps / 1_000.0)
+    } else if bps < 1_000_000_000.0 {
+        format!("{:.1} Mbit/s", bps / 1_000_000.0)
+    } else {
+        format!("{:.2} Gbit/s", bps / 1_000_000_000.0)
+    }
+}
+
+impl PingReport {
+    /// Median round trip in µs as printed by `ping --bare`
+    pub fn bare(&self) -> String {
+        match self.rtt.as_ref() {
+            Some(rtt) => rtt.p50_us.to_string(),
+            None => crate::t!("error-measurement"),
         }
     }
 }
 
-fn format_stats(times: Vec<u32>) -> String {
-    let p50 = times.len() as f64 * 0.5;
-    let p95 = times.len() as f64 * 0.95;
-    let p99 = times.len() as f64 * 0.99;
+impl fmt::Display for PingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(rtt) = self.rtt.as_ref() else {
+            return write!(f, "{}", crate::t!("error-measurement"));
+        };
 
-    // Convert nanoseconds to appropriate time units for better readability
-    let format_duration = |ns: u32| -> String {
-        if ns < 1_000 {
-            format!("{} ns", ns)
-        } else if ns < 1_000_000 {
-            format!("{:.2} µs", ns as f64 / 1_000.0)
-        } else if ns < 1_000_000_000 {
-            format!("{:.2} ms", ns as f64 / 1_000_000.0)
-        } else {
-            format!("{:.2} s", ns as f64 / 1_000_000_000.0)
+        write!(
+            f,
+            "{}:\n   p50: {}\n   p95: {}\n   p99: {}\n   max: {}",
+            crate::t!("ping-time-percentiles"),
+            format_duration(rtt.p50_us),
+            format_duration(rtt.p95_us),
+            format_duration(rtt.p99_us),
+            format_duration(rtt.max_us),
+        )?;
+        write!(
+            f,
+            "\n{}",
+            crate::t!("ping-jitter", "jitter" => format_duration(rtt.jitter_us))
+        )?;
+
+        if self.sent != self.received {
+            write!(
+                f,
+                "\n{}",
+                crate::t!("ping-loss",
+                    "sent" => self.sent,
+                    "received" => self.received,
+                    "loss" => format!("{:.1}", self.loss_percent))
+            )?;
         }
-    };
 
-    format!(
-        "{}:\n   p50: {}\n   p95: {}\n   p99: {}\n   max: {}",
-        crate::t!("ping-time-percentiles"),
-        format_duration(times[p50 as usize]),
-        format_duration(times[p95 as usize]),
-        format_duration(times[p99 as usize]),
-        format_duration(*times.last().unwrap()),
-    )
+        if let Some(handshake) = self.handshake.as_ref() {
+            let mut steps = vec![crate::t!("ping-handshake-connect",
+                "time" => format_ms(handshake.connect_ms))];
+            if let Some(ms) = handshake.tls_ms {
+                steps.push(crate::t!("ping-handshake-tls", "time" => format_ms(ms)));
+            }
+            if let Some(ms) = handshake.websocket_ms {
+                steps.push(crate::t!("ping-handshake-websocket", "time" => format_ms(ms)));
+            }
+            if let Some(ms) = handshake.data_channel_ms {
+                steps.push(crate::t!("ping-handshake-data-channel", "time" => format_ms(ms)));
+            }
+            write!(f, "\n{}: {}", crate::t!("ping-handshake"), steps.join(", "))?;
+        }
+
+        if let Some(throughput) = self.throughput.as_ref() {
+            write!(
+                f,
+                "\n{}",
+                crate::t!("ping-throughput",
+                    "streams" => throughput.streams,
+                    "upload" => format_bps(throughput.upload_bps),
+                    "download" => format_bps(throughput.download_bps))
+            )?;
+        }
+
+        if !self.histogram.is_empty() {
+            write!(f, "\n{}:", crate::t!("ping-histogram"))?;
+            let max = self.histogram.iter().map(|b| b.count).max().unwrap_or(1);
+            for bucket in &self.histogram {
+                let label = match bucket.le_us {
+                    Some(le) => format!("<= {}", format_duration(le)),
+                    None => format!(
+                        "> {}",
+                        format_duration(HISTOGRAM_BOUNDS_US[HISTOGRAM_BOUNDS_US.len() - 1])
+                    ),
+                };
+                let bar = "#".repeat((bucket.count * HISTOGRAM_WIDTH / max.max(1)) as usize);
+                write!(f, "\n   {:>12} {} {}", label, bar, bucket.count)?;
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -1,4 +1,5 @@
 use crate::config::{ClientConfig, EnvConfig};
+use crate::logs::{access_log_path, error_log_path, process_log_path};
 use crate::shell::{download, get_cache_dir, unzip, SubProcess, DOWNLOAD_SUBDIR};
 use anyhow::{Context, Result};
 use common::protocol::message::Message;
@@ -79,6 +80,8 @@ pub async fn setup_httpd(
             None,
             Default::default(),
             Some((crate::t!("installing-vcpp"), result_tx.clone(), 2)),
+            None,
+            None,
             command_rx.resubscribe(),
         )
         .await
@@ -130,6 +133,14 @@ pub async fn start_httpd(
     );
     let httpd_config = httpd_config.replace("[[PID_FILE]]", pid_file.to_str().unwrap());
     let httpd_config = httpd_config.replace("[[LOCK_FILE]]", lock_file.to_str().unwrap());
+    let httpd_config = httpd_config.replace(
+        "[[ERROR_LOG]]",
+        error_log_path(&endpoint.guid)?.to_str().unwrap(),
+    );
+    let httpd_config = httpd_config.replace(
+        "[[ACCESS_LOG]]",
+        access_log_path(&endpoint.guid)?.to_str().unwrap(),
+    );
 
     #[cfg(unix)]
     let httpd_config = httpd_config.replace("[[IS_LINUX]]", "");
@@ -162,6 +173,12 @@ pub async fn start_httpd(
         vec!["-X".to_string(), "-f".to_string(), httpd_cfg],
         None,
         envs,
+        endpoint
+            .client
+            .as_ref()
+            .and_then(|c| c.restart.clone())
+            .unwrap_or_default(),
+        Some(process_log_path(&endpoint.guid)?),
         result_tx,
     );
     Ok(server)
@@ -1,4 +1,5 @@
 use crate::config::ClientConfig;
+use crate::logs::process_log_path;
 use crate::plugins::Plugin;
 use crate::shell::{download, get_cache_dir, SubProcess};
 use anyhow::{bail, Context, Result};
@@ -109,6 +110,8 @@ impl Plugin for MinecraftPlugin {
             None,
             Default::default(),
             Some((crate::t!("installing-jdk"), result_tx.clone(), 450)),
+            None,
+            None,
             command_rx.resubscribe(),
         )
         .await?;
@@ -225,6 +228,12 @@ impl Plugin for MinecraftPlugin {
             args,
             Some(minecraft_dir),
             Default::default(),
+            endpoint
+                .client
+                .as_ref()
+                .and_then(|c| c.restart.clone())
+                .unwrap_or_default(),
+            Some(process_log_path(&endpoint.guid)?),
             result_tx,
         );
         Ok(server)
@@ -45,7 +45,7 @@ fn check_enviroment(config: Arc<RwLock<ClientConfig>>) -> Result<EnvConfig> {
     let mut env = if let Some(env) = env {
         env
     } else {
-        bail!("Платформа 1C не найдена, укажите ее битность (x32/x64) и путь в настройках");
+        bail!(crate::t!("error-onec-platform-not-found"));
     };
 
     if let Some(one_c_home) = &config.read().one_c_home {
@@ -53,10 +53,10 @@ fn check_enviroment(config: Arc<RwLock<ClientConfig>>) -> Result<EnvConfig> {
     }
 
     if !std::path::Path::new(&env.home_1c).exists() {
-        bail!(
-            "Путь до платформы 1C ({}) не найден, укажите его в настройках",
-            env.home_1c.to_str().unwrap()
-        );
+        bail!(crate::t!(
+            "error-onec-home-not-found",
+            "path" => env.home_1c.to_string_lossy().to_string()
+        ));
     }
     Ok(env)
 }
@@ -96,13 +96,17 @@ impl Plugin for OneCPlugin {
 
         let publish_dir = one_c_publish_dir.join(&endpoint.guid);
 
-        std::fs::create_dir_all(publish_dir.clone()).context("Can't create publish dir")?;
+        std::fs::create_dir_all(publish_dir.clone())
+            .context(crate::t!("error-creating-publish-dir"))?;
 
         let mut default_vrd = publish_dir.clone();
         default_vrd.push("default.vrd");
 
-        let wsap_error = format!(
-            "Модуль {} на найден в {}. Проверьте настройки и убедитесь что у вас установлены модули расширения веб-сервера для 1С", WSAP_MODULE, &env.home_1c.to_str().unwrap());
+        let wsap_error = crate::t!(
+            "error-wsap-not-found",
+            "module" => WSAP_MODULE,
+            "path" => env.home_1c.to_string_lossy().to_string()
+        );
 
         let wsap = if let Some(wsap) =
             find(&env.home_1c, &PathBuf::from(WSAP_MODULE)).context(wsap_error.clone())?
@@ -123,7 +127,7 @@ impl Plugin for OneCPlugin {
         let vrd_config = DEFAULT_VRD.replace("[[IB]]", &escape_str_attribute(&ib));
 
         if !default_vrd.exists() {
-            std::fs::write(&default_vrd, vrd_config).context("Ошибка записи default.vrd")?;
+            std::fs::write(&default_vrd, vrd_config).context(crate::t!("error-writing-vrd"))?;
         }
 
         let httpd_config = ONEC_CONFIG.replace("[[WSAP_MODULE]]", wsap.to_str().unwrap());
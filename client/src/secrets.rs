@@ -0,0 +1,176 @@
+//! Storage of the session token outside of the plain text config.
+//!
+//! The token goes to the OS keyring on macOS and Windows. Elsewhere, or if
+//! the keyring is not available, it's encrypted with a key kept next to the
+//! config, readable only by the owner.
+
+use crate::config::MaskedString;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+use zeroize::Zeroizing;
+
+// Value of `token` in the config when the token is in the keyring
+const KEYRING_MARKER: &str = "keyring";
+// Secret Service isn't there on servers, and the kernel keyring is cleared on reboot
+const KEYRING_SUPPORTED: bool = cfg!(any(target_os = "macos", target_os = "windows"));
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+const KEYRING_SERVICE: &str = "cloudpub";
+// Prefix of the token encrypted with the key file
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const KEY_FILE: &str = "client.key";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// Value of `token` to write to the config at `config_path`
+pub fn seal(
+    config_path: &Path,
+    agent_id: &str,
+    token: Option<&MaskedString>,
+) -> Result<Option<MaskedString>> {
+    let Some(token) = token else {
+        keyring_delete(agent_id);
+        return Ok(None);
+    };
+
+    if KEYRING_SUPPORTED {
+        match keyring_set(agent_id, token) {
+            Ok(()) => return Ok(Some(KEYRING_MARKER.into())),
+            Err(err) => warn!("Keyring is not available, using key file: {:#}", err),
+        }
+    }
+
+    let key = load_or_create_key(&key_path(config_path))?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let encrypted = cipher
+        .encrypt(&nonce, token.as_bytes())
+        .map_err(|_| anyhow!("Failed to encrypt the token"))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&encrypted);
+    Ok(Some(
+        format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(sealed)).into(),
+    ))
+}
+
+/// Token of the `token` value read from the config. A token that can't be
+/// recovered is dropped, so the user just has to log in again.
+pub fn open(config_path: &Path, agent_id: &str, stored: &MaskedString) -> Option<MaskedString> {
+    let res = if stored.0 == KEYRING_MARKER {
+        keyring_get(agent_id)
+    } else if let Some(sealed) = stored.strip_prefix(ENCRYPTED_PREFIX) {
+        decrypt(config_path, sealed)
+    } else {
+        debug!("Token is stored in plain text");
+        Ok(stored.clone())
+    };
+    res.map_err(|err| warn!("Failed to read the token: {:#}", err))
+        .ok()
+}
+
+/// Whether the `token` value of the config is protected
+pub fn is_sealed(stored: &MaskedString) -> bool {
+    stored.0 == KEYRING_MARKER || stored.starts_with(ENCRYPTED_PREFIX)
+}
+
+fn decrypt(config_path: &Path, sealed: &str) -> Result<MaskedString> {
+    let key = read_key(&key_path(config_path))?;
+    let data = STANDARD.decode(sealed).context("Invalid encrypted token")?;
+    if data.len() < NONCE_LEN {
+        bail!("Invalid encrypted token");
+    }
+    let (nonce, encrypted) = data.split_at(NONCE_LEN);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let token = Zeroizing::new(
+        cipher
+            .decrypt(Nonce::from_slice(nonce), encrypted)
+            .map_err(|_| anyhow!("Token is encrypted with another key"))?,
+    );
+    Ok(std::str::from_utf8(&token)
+        .context("Invalid encrypted token")?
+        .into())
+}
+
+fn key_path(config_path: &Path) -> PathBuf {
+    config_path.with_file_name(KEY_FILE)
+}
+
+fn read_key(path: &Path) -> Result<Zeroizing<Vec<u8>>> {
+    let key = Zeroizing::new(
+        fs::read(path).with_context(|| format!("Failed to read the key {:?}", path))?,
+    );
+    if key.len() != KEY_LEN {
+        bail!("Invalid key {:?}", path);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            warn!("Key {:?} is accessible by other users, fixing", path);
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    Ok(key)
+}
+
+fn load_or_create_key(path: &Path) -> Result<Zeroizing<Vec<u8>>> {
+    if path.exists() {
+        return read_key(path);
+    }
+    let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create the key {:?}", path))?;
+    file.write_all(&key)
+        .with_context(|| format!("Failed to write the key {:?}", path))?;
+    Ok(Zeroizing::new(key.to_vec()))
+}
+
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+fn keyring_entry(agent_id: &str) -> Result<keyring::Entry> {
+    Ok(keyring::Entry::new(KEYRING_SERVICE, agent_id)?)
+}
+
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+fn keyring_get(agent_id: &str) -> Result<MaskedString> {
+    Ok(keyring_entry(agent_id)?.get_password()?.into())
+}
+
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+fn keyring_set(agent_id: &str, token: &str) -> Result<()> {
+    Ok(keyring_entry(agent_id)?.set_password(token)?)
+}
+
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+fn keyring_delete(agent_id: &str) {
+    if let Err(err) = keyring_entry(agent_id).and_then(|entry| Ok(entry.delete_credential()?)) {
+        debug!("Failed to delete the token from the keyring: {:#}", err);
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn keyring_get(_agent_id: &str) -> Result<MaskedString> {
+    bail!("Keyring is not supported on this platform")
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn keyring_set(_agent_id: &str, _token: &str) -> Result<()> {
+    bail!("Keyring is not supported on this platform")
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn keyring_delete(_agent_id: &str) {}
@@ -103,6 +103,15 @@ impl ServiceManager for LinuxServiceManager {
         Ok(())
     }
 
+    fn restart(&self) -> Result<()> {
+        // The service may restart itself, don't wait for the job to finish
+        Command::new("systemctl")
+            .args(["restart", "--no-block", &self.config.name])
+            .status()
+            .context("Failed to restart service")?;
+        Ok(())
+    }
+
     fn status(&self) -> Result<ServiceStatus> {
         let service_path = self.service_file_path();
         let service_file = Path::new(&service_path);
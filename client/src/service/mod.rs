@@ -1,4 +1,6 @@
-use anyhow::Result;
+use crate::config::ClientConfig;
+use anyhow::{Context, Result};
+use std::env;
 use std::path::PathBuf;
 
 #[cfg(target_os = "windows")]
@@ -23,6 +25,10 @@ pub trait ServiceManager {
     fn start(&self) -> Result<()>;
     fn stop(&self) -> Result<()>;
     fn status(&self) -> Result<ServiceStatus>;
+    fn restart(&self) -> Result<()> {
+        self.stop()?;
+        self.start()
+    }
 }
 
 #[allow(dead_code)]
@@ -43,6 +49,40 @@ pub struct ServiceConfig {
     pub config_path: Option<PathBuf>,
 }
 
+/// Service running `clo run` with the given config
+pub fn agent_service_config(config: &ClientConfig) -> Result<ServiceConfig> {
+    // Get the current executable path
+    let exe_path = env::current_exe().context("Failed to get current executable path")?;
+
+    // Prepare config file argument
+    let mut args = Vec::new();
+    if let Some(path_str) = config.get_config_path().to_str() {
+        args.push("--conf".to_string());
+        args.push(path_str.to_string());
+    }
+
+    // Add the run command for the service
+    args.push("run".to_string());
+
+    // On Windows, add the service flag
+    #[cfg(target_os = "windows")]
+    {
+        args.push("--run-as-service".to_string());
+    }
+
+    Ok(ServiceConfig {
+        #[cfg(target_os = "macos")]
+        name: "ru.cloudpub.clo".to_string(),
+        #[cfg(not(target_os = "macos"))]
+        name: "cloudpub".to_string(),
+        display_name: "CloudPub Client".to_string(),
+        description: "CloudPub Client Service".to_string(),
+        executable_path: exe_path,
+        args,
+        config_path: Some(config.get_config_path().to_owned()),
+    })
+}
+
 pub fn create_service_manager(config: ServiceConfig) -> Box<dyn ServiceManager> {
     #[cfg(target_os = "windows")]
     {
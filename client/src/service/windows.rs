@@ -150,6 +150,19 @@ impl ServiceManagerTrait for WindowsServiceManager {
         Ok(())
     }
 
+    fn restart(&self) -> Result<()> {
+        // Stopping the service may stop this process, so a detached shell
+        // waits for the stop and starts the service again
+        std::process::Command::new("cmd")
+            .args([
+                "/C",
+                &format!("net stop {0} & net start {0}", self.config.name),
+            ])
+            .spawn()
+            .context("Failed to restart service")?;
+        Ok(())
+    }
+
     fn status(&self) -> Result<ServiceStatus> {
         let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
 
@@ -252,6 +265,9 @@ pub async fn run_app(stop_tx: broadcast::Sender<()>) {
         verbose: false,
         readonly: false,
         log_level: "debug".to_string(),
+        lang: None,
+        #[cfg(feature = "tui")]
+        tui: false,
     };
     let (_guard, config) = match init(&cli, false).context("Failed to initialize config") {
         Ok(r) => r,
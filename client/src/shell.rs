@@ -1,4 +1,5 @@
 use crate::config::ClientConfig;
+use crate::logs::ProcessLog;
 use anyhow::{bail, Context, Result};
 use std::cmp::min;
 use std::fs::File;
@@ -6,7 +7,7 @@ use std::io::Write;
 use std::path::PathBuf;
 
 use common::protocol::message::Message;
-use common::protocol::{Break, ErrorInfo, ErrorKind, ProgressInfo};
+use common::protocol::{Break, ErrorInfo, ErrorKind, ProgressInfo, Restart, RestartPolicy};
 use common::transport::rustls::load_roots;
 use dirs::cache_dir;
 use futures::stream::StreamExt;
@@ -16,10 +17,12 @@ use std::collections::HashMap;
 use std::io;
 use std::path::Path;
 use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
+use tokio::process::{Child, Command};
 use tokio::sync::broadcast;
+use tokio::time::{self, Duration, Instant};
 use tracing::{error, info, warn};
 use walkdir::WalkDir;
 #[cfg(feature = "zip")]
@@ -27,8 +30,16 @@ use zip::read::ZipArchive;
 
 pub const DOWNLOAD_SUBDIR: &str = "download";
 
+const DEFAULT_MAX_RESTARTS: u32 = 5;
+const RESTART_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RESTART_MAX_DELAY: Duration = Duration::from_secs(60);
+// Process running longer than this is considered recovered
+const RESTART_RESET_AFTER: Duration = Duration::from_secs(60);
+
 pub struct SubProcess {
     shutdown_tx: broadcast::Sender<Message>,
+    stopped: Arc<AtomicBool>,
+    pid: Arc<AtomicU32>,
 }
 
 impl SubProcess {
@@ -37,31 +48,45 @@ impl SubProcess {
         args: Vec<String>,
         chdir: Option<PathBuf>,
         envs: HashMap<String, String>,
+        restart: Restart,
+        log: Option<PathBuf>,
         result_tx: broadcast::Sender<Message>,
     ) -> Self {
-        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let (shutdown_tx, _) = broadcast::channel(1);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let pid = Arc::new(AtomicU32::new(0));
+        let shutdown_tx2 = shutdown_tx.clone();
+        let stopped2 = stopped.clone();
+        let pid2 = pid.clone();
         tokio::spawn(async move {
-            if let Err(err) = execute(command, args, chdir, envs, None, shutdown_rx).await {
-                error!("Failed to execute command: {:?}", err);
-                result_tx
-                    .send(Message::Error(ErrorInfo {
-                        kind: ErrorKind::Fatal.into(),
-                        message: err.to_string(),
-                    }))
-                    .ok();
-            } else {
-                result_tx
-                    .send(Message::Error(ErrorInfo {
-                        kind: ErrorKind::Fatal.into(),
-                        message: crate::t!("error-process-terminated"),
-                    }))
-                    .ok();
-            }
+            supervise(
+                command,
+                args,
+                chdir,
+                envs,
+                restart,
+                log,
+                pid2,
+                shutdown_tx2,
+                stopped2,
+                result_tx,
+            )
+            .await;
         });
-        Self { shutdown_tx }
+        Self {
+            shutdown_tx,
+            stopped,
+            pid,
+        }
+    }
+
+    /// Pid of the running process, zero while it's not running
+    pub fn pid(&self) -> Arc<AtomicU32> {
+        self.pid.clone()
     }
 
     pub fn stop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
         self.shutdown_tx.send(Message::Break(Break {})).ok();
     }
 }
@@ -72,6 +97,111 @@ impl Drop for SubProcess {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
+async fn supervise(
+    command: PathBuf,
+    args: Vec<String>,
+    chdir: Option<PathBuf>,
+    envs: HashMap<String, String>,
+    restart: Restart,
+    log: Option<PathBuf>,
+    pid: Arc<AtomicU32>,
+    shutdown_tx: broadcast::Sender<Message>,
+    stopped: Arc<AtomicBool>,
+    result_tx: broadcast::Sender<Message>,
+) {
+    // Shared by all restarts of the process
+    let log = log.and_then(|path| match ProcessLog::new(&path) {
+        Ok(log) => Some(Arc::new(log)),
+        Err(err) => {
+            warn!("{:#}", err);
+            None
+        }
+    });
+    let policy = restart.policy();
+    let max_restarts = match restart.max_restarts {
+        0 => DEFAULT_MAX_RESTARTS,
+        n => n,
+    };
+    let mut restarts = 0;
+    let mut delay = RESTART_INITIAL_DELAY;
+
+    loop {
+        let started = Instant::now();
+        let res = execute(
+            command.clone(),
+            args.clone(),
+            chdir.clone(),
+            envs.clone(),
+            None,
+            log.clone(),
+            Some(pid.clone()),
+            shutdown_tx.subscribe(),
+        )
+        .await;
+
+        if stopped.load(Ordering::SeqCst) {
+            break;
+        }
+
+        if let Err(err) = &res {
+            error!("Failed to execute command: {:?}", err);
+        }
+
+        let should_restart = match policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => res.is_err(),
+            RestartPolicy::Always => true,
+        };
+
+        if !should_restart {
+            let message = match res {
+                Err(err) => err.to_string(),
+                Ok(()) => crate::t!("error-process-terminated"),
+            };
+            result_tx
+                .send(Message::Error(ErrorInfo {
+                    kind: ErrorKind::ExecuteFailed.into(),
+                    message,
+                }))
+                .ok();
+            break;
+        }
+
+        if started.elapsed() > RESTART_RESET_AFTER {
+            restarts = 0;
+            delay = RESTART_INITIAL_DELAY;
+        }
+
+        if restarts >= max_restarts {
+            error!("Process {:?} is crash looping, giving up", command);
+            result_tx
+                .send(Message::Error(ErrorInfo {
+                    kind: ErrorKind::CrashLoop.into(),
+                    message: crate::t!("error-crash-loop", "restarts" => restarts),
+                }))
+                .ok();
+            break;
+        }
+
+        restarts += 1;
+        warn!(
+            "Process {:?} exited, restart {}/{} in {:?}",
+            command, restarts, max_restarts, delay
+        );
+
+        let mut shutdown_rx = shutdown_tx.subscribe();
+        tokio::select! {
+            _ = time::sleep(delay) => {}
+            _ = shutdown_rx.recv() => break,
+        }
+        if stopped.load(Ordering::SeqCst) {
+            break;
+        }
+        delay = min(delay * 2, RESTART_MAX_DELAY);
+    }
+}
+
 pub async fn send_progress(
     message: &str,
     template: &str,
@@ -88,13 +218,16 @@ pub async fn send_progress(
     progress_tx.send(Message::Progress(progress)).ok();
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn execute(
     command: PathBuf,
     args: Vec<String>,
     chdir: Option<PathBuf>,
     envs: HashMap<String, String>,
     progress: Option<(String, broadcast::Sender<Message>, u64)>,
-    mut shutdown_rx: broadcast::Receiver<Message>,
+    log: Option<Arc<ProcessLog>>,
+    pid: Option<Arc<AtomicU32>>,
+    shutdown_rx: broadcast::Receiver<Message>,
 ) -> Result<()> {
     info!(
         "Executing command: {} {}",
@@ -139,6 +272,10 @@ pub async fn execute(
             command, args
         ))?;
 
+    if let Some(pid) = pid.as_ref() {
+        pid.store(child.id().unwrap_or_default(), Ordering::Relaxed);
+    }
+
     let stdout = child.stdout.take().context("Failed to get stdout")?;
     let stderr = child.stderr.take().context("Failed to get stderr")?;
 
@@ -156,7 +293,10 @@ pub async fn execute(
             tokio::select! {
                 line = stdout_reader.next_line() => match line {
                     Ok(Some(line)) => {
-                        info!("STDOUT: {}", line);
+                        match log.as_ref() {
+                            Some(log) => log.write_line(&line),
+                            None => info!("STDOUT: {}", line),
+                        }
                         current += 1;
                         if let Some((message, tx, total)) = progress.as_ref() {
                             send_progress(message, &template_clone, *total, current, tx.clone()).await;
@@ -172,7 +312,10 @@ pub async fn execute(
                 },
                 line = stderr_reader.next_line() => match line {
                     Ok(Some(line)) => {
-                        warn!("STDERR: {}", line);
+                        match log.as_ref() {
+                            Some(log) => log.write_line(&line),
+                            None => warn!("STDERR: {}", line),
+                        }
                         current += 1;
                         if let Some((message, tx, total)) = progress.as_ref() {
                             send_progress(message, &template_clone, *total, current, tx.clone()).await;
@@ -191,12 +334,25 @@ pub async fn execute(
         Ok(())
     });
 
+    let res = wait_child(&mut child, &progress, &template, shutdown_rx).await;
+    if let Some(pid) = pid.as_ref() {
+        pid.store(0, Ordering::Relaxed);
+    }
+    res
+}
+
+async fn wait_child(
+    child: &mut Child,
+    progress: &Option<(String, broadcast::Sender<Message>, u64)>,
+    template: &str,
+    mut shutdown_rx: broadcast::Receiver<Message>,
+) -> Result<()> {
     tokio::select! {
         status = child.wait() => {
             let status = status.context("Failed to wait on child")?;
             if !status.success() {
                 if let Some((message, tx, total)) = progress.as_ref() {
-                    send_progress(message, &template, *total, *total, tx.clone()).await;
+                    send_progress(message, template, *total, *total, tx.clone()).await;
                 }
                 bail!("Command failed: {:?}", status);
             }
@@ -216,7 +372,7 @@ pub async fn execute(
     }
 
     if let Some((message, tx, total)) = progress.as_ref() {
-        send_progress(message, &template, *total, *total, tx.clone()).await;
+        send_progress(message, template, *total, *total, tx.clone()).await;
     }
     info!("Command executed successfully");
 
@@ -280,19 +436,11 @@ pub fn unzip(
     Ok(())
 }
 
-pub async fn download(
-    message: &str,
-    config: Arc<RwLock<ClientConfig>>,
-    url: &str,
-    path: &Path,
-    mut command_rx: broadcast::Receiver<Message>,
-    result_tx: broadcast::Sender<Message>,
-) -> Result<()> {
-    info!("Downloading {} to {:?}", url, path);
-
+/// HTTP client trusting the same roots as the transport
+pub fn http_client(config: &ClientConfig) -> Result<reqwest::Client> {
     let mut client = ClientBuilder::default();
 
-    if let Some(tls) = &config.read().transport.tls {
+    if let Some(tls) = &config.transport.tls {
         let roots = load_roots(tls).context("Failed to load client config")?;
         for cert in roots {
             client = client.add_root_certificate(Certificate::from_der(&cert)?);
@@ -302,7 +450,20 @@ pub async fn download(
         }
     }
 
-    let client = client.build().context("Failed to create reqwest client")?;
+    client.build().context("Failed to create reqwest client")
+}
+
+pub async fn download(
+    message: &str,
+    config: Arc<RwLock<ClientConfig>>,
+    url: &str,
+    path: &Path,
+    mut command_rx: broadcast::Receiver<Message>,
+    result_tx: broadcast::Sender<Message>,
+) -> Result<()> {
+    info!("Downloading {} to {:?}", url, path);
+
+    let client = http_client(&config.read())?;
     // Reqwest setup
     let res = client
         .get(url)
@@ -407,6 +568,15 @@ pub fn find(dir: &Path, file: &Path) -> Result<Option<PathBuf>> {
     Ok(None)
 }
 
+/// Guids name files in the cache dir, nothing else may get into the path
+pub fn check_guid(guid: &str) -> Result<()> {
+    let valid = guid.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if guid.is_empty() || !valid {
+        bail!(crate::t!("invalid-guid", "guid" => guid.to_string()));
+    }
+    Ok(())
+}
+
 pub fn get_cache_dir(subdir: &str) -> Result<PathBuf> {
     let mut cache_dir = cache_dir().context("Can't get cache dir")?;
     cache_dir.push("cloudpub");
@@ -0,0 +1,447 @@
+use crate::config::ClientConfig;
+use anyhow::{Context, Result};
+use common::protocol::{ConnectState, Endpoint, ServerEndpoint};
+use common::utils::format_host_port;
+use common::version::VERSION;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+// Port and pid of the running agent, next to its config
+const AGENT_FILE_EXTENSION: &str = "agent.json";
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static::lazy_static! {
+    /// State of the agent of the command line, embedded agents keep their own
+    pub static ref REGISTRY: Arc<StatusRegistry> = Default::default();
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EndpointInfo {
+    pub guid: String,
+    pub name: Option<String>,
+    pub url: String,
+    pub target: String,
+    pub status: String,
+    pub active_channels: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub pid: Option<u32>,
+    #[serde(default)]
+    pub visitors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AgentStatus {
+    pub pid: u32,
+    pub version: String,
+    pub state: String,
+    pub server: String,
+    pub uptime_secs: u64,
+    pub connected_secs: Option<u64>,
+    pub rtt_ms: Option<u64>,
+    pub last_heartbeat_secs: Option<u64>,
+    pub endpoints: Vec<EndpointInfo>,
+}
+
+/// Data channel counters of an endpoint
+#[derive(Debug, Default)]
+pub struct ChannelStats {
+    pub active: AtomicU64,
+    /// Bytes sent from the local service to visitors
+    pub sent: AtomicU64,
+    /// Bytes received from visitors
+    pub received: AtomicU64,
+    next_id: AtomicU64,
+    // Visitor addresses of the active channels
+    visitors: Mutex<BTreeMap<u64, String>>,
+}
+
+impl ChannelStats {
+    /// Count the channel as active until the guard is dropped
+    pub fn open(self: &Arc<Self>, visitor: &str) -> ChannelGuard {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if !visitor.is_empty() {
+            self.visitors.lock().insert(id, visitor.to_string());
+        }
+        ChannelGuard {
+            stats: self.clone(),
+            id,
+        }
+    }
+
+    pub fn visitors(&self) -> Vec<String> {
+        self.visitors.lock().values().cloned().collect()
+    }
+}
+
+pub struct ChannelGuard {
+    stats: Arc<ChannelStats>,
+    id: u64,
+}
+
+impl Drop for ChannelGuard {
+    fn drop(&mut self) {
+        self.stats.active.fetch_sub(1, Ordering::Relaxed);
+        self.stats.visitors.lock().remove(&self.id);
+    }
+}
+
+struct EndpointState {
+    endpoint: ServerEndpoint,
+    status: String,
+    stats: Arc<ChannelStats>,
+    pid: Option<Arc<AtomicU32>>,
+}
+
+struct State {
+    started: Instant,
+    state: ConnectState,
+    server: String,
+    connected: Option<Instant>,
+    rtt: Option<Duration>,
+    heartbeat: Option<Instant>,
+    endpoints: BTreeMap<String, EndpointState>,
+}
+
+pub struct StatusRegistry {
+    state: RwLock<State>,
+}
+
+impl Default for StatusRegistry {
+    fn default() -> Self {
+        Self {
+            state: RwLock::new(State {
+                started: Instant::now(),
+                state: ConnectState::Disconnected,
+                server: String::new(),
+                connected: None,
+                rtt: None,
+                heartbeat: None,
+                endpoints: BTreeMap::new(),
+            }),
+        }
+    }
+}
+
+impl StatusRegistry {
+    pub fn set_state(&self, state: ConnectState) {
+        let mut s = self.state.write();
+        s.state = state;
+        if state != ConnectState::Connected {
+            s.connected = None;
+        }
+    }
+
+    pub fn set_connected(&self, server: &str, rtt: Duration) {
+        let mut s = self.state.write();
+        s.state = ConnectState::Connected;
+        s.server = server.to_string();
+        s.connected = Some(Instant::now());
+        s.rtt = Some(rtt);
+    }
+
+    pub fn set_rtt(&self, rtt: Duration) {
+        self.state.write().rtt = Some(rtt);
+    }
+
+    pub fn heartbeat(&self) {
+        self.state.write().heartbeat = Some(Instant::now());
+    }
+
+    pub fn upsert_endpoint(&self, endpoint: &ServerEndpoint) {
+        let mut s = self.state.write();
+        let status = endpoint.status.clone().unwrap_or_default();
+        match s.endpoints.get_mut(&endpoint.guid) {
+            Some(ep) => {
+                ep.endpoint = endpoint.clone();
+                ep.status = status;
+            }
+            None => {
+                s.endpoints.insert(
+                    endpoint.guid.clone(),
+                    EndpointState {
+                        endpoint: endpoint.clone(),
+                        status,
+                        stats: Default::default(),
+                        pid: None,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn set_endpoint_status(&self, guid: &str, status: &str) {
+        if let Some(ep) = self.state.write().endpoints.get_mut(guid) {
+            ep.status = status.to_string();
+        }
+    }
+
+    pub fn remove_endpoint(&self, guid: &str) {
+        self.state.write().endpoints.remove(guid);
+    }
+
+    pub fn set_process(&self, guid: &str, pid: Arc<AtomicU32>) {
+        if let Some(ep) = self.state.write().endpoints.get_mut(guid) {
+            ep.pid = Some(pid);
+        }
+    }
+
+    pub fn channel_stats(&self, guid: &str) -> Arc<ChannelStats> {
+        self.state
+            .read()
+            .endpoints
+            .get(guid)
+            .map(|ep| ep.stats.clone())
+            // Endpoint may be acked after the first data channel, count it anyway
+            .unwrap_or_default()
+    }
+
+    pub fn snapshot(&self) -> AgentStatus {
+        let s = self.state.read();
+        let endpoints = s
+            .endpoints
+            .values()
+            .map(|ep| {
+                let client = ep.endpoint.client.clone().unwrap_or_default();
+                let target = if client.local_port == 0 {
+                    client.local_addr.clone()
+                } else {
+                    format!(
+                        "{}{}",
+                        format_host_port(&client.local_addr, client.local_port as u16),
+                        client.local_path
+                    )
+                };
+                EndpointInfo {
+                    guid: ep.endpoint.guid.clone(),
+                    name: client.description.clone(),
+                    url: ep.endpoint.as_redacted_url(),
+                    target,
+                    status: ep.status.clone(),
+                    active_channels: ep.stats.active.load(Ordering::Relaxed),
+                    bytes_sent: ep.stats.sent.load(Ordering::Relaxed),
+                    bytes_received: ep.stats.received.load(Ordering::Relaxed),
+                    pid: ep
+                        .pid
+                        .as_ref()
+                        .map(|p| p.load(Ordering::Relaxed))
+                        .filter(|p| *p != 0),
+                    visitors: ep.stats.visitors(),
+                }
+            })
+            .collect();
+
+        AgentStatus {
+            pid: std::process::id(),
+            version: VERSION.to_string(),
+            state: format!("{:?}", s.state).to_lowercase(),
+            server: s.server.clone(),
+            uptime_secs: s.started.elapsed().as_secs(),
+            connected_secs: s.connected.map(|t| t.elapsed().as_secs()),
+            rtt_ms: s.rtt.map(|rtt| rtt.as_millis() as u64),
+            last_heartbeat_secs: s.heartbeat.map(|t| t.elapsed().as_secs()),
+            endpoints,
+        }
+    }
+}
+
+/// Counts bytes passing through the local side of a data channel
+pub struct CountingStream<S> {
+    inner: S,
+    stats: Arc<ChannelStats>,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S, stats: Arc<ChannelStats>) -> Self {
+        Self { inner, stats }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        let read = (buf.filled().len() - before) as u64;
+        this.stats.sent.fetch_add(read, Ordering::Relaxed);
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            this.stats.received.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AgentFile {
+    pid: u32,
+    port: u16,
+}
+
+// The service runs with the config of the user who installed it, so both
+// find the file of the same config
+fn agent_file(config: &ClientConfig) -> PathBuf {
+    config.get_config_path().with_extension(AGENT_FILE_EXTENSION)
+}
+
+/// Local status endpoint of the agent, stops and cleans up on drop
+pub struct StatusServer {
+    handle: JoinHandle<()>,
+    path: PathBuf,
+}
+
+impl Drop for StatusServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+        // Don't remove the file of another agent started later
+        let ours = std::fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|s| serde_json::from_str::<AgentFile>(&s).ok())
+            .map(|f| f.pid == std::process::id())
+            .unwrap_or(false);
+        if ours {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
+/// Answer status queries on a loopback port advertised next to the config
+pub async fn serve(config: &ClientConfig) -> Result<StatusServer> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("Failed to bind status port")?;
+    let port = listener.local_addr()?.port();
+
+    let file = AgentFile {
+        pid: std::process::id(),
+        port,
+    };
+    let path = agent_file(config);
+    std::fs::write(&path, serde_json::to_string(&file)?).context("Failed to write agent file")?;
+    debug!("Status server listening on port {}", port);
+
+    let handle = tokio::spawn(async move {
+        loop {
+            let mut conn = match listener.accept().await {
+                Ok((conn, _)) => conn,
+                Err(err) => {
+                    warn!("Status server accept failed: {}", err);
+                    continue;
+                }
+            };
+            let status = REGISTRY.snapshot();
+            tokio::spawn(async move {
+                if let Ok(json) = serde_json::to_vec(&status) {
+                    conn.write_all(&json).await.ok();
+                }
+                conn.shutdown().await.ok();
+            });
+        }
+    });
+
+    Ok(StatusServer { handle, path })
+}
+
+/// Ask the agent running with the config for its status
+pub async fn query(config: &ClientConfig) -> Result<AgentStatus> {
+    let file =
+        std::fs::read_to_string(agent_file(config)).context(crate::t!("agent-not-running"))?;
+    let file: AgentFile = serde_json::from_str(&file).context("Invalid agent file")?;
+
+    let mut conn =
+        tokio::time::timeout(QUERY_TIMEOUT, TcpStream::connect(("127.0.0.1", file.port)))
+            .await
+            .ok()
+            .and_then(|res| res.ok())
+            .context(crate::t!("agent-not-running"))?;
+
+    let mut buf = Vec::new();
+    tokio::time::timeout(QUERY_TIMEOUT, conn.read_to_end(&mut buf))
+        .await
+        .context("Status query timed out")??;
+    serde_json::from_slice(&buf).context("Invalid status response")
+}
+
+fn format_duration(secs: u64) -> String {
+    format!("{}h {:02}m {:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Human readable status report
+pub fn render(status: &AgentStatus) -> String {
+    let unknown = || "-".to_string();
+    let mut out = vec![
+        crate::t!("status-agent",
+            "pid" => status.pid,
+            "version" => status.version.clone(),
+            "state" => status.state.clone(),
+            "server" => status.server.clone()),
+        crate::t!("status-uptime",
+            "uptime" => format_duration(status.uptime_secs),
+            "connected" => status.connected_secs.map(format_duration).unwrap_or_else(unknown)),
+        crate::t!("status-latency",
+            "rtt" => status.rtt_ms.map(|v| v.to_string()).unwrap_or_else(unknown),
+            "heartbeat" => status.last_heartbeat_secs.map(|v| v.to_string()).unwrap_or_else(unknown)),
+    ];
+
+    if status.endpoints.is_empty() {
+        out.push(crate::t!("no-registered-services"));
+    }
+
+    for ep in &status.endpoints {
+        out.push(String::new());
+        out.push(format!(
+            "{}: [{}] {}",
+            ep.guid,
+            ep.status,
+            ep.name.clone().unwrap_or_default()
+        ));
+        out.push(format!("  {} -> {}", ep.url, ep.target));
+        let mut stats = crate::t!("status-endpoint-stats",
+            "channels" => ep.active_channels,
+            "sent" => ep.bytes_sent,
+            "received" => ep.bytes_received);
+        if let Some(pid) = ep.pid {
+            stats.push_str(", ");
+            stats.push_str(&crate::t!("status-pid", "pid" => pid));
+        }
+        out.push(format!("  {}", stats));
+    }
+
+    out.join("\n")
+}
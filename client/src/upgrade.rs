@@ -0,0 +1,252 @@
+use crate::config::ClientConfig;
+use crate::service::{agent_service_config, create_service_manager, ServiceStatus};
+use crate::shell::download;
+use anyhow::{bail, Context, Result};
+use common::protocol::message::Message;
+use common::protocol::{ErrorInfo, ErrorKind, UpgradeInfo};
+use common::version::VERSION;
+use ed25519_dalek::{Signature, VerifyingKey};
+use parking_lot::RwLock;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::{error, info};
+
+// Hex encoded ed25519 release key of the maintainers, see `sign-release.sh`.
+// Empty until it's provisioned, which keeps upgrades disabled.
+const PUBLIC_KEY: &str = "";
+// Detached signature of the binary is published next to it
+const SIGNATURE_SUFFIX: &str = ".sig";
+
+/// Upgrade in background, the outcome is sent to `result_tx` as
+/// `UpgradeInstalled` or an `UpgradeFailed` error
+pub fn spawn(
+    info: UpgradeInfo,
+    config: Arc<RwLock<ClientConfig>>,
+    command_rx: broadcast::Receiver<Message>,
+    result_tx: broadcast::Sender<Message>,
+) {
+    tokio::spawn(async move {
+        let msg = match upgrade(&info, config, command_rx, result_tx.clone()).await {
+            Ok(()) => Message::UpgradeInstalled(info),
+            Err(err) => {
+                error!("{:?}", err);
+                Message::Error(ErrorInfo {
+                    kind: ErrorKind::UpgradeFailed.into(),
+                    message: crate::t!("upgrade-failed", "error" => format!("{:#}", err)),
+                })
+            }
+        };
+        result_tx.send(msg).ok();
+    });
+}
+
+/// Download the new version, verify its signature and replace the running
+/// executable. The previous one is kept for `clo upgrade --rollback`.
+pub async fn upgrade(
+    info: &UpgradeInfo,
+    config: Arc<RwLock<ClientConfig>>,
+    command_rx: broadcast::Receiver<Message>,
+    result_tx: broadcast::Sender<Message>,
+) -> Result<()> {
+    if !is_newer(&info.version) {
+        bail!(crate::t!("upgrade-not-newer", "version" => info.version.clone()));
+    }
+    let key = public_key()?;
+    let exe = current_exe()?;
+    let new = sibling(&exe, "new");
+    let sig = sibling(&exe, "sig");
+
+    // Download skips files of the same size, never trust leftovers
+    fs::remove_file(&new).ok();
+    fs::remove_file(&sig).ok();
+
+    let res = async {
+        download(
+            &crate::t!("downloading-upgrade", "version" => info.version.clone()),
+            config.clone(),
+            &info.url,
+            &new,
+            command_rx.resubscribe(),
+            result_tx.clone(),
+        )
+        .await?;
+        download(
+            &crate::t!("downloading-signature"),
+            config,
+            &format!("{}{}", info.url, SIGNATURE_SUFFIX),
+            &sig,
+            command_rx,
+            result_tx,
+        )
+        .await?;
+        verify(&key, &info.version, &new, &sig)?;
+        prepare(&new)
+    }
+    .await;
+    fs::remove_file(&sig).ok();
+    if let Err(err) = res {
+        fs::remove_file(&new).ok();
+        return Err(err);
+    }
+
+    replace(&exe, &new, &sibling(&exe, "old"))?;
+    info!("Upgraded to {}", info.version);
+    Ok(())
+}
+
+/// Restore the executable replaced by the last upgrade
+pub fn rollback() -> Result<()> {
+    let exe = current_exe()?;
+    let old = sibling(&exe, "old");
+    if !old.exists() {
+        bail!(crate::t!("upgrade-no-rollback"));
+    }
+    let new = sibling(&exe, "new");
+    fs::copy(&old, &new).context("Failed to copy the previous version")?;
+    // The failed version isn't worth keeping
+    replace(&exe, &new, &sibling(&exe, "failed"))?;
+    fs::remove_file(sibling(&exe, "failed")).ok();
+    fs::remove_file(&old).ok();
+    Ok(())
+}
+
+/// Restart the system service to run the new executable.
+/// Returns `false` if the service isn't running.
+pub fn restart_service(config: &ClientConfig) -> Result<bool> {
+    let manager = create_service_manager(agent_service_config(config)?);
+    match manager.status()? {
+        ServiceStatus::Running => {
+            manager.restart()?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Whether `version` is later than the running one, e.g. `1.10.0` > `1.9.2`
+pub fn is_newer(version: &str) -> bool {
+    fn parse(version: &str) -> Option<Vec<u64>> {
+        version
+            .trim_start_matches('v')
+            .split(['.', '-', '+'])
+            .take(3)
+            .map(|n| n.parse().ok())
+            .collect()
+    }
+    match (parse(version), parse(VERSION)) {
+        (Some(new), Some(current)) => new > current,
+        _ => false,
+    }
+}
+
+/// Whether this build can verify releases, `auto_upgrade` is ignored otherwise
+pub fn is_enabled() -> bool {
+    !PUBLIC_KEY.is_empty()
+}
+
+fn public_key() -> Result<VerifyingKey> {
+    if !is_enabled() {
+        bail!(crate::t!("upgrade-key-missing"));
+    }
+    let bytes: [u8; 32] = decode_hex(PUBLIC_KEY)
+        .and_then(|b| b.try_into().ok())
+        .context("Invalid upgrade public key")?;
+    VerifyingKey::from_bytes(&bytes).context("Invalid upgrade public key")
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Signed text of a release, the version keeps old binaries from being
+// offered as upgrades
+fn signed_message(version: &str, binary: &[u8]) -> Vec<u8> {
+    let digest: String = Sha256::digest(binary)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("clo {} sha256:{}", version, digest).into_bytes()
+}
+
+fn verify(key: &VerifyingKey, version: &str, binary: &Path, sig: &Path) -> Result<()> {
+    let data = fs::read(binary).context("Failed to read the downloaded binary")?;
+    let sig = fs::read(sig).context("Failed to read the signature")?;
+    // Accept both raw and hex encoded signatures
+    let sig = match sig.len() {
+        64 => sig,
+        _ => decode_hex(&String::from_utf8_lossy(&sig))
+            .context(crate::t!("upgrade-bad-signature"))?,
+    };
+    let sig = Signature::from_slice(&sig).context(crate::t!("upgrade-bad-signature"))?;
+    key.verify_strict(&signed_message(version, &data), &sig)
+        .context(crate::t!("upgrade-bad-signature"))
+}
+
+// Make the new binary executable and check it starts on this platform
+fn prepare(new: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(new, fs::Permissions::from_mode(0o755))
+            .context("Failed to make the new binary executable")?;
+    }
+    let status = Command::new(new)
+        .arg("--version")
+        .output()
+        .context(crate::t!("upgrade-broken-binary"))?
+        .status;
+    if !status.success() {
+        bail!(crate::t!("upgrade-broken-binary"));
+    }
+    Ok(())
+}
+
+// Put `new` in place of `exe`, keeping the current one as `backup`
+fn replace(exe: &Path, new: &Path, backup: &Path) -> Result<()> {
+    fs::remove_file(backup).ok();
+    #[cfg(unix)]
+    {
+        fs::hard_link(exe, backup)
+            .or_else(|_| fs::copy(exe, backup).map(|_| ()))
+            .context("Failed to keep the current executable")?;
+        // Rename is atomic, the path always points to a complete binary
+        fs::rename(new, exe).context("Failed to install the new executable")?;
+    }
+    #[cfg(not(unix))]
+    {
+        // A running executable can be renamed but not overwritten on Windows
+        fs::rename(exe, backup).context("Failed to move the current executable")?;
+        if let Err(err) = fs::rename(new, exe) {
+            tracing::warn!("Failed to install the new executable: {}", err);
+            fs::rename(backup, exe).context("Failed to restore the current executable")?;
+            return Err(err).context("Failed to install the new executable");
+        }
+    }
+    Ok(())
+}
+
+fn current_exe() -> Result<PathBuf> {
+    let exe = env::current_exe().context("Failed to get current executable path")?;
+    // Replace the binary behind a symlink, not the link itself
+    fs::canonicalize(&exe).context("Failed to get current executable path")
+}
+
+// `clo` -> `clo.old`, `clo.exe` -> `clo.exe.old`
+fn sibling(exe: &Path, suffix: &str) -> PathBuf {
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    exe.with_file_name(name)
+}
@@ -0,0 +1,99 @@
+use crate::client::{do_data_channel_handshake, forward, Service};
+use crate::p2p::P2pLink;
+use crate::status::ChannelStats;
+use anyhow::{bail, Context, Result};
+use common::protocol::message::Message;
+use common::protocol::{read_message, Compression};
+use common::transport::Transport;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+/// Local listener of `clo connect`. Every accepted connection is carried
+/// to the private endpoint of the publishing agent directly if there is a
+/// direct connection, or through the server otherwise.
+pub struct Visitor {
+    handle: JoinHandle<()>,
+}
+
+impl Visitor {
+    pub(crate) fn spawn<T: 'static + Transport>(
+        listener: TcpListener,
+        service: Service<T>,
+        link: Option<Arc<P2pLink>>,
+    ) -> Self {
+        let handle = tokio::spawn(async move {
+            accept(listener, service, link).await;
+        });
+        Self { handle }
+    }
+}
+
+impl Drop for Visitor {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn accept<T: 'static + Transport>(
+    listener: TcpListener,
+    service: Service<T>,
+    link: Option<Arc<P2pLink>>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(v) => v,
+            Err(err) => {
+                warn!("Failed to accept visitor connection: {:#}", err);
+                continue;
+            }
+        };
+        debug!("Visitor connection from {}", peer);
+        stream.set_nodelay(true).ok();
+        let service = service.clone();
+        let link = link.clone();
+        tokio::spawn(async move {
+            if let Err(err) = run_visitor_channel(stream, peer, service, link).await {
+                warn!("{:#}", err);
+            }
+        });
+    }
+}
+
+async fn run_visitor_channel<T: Transport>(
+    stream: TcpStream,
+    peer: SocketAddr,
+    service: Service<T>,
+    link: Option<Arc<P2pLink>>,
+) -> Result<()> {
+    // Visitor traffic doesn't belong to any endpoint of this agent
+    let stats = Arc::new(ChannelStats::default());
+    let _active = stats.open(&peer.to_string());
+
+    if let Some(link) = link {
+        match link.open().await {
+            Some(Ok(direct)) => {
+                return forward(direct, stream, Compression::None, stats.clone()).await;
+            }
+            Some(Err(err)) => warn!("{:#}. Using relay", err),
+            None => {}
+        }
+    }
+
+    let mut conn = do_data_channel_handshake(service.clone())
+        .await
+        .context("Failed to handshake visitor channel")?;
+
+    match read_message(&mut conn)
+        .await
+        .context("Failed to read visitor channel message")?
+    {
+        Message::StartForwardTcp(start) => {
+            forward(conn, stream, start.compression(), stats.clone()).await
+        }
+        Message::Error(err) => bail!("{}", err.message),
+        msg => bail!("Unexpected visitor channel message: {:?}", msg),
+    }
+}
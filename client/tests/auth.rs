@@ -0,0 +1,72 @@
+//! Non-interactive login against the local mock of the auth endpoints
+
+mod common;
+
+use crate::common::auth::{MockAuth, MOCK_USER_CODE};
+use client::auth::{read_password, read_token_file, DeviceLogin};
+use client::config::ClientConfig;
+use std::io::Cursor;
+use std::path::PathBuf;
+use tokio::time::{self, Duration};
+use uuid::Uuid;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+async fn mock_auth(token: Option<&str>, pending: usize) -> String {
+    let auth = MockAuth::bind("127.0.0.1:0", token, pending).await.unwrap();
+    let url = auth.url().unwrap();
+    tokio::spawn(auth.run());
+    url
+}
+
+fn temp_file(content: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("clo-token-{}", Uuid::new_v4()));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn device_login_polls_until_approved() {
+    let url = mock_auth(Some("device-token"), 2).await;
+    let login = DeviceLogin::start(&ClientConfig::default(), Some(&url))
+        .await
+        .unwrap();
+    assert_eq!(login.user_code, MOCK_USER_CODE);
+    assert_eq!(login.verification_uri, format!("{}device", url));
+
+    let token = time::timeout(TIMEOUT, login.wait()).await.unwrap().unwrap();
+    assert_eq!(token.0, "device-token");
+}
+
+#[tokio::test]
+async fn device_login_denied() {
+    let url = mock_auth(None, 1).await;
+    let login = DeviceLogin::start(&ClientConfig::default(), Some(&url))
+        .await
+        .unwrap();
+    assert!(time::timeout(TIMEOUT, login.wait()).await.unwrap().is_err());
+}
+
+#[test]
+fn password_from_stdin() {
+    let mut stdin = Cursor::new("secret pass\r\nnext line\n");
+    assert_eq!(read_password(&mut stdin).unwrap().0, "secret pass");
+
+    let mut stdin = Cursor::new("no line break");
+    assert_eq!(read_password(&mut stdin).unwrap().0, "no line break");
+}
+
+#[test]
+fn token_from_file() {
+    let path = temp_file("  file-token\n");
+    let token = read_token_file(&path);
+    std::fs::remove_file(&path).ok();
+    assert_eq!(token.unwrap().0, "file-token");
+
+    let path = temp_file("\n");
+    let token = read_token_file(&path);
+    std::fs::remove_file(&path).ok();
+    assert!(token.is_err());
+
+    assert!(read_token_file(&std::env::temp_dir().join("clo-no-such-token")).is_err());
+}
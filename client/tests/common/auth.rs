@@ -0,0 +1,134 @@
+//! Local stand-in of the device login endpoints described in `client::auth`
+
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::debug;
+
+const DEVICE_CODE_PATH: &str = "api/device/code";
+const DEVICE_TOKEN_PATH: &str = "api/device/token";
+const DEVICE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+const CLIENT_ID: &str = "clo";
+
+/// Codes are approved with the token after the given number of pending
+/// polls, or denied if there is no token.
+pub struct MockAuth {
+    listener: TcpListener,
+    token: Option<String>,
+    pending: usize,
+}
+
+// Code issued by the mock, polls with any other one are rejected
+const MOCK_DEVICE_CODE: &str = "mock-device-code";
+pub const MOCK_USER_CODE: &str = "MOCK-CODE";
+
+impl MockAuth {
+    pub async fn bind(addr: &str, token: Option<&str>, pending: usize) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen on {}", addr))?;
+        Ok(Self {
+            listener,
+            token: token.map(str::to_string),
+            pending,
+        })
+    }
+
+    /// Base URL to pass as `auth_url`
+    pub fn url(&self) -> Result<String> {
+        Ok(format!("http://{}/", self.listener.local_addr()?))
+    }
+
+    pub async fn run(self) -> Result<()> {
+        let base = self.url()?;
+        let listener = self.listener;
+        let token = Arc::new(self.token);
+        let pending = Arc::new(AtomicUsize::new(self.pending));
+        loop {
+            let (conn, _) = listener.accept().await.context("Failed to accept")?;
+            let (base, token, pending) = (base.clone(), token.clone(), pending.clone());
+            tokio::spawn(async move {
+                if let Err(err) = serve_mock(conn, &base, &token, &pending).await {
+                    debug!("{:#}", err);
+                }
+            });
+        }
+    }
+}
+
+// One request per connection is enough for reqwest with `Connection: close`
+async fn serve_mock(
+    mut conn: TcpStream,
+    base: &str,
+    token: &Option<String>,
+    pending: &AtomicUsize,
+) -> Result<()> {
+    let (read, mut write) = conn.split();
+    let mut reader = BufReader::new(read);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let path = line.split_whitespace().nth(1).unwrap_or_default().to_string();
+    let mut len = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = value.trim().parse().context("Invalid Content-Length")?;
+            }
+        }
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await?;
+    let form: HashMap<String, String> = url::form_urlencoded::parse(&body).into_owned().collect();
+    let field = |name: &str| form.get(name).map(String::as_str);
+
+    let (status, reply) = if path.ends_with(DEVICE_CODE_PATH) {
+        if field("client_id") == Some(CLIENT_ID) && field("agent_id").is_some() {
+            let reply = json!({
+                "device_code": MOCK_DEVICE_CODE,
+                "user_code": MOCK_USER_CODE,
+                "verification_uri": format!("{}device", base),
+                "expires_in": 60,
+                "interval": 0,
+            });
+            ("200 OK", reply)
+        } else {
+            ("400 Bad Request", json!({ "error": "invalid_client" }))
+        }
+    } else if path.ends_with(DEVICE_TOKEN_PATH) {
+        if field("grant_type") != Some(DEVICE_GRANT_TYPE)
+            || field("device_code") != Some(MOCK_DEVICE_CODE)
+        {
+            ("400 Bad Request", json!({ "error": "invalid_grant" }))
+        } else if pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            ("400 Bad Request", json!({ "error": "authorization_pending" }))
+        } else if let Some(token) = token {
+            let reply = json!({ "access_token": token, "token_type": "bearer" });
+            ("200 OK", reply)
+        } else {
+            ("400 Bad Request", json!({ "error": "access_denied" }))
+        }
+    } else {
+        ("404 Not Found", json!({ "error": "not_found" }))
+    };
+
+    let reply = reply.to_string();
+    let res = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reply.len(),
+        reply
+    );
+    write.write_all(res.as_bytes()).await?;
+    Ok(())
+}
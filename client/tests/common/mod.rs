@@ -0,0 +1,7 @@
+//! Local stand-ins of the server shared by the integration tests
+
+// Each test binary uses only a part of them
+#![allow(dead_code)]
+
+pub mod auth;
+pub mod rendezvous;
@@ -0,0 +1,196 @@
+//! Local stand-in of the server for direct connections between agents.
+//!
+//! Agents connect to it with the TCP transport. It acks their endpoints,
+//! relays the offers and answers of direct connections and reflects UDP
+//! addresses on the same port. Nothing is relayed through it, so a visitor
+//! channel works only over a direct connection.
+
+use anyhow::{Context, Result};
+use client::p2p;
+use ::common::constants::DEFAULT_HEARTBEAT_INTERVAL_SECS;
+use ::common::protocol::message::Message;
+use ::common::protocol::{
+    local_capabilities, read_message, write_message, AgentAck, Capability, EndpointRemoveAck,
+    ErrorInfo, ErrorKind, HeartBeat, ServerEndpoint,
+};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+#[derive(Default)]
+struct State {
+    // Endpoints with the control channel of the publishing agent
+    endpoints: HashMap<String, (ServerEndpoint, mpsc::Sender<Message>)>,
+    // Control channels of the visitors waiting for an answer
+    sessions: HashMap<String, mpsc::Sender<Message>>,
+}
+
+pub struct Rendezvous {
+    listener: TcpListener,
+    reflector: UdpSocket,
+    capabilities: Vec<i32>,
+}
+
+impl Rendezvous {
+    /// Listen on the TCP and UDP ports of the address
+    pub async fn bind(addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen on {}", addr))?;
+        let reflector = UdpSocket::bind(listener.local_addr()?)
+            .await
+            .with_context(|| format!("Failed to listen on UDP {}", addr))?;
+        Ok(Self {
+            listener,
+            reflector,
+            capabilities: local_capabilities(),
+        })
+    }
+
+    /// Act as a server that doesn't advertise the capability
+    pub fn without(mut self, capability: Capability) -> Self {
+        self.capabilities.retain(|c| *c != capability as i32);
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub async fn run(self) -> Result<()> {
+        let rendezvous = self.reflector.local_addr()?.to_string();
+        let reflector = tokio::spawn(p2p::reflect(self.reflector));
+        let state: Arc<Mutex<State>> = Default::default();
+        let res = loop {
+            let conn = match self.listener.accept().await {
+                Ok((conn, _)) => conn,
+                Err(err) => break Err(err).context("Failed to accept agent"),
+            };
+            let rendezvous = rendezvous.clone();
+            let capabilities = self.capabilities.clone();
+            let state = state.clone();
+            tokio::spawn(async move {
+                if let Err(err) = serve(conn, rendezvous, capabilities, state).await {
+                    debug!("{:#}", err);
+                }
+            });
+        };
+        reflector.abort();
+        res
+    }
+}
+
+async fn serve(
+    mut conn: TcpStream,
+    rendezvous: String,
+    capabilities: Vec<i32>,
+    state: Arc<Mutex<State>>,
+) -> Result<()> {
+    let agent = match read_message(&mut conn).await? {
+        Message::AgentHello(info) => info,
+        msg => {
+            debug!("Refusing relayed channel: {:?}", msg);
+            return Ok(());
+        }
+    };
+    info!("Agent {} connected", agent.agent_id);
+    let ack = Message::AgentAck(AgentAck {
+        token: String::new(),
+        capabilities,
+        rendezvous,
+    });
+    write_message(&mut conn, &ack).await?;
+
+    let (tx, rx) = mpsc::channel(16);
+    let mut guids = Vec::new();
+    let res = relay(&mut conn, tx, rx, &state, &mut guids).await;
+
+    let mut state = state.lock();
+    for guid in guids {
+        state.endpoints.remove(&guid);
+    }
+    info!("Agent {} disconnected", agent.agent_id);
+    res
+}
+
+async fn relay(
+    conn: &mut TcpStream,
+    tx: mpsc::Sender<Message>,
+    mut rx: mpsc::Receiver<Message>,
+    state: &Mutex<State>,
+    guids: &mut Vec<String>,
+) -> Result<()> {
+    let mut heartbeat = time::interval(Duration::from_secs(DEFAULT_HEARTBEAT_INTERVAL_SECS));
+    loop {
+        tokio::select! {
+            _ = heartbeat.tick() => {
+                write_message(conn, &Message::HeartBeat(HeartBeat::default())).await?;
+            }
+            msg = rx.recv() => {
+                if let Some(msg) = msg {
+                    write_message(conn, &msg).await?;
+                }
+            }
+            msg = read_message(conn) => {
+                let reply = match msg? {
+                    Message::EndpointStart(client) => {
+                        let endpoint = ServerEndpoint {
+                            status: Some("online".to_string()),
+                            guid: Uuid::new_v4().to_string(),
+                            remote_proto: client.local_proto,
+                            client: Some(client),
+                            ..Default::default()
+                        };
+                        guids.push(endpoint.guid.clone());
+                        state.lock().endpoints.insert(endpoint.guid.clone(), (endpoint.clone(), tx.clone()));
+                        Message::EndpointAck(endpoint)
+                    }
+                    Message::EndpointRemove(remove) => {
+                        state.lock().endpoints.remove(&remove.guid);
+                        Message::EndpointRemoveAck(EndpointRemoveAck { guid: remove.guid })
+                    }
+                    Message::VisitorConnect(visitor) => {
+                        let endpoint = state.lock().endpoints.get(&visitor.guid).map(|e| e.0.clone());
+                        match endpoint {
+                            Some(endpoint) => Message::VisitorAck(endpoint),
+                            None => Message::Error(ErrorInfo {
+                                kind: ErrorKind::Fatal.into(),
+                                message: format!("Endpoint {} not found", visitor.guid),
+                            }),
+                        }
+                    }
+                    Message::HeartBeat(hb) if hb.seq != 0 => Message::HeartBeat(hb),
+                    Message::P2pOffer(offer) => {
+                        let publisher = state.lock().endpoints.get(&offer.guid).map(|e| e.1.clone());
+                        match publisher {
+                            Some(publisher) => {
+                                state.lock().sessions.insert(offer.session.clone(), tx.clone());
+                                publisher.send(Message::P2pOffer(offer)).await.ok();
+                            }
+                            None => warn!("Offer for unknown endpoint {}", offer.guid),
+                        }
+                        continue;
+                    }
+                    Message::P2pAnswer(answer) => {
+                        let visitor = state.lock().sessions.remove(&answer.session);
+                        if let Some(visitor) = visitor {
+                            visitor.send(Message::P2pAnswer(answer)).await.ok();
+                        }
+                        continue;
+                    }
+                    msg => {
+                        debug!("Ignoring {:?}", msg);
+                        continue;
+                    }
+                };
+                write_message(conn, &reply).await?;
+            }
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Error pages are served only from the templates dir
+
+use client::error_page::{install_template, serve, STATUS_BAD_GATEWAY};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+async fn fetch(template: &str) -> String {
+    let mut page = serve(STATUS_BAD_GATEWAY, template);
+    page.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    page.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn template_outside_of_dir_is_refused() {
+    let marker = Uuid::new_v4().to_string();
+    let path = std::env::temp_dir().join(format!("clo-page-{}.html", marker));
+    std::fs::write(&path, format!("{} {{status}}", marker)).unwrap();
+
+    let response = fetch(&path.to_string_lossy()).await;
+    assert!(response.starts_with("HTTP/1.1 502"));
+    assert!(!response.contains(&marker));
+
+    let name = install_template(&path.to_string_lossy()).unwrap();
+    std::fs::remove_file(&path).ok();
+    assert!(fetch(&format!("../{}", name)).await.contains("CloudPub"));
+    assert!(fetch(&name).await.contains(&format!("{} 502", marker)));
+}
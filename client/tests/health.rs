@@ -0,0 +1,33 @@
+//! Health checks of acked endpoints never run a command from the server
+
+use client::health::LocalChecks;
+use common::protocol::{ClientEndpoint, HealthCheck, HealthCheckKind, Protocol};
+
+fn endpoint(kind: HealthCheckKind, command: &str) -> ClientEndpoint {
+    let mut check = HealthCheck::default();
+    check.set_kind(kind);
+    check.command = command.to_string();
+    let mut endpoint = ClientEndpoint::default();
+    endpoint.local_proto = Protocol::Http.into();
+    endpoint.local_addr = "127.0.0.1".to_string();
+    endpoint.local_port = 8080;
+    endpoint.health_check = Some(check);
+    endpoint
+}
+
+#[test]
+fn command_comes_from_local_state() {
+    let mut checks = LocalChecks::default();
+    let ack = endpoint(HealthCheckKind::Command, "rm -rf ~");
+    assert!(checks.resolve(&ack).is_none());
+
+    checks.insert(&endpoint(HealthCheckKind::Command, "true"));
+    assert_eq!(checks.resolve(&ack).unwrap().command, "true");
+}
+
+#[test]
+fn probes_come_from_ack() {
+    let checks = LocalChecks::default();
+    let ack = endpoint(HealthCheckKind::Http, "");
+    assert_eq!(checks.resolve(&ack).unwrap().kind(), HealthCheckKind::Http);
+}
@@ -0,0 +1,11 @@
+//! Log paths stay inside the logs dir
+
+use client::logs::endpoint_logs;
+
+#[test]
+fn guid_with_path_is_refused() {
+    for guid in ["../x", "..", "a/b", "a\\b", ""] {
+        assert!(endpoint_logs(guid).is_err(), "{:?}", guid);
+    }
+    assert!(endpoint_logs("3f2c9a1e-7b5d-4c8e-9f0a-1b2c3d4e5f60").is_ok());
+}
@@ -0,0 +1,122 @@
+//! Direct connection between two local agents through the rendezvous stand-in
+
+mod common;
+
+use client::agent::Agent;
+use client::config::ClientConfig;
+use ::common::config::TransportType;
+use ::common::protocol::message::Message;
+use ::common::protocol::{Capability, ClientEndpoint, Protocol, VisitorConnect};
+use crate::common::rendezvous::Rendezvous;
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{self, Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+async fn agent(server: &str) -> Agent {
+    let mut config = ClientConfig::default();
+    config.server = format!("tcp://{}", server).parse().unwrap();
+    config.transport.transport_type = TransportType::Tcp;
+    Agent::builder()
+        .config(config)
+        .token("test")
+        .timeout(TIMEOUT)
+        .connect()
+        .await
+        .unwrap()
+}
+
+async fn echo_server() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = conn.split();
+                tokio::io::copy(&mut reader, &mut writer).await.ok();
+            });
+        }
+    });
+    port
+}
+
+async fn roundtrip(addr: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut conn = TcpStream::connect(addr).await?;
+    conn.write_all(data).await?;
+    let mut buf = vec![0; data.len()];
+    conn.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn direct_connection_between_local_agents() {
+    let rendezvous = Rendezvous::bind("127.0.0.1:0").await.unwrap();
+    let server = rendezvous.local_addr().unwrap().to_string();
+    tokio::spawn(rendezvous.run());
+
+    let mut endpoint = ClientEndpoint::default();
+    endpoint.local_proto = Protocol::Tcp.into();
+    endpoint.local_addr = "127.0.0.1".to_string();
+    endpoint.local_port = echo_server().await as u32;
+    endpoint.private = true;
+
+    let publisher = agent(&server).await;
+    let published = publisher.publish(endpoint).await.unwrap();
+
+    let visitor = agent(&server).await;
+    let mut events = visitor.events();
+    visitor
+        .send(Message::VisitorConnect(VisitorConnect {
+            guid: published.guid.clone(),
+            bind_addr: "127.0.0.1:0".to_string(),
+            p2p: true,
+        }))
+        .unwrap();
+    let bind_addr = time::timeout(TIMEOUT, async {
+        loop {
+            if let Ok(Message::VisitorAck(endpoint)) = events.recv().await {
+                break endpoint.bind_addr;
+            }
+        }
+    })
+    .await
+    .expect("No visitor ack");
+
+    // The stand-in doesn't relay data, so only a direct connection echoes
+    let echoed = time::timeout(TIMEOUT, async {
+        loop {
+            match roundtrip(&bind_addr, b"ping").await {
+                Ok(echoed) => break echoed,
+                Err(_) => time::sleep(Duration::from_millis(200)).await,
+            }
+        }
+    })
+    .await
+    .expect("No direct connection");
+    assert_eq!(echoed, b"ping");
+
+    visitor.shutdown().await;
+    publisher.shutdown().await;
+}
+
+#[tokio::test]
+async fn private_endpoint_needs_visitor_capability() {
+    let rendezvous = Rendezvous::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .without(Capability::Visitor);
+    let server = rendezvous.local_addr().unwrap().to_string();
+    tokio::spawn(rendezvous.run());
+
+    let mut endpoint = ClientEndpoint::default();
+    endpoint.local_proto = Protocol::Tcp.into();
+    endpoint.local_addr = "127.0.0.1".to_string();
+    endpoint.local_port = echo_server().await as u32;
+    endpoint.private = true;
+
+    let publisher = agent(&server).await;
+    assert!(publisher.publish(endpoint).await.is_err());
+    publisher.shutdown().await;
+}
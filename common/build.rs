@@ -8,6 +8,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Compile the .proto file
     config.type_attribute(".", "#[derive(serde::Serialize,serde::Deserialize)]");
+    // Secrets are redacted by the Debug implementations in protocol.rs
+    config.skip_debug([
+        ".protocol.ClientEndpoint",
+        ".protocol.AgentInfo",
+        ".protocol.AgentAck",
+        ".protocol.P2pOffer",
+        ".protocol.P2pAnswer",
+    ]);
     config.compile_protos(&["src/protocol.proto"], &["src/"])?;
 
     // Post process the generated code
@@ -16,20 +24,22 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Remove PartialEq from ClientEndpoint derive attributes
     for name in ["ClientEndpoint", "ServerEndpoint"] {
-        generated = generated.replace(
-            format!(
-                "#[derive(Clone, PartialEq, ::prost::Message)]
-pub struct {} {{",
-                name
-            )
-            .as_str(),
-            format!(
-                "#[derive(Clone, ::prost::Message)]
-pub struct {} {{",
-                name
-            )
-            .as_str(),
-        );
+        for attrs in ["", "#[prost(skip_debug)]\n"] {
+            generated = generated.replace(
+                format!(
+                    "#[derive(Clone, PartialEq, ::prost::Message)]
+{}pub struct {} {{",
+                    attrs, name
+                )
+                .as_str(),
+                format!(
+                    "#[derive(Clone, ::prost::Message)]
+{}pub struct {} {{",
+                    attrs, name
+                )
+                .as_str(),
+            );
+        }
     }
 
     std::fs::write(format!("{}/protocol.rs", out_dir), generated)?;
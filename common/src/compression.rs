@@ -0,0 +1,187 @@
+use crate::protocol::Compression;
+use anyhow::{bail, Result};
+use async_compression::tokio::bufread::{DeflateDecoder, ZstdDecoder};
+use async_compression::tokio::write::{DeflateEncoder, ZstdEncoder};
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
+
+const COPY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Compression algorithms supported by this side, in order of preference
+pub const SUPPORTED_COMPRESSION: [Compression; 2] = [Compression::Zstd, Compression::Deflate];
+
+/// Pick the first algorithm offered by the peer that we support
+pub fn negotiate(offered: &[i32]) -> Compression {
+    offered
+        .iter()
+        .filter_map(|c| Compression::try_from(*c).ok())
+        .find(|c| SUPPORTED_COMPRESSION.contains(c))
+        .unwrap_or(Compression::None)
+}
+
+/// Byte counters of a compressed data channel
+#[derive(Debug, Default)]
+pub struct CompressionStats {
+    /// Uncompressed bytes read from the local side
+    pub raw_sent: AtomicU64,
+    /// Compressed bytes written to the peer
+    pub wire_sent: AtomicU64,
+    /// Compressed bytes read from the peer
+    pub wire_received: AtomicU64,
+    /// Uncompressed bytes written to the local side
+    pub raw_received: AtomicU64,
+}
+
+impl CompressionStats {
+    /// Ratio of uncompressed to compressed bytes in both directions
+    pub fn ratio(&self) -> f64 {
+        let raw = self.raw_sent.load(Ordering::Relaxed) + self.raw_received.load(Ordering::Relaxed);
+        let wire =
+            self.wire_sent.load(Ordering::Relaxed) + self.wire_received.load(Ordering::Relaxed);
+        if wire == 0 {
+            1.0
+        } else {
+            raw as f64 / wire as f64
+        }
+    }
+}
+
+impl Display for CompressionStats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "sent {} -> {} bytes, received {} -> {} bytes, ratio {:.2}",
+            self.raw_sent.load(Ordering::Relaxed),
+            self.wire_sent.load(Ordering::Relaxed),
+            self.wire_received.load(Ordering::Relaxed),
+            self.raw_received.load(Ordering::Relaxed),
+            self.ratio()
+        )
+    }
+}
+
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<CompressionStats>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        let read = (buf.filled().len() - before) as u64;
+        this.count.wire_received.fetch_add(read, Ordering::Relaxed);
+        res
+    }
+}
+
+struct CountingWriter<W> {
+    inner: W,
+    count: Arc<CompressionStats>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CountingWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            this.count.wire_sent.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Copy data in both directions, compressing traffic towards `remote` and
+/// decompressing traffic from it. Returns byte counters of the channel.
+pub async fn copy_bidirectional_compressed<S, L>(
+    remote: S,
+    local: L,
+    compression: Compression,
+) -> Result<Arc<CompressionStats>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    L: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let stats = Arc::new(CompressionStats::default());
+
+    let (remote_rd, remote_wr) = tokio::io::split(remote);
+    let (mut local_rd, mut local_wr) = tokio::io::split(local);
+
+    let remote_rd = BufReader::new(CountingReader {
+        inner: remote_rd,
+        count: stats.clone(),
+    });
+    let remote_wr = CountingWriter {
+        inner: remote_wr,
+        count: stats.clone(),
+    };
+
+    let (mut decoder, mut encoder): (
+        Box<dyn AsyncRead + Unpin + Send + '_>,
+        Box<dyn AsyncWrite + Unpin + Send + '_>,
+    ) = match compression {
+        Compression::Zstd => (
+            Box::new(ZstdDecoder::new(remote_rd)),
+            Box::new(ZstdEncoder::new(remote_wr)),
+        ),
+        Compression::Deflate => (
+            Box::new(DeflateDecoder::new(remote_rd)),
+            Box::new(DeflateEncoder::new(remote_wr)),
+        ),
+        Compression::None => bail!("Compression is not negotiated"),
+    };
+
+    let upstream = async {
+        let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+        loop {
+            let n = local_rd.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            encoder.write_all(&buf[..n]).await?;
+            // Flush every chunk, interactive protocols can't wait for a full block
+            encoder.flush().await?;
+            stats.raw_sent.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        encoder.shutdown().await
+    };
+
+    let downstream = async {
+        let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+        loop {
+            let n = decoder.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            local_wr.write_all(&buf[..n]).await?;
+            stats.raw_received.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        local_wr.shutdown().await
+    };
+
+    tokio::try_join!(upstream, downstream)?;
+
+    Ok(stats)
+}
@@ -1,28 +1,43 @@
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
+use std::net::IpAddr;
 use std::ops::Deref;
+use std::path::Path;
 use url::Url;
+use zeroize::Zeroize;
 
 use crate::constants::{DEFAULT_KEEPALIVE_INTERVAL, DEFAULT_KEEPALIVE_SECS, DEFAULT_NODELAY};
 
 pub use crate::protocol::Protocol;
 
 /// String with Debug implementation that emits "MASKED"
-/// Used to mask sensitive strings when logging
+/// Used to mask sensitive strings when logging, zeroed on drop
 #[derive(Serialize, Deserialize, Default, PartialEq, Eq, Clone)]
 pub struct MaskedString(pub String);
 
 impl Debug for MaskedString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        Redacted(&self.0).fmt(f)
+    }
+}
+
+impl Drop for MaskedString {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Debug of a secret that isn't a `MaskedString`, like the protocol fields
+pub struct Redacted<'a>(pub &'a str);
+
+impl Debug for Redacted<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         if self.0.is_empty() {
             f.write_str("EMPTY")
         } else {
-            #[cfg(debug_assertions)]
-            f.write_str(&self.0)?;
-            #[cfg(not(debug_assertions))]
-            f.write_str("MASKED")?;
-            Ok(())
+            f.write_str("MASKED")
         }
     }
 }
@@ -40,6 +55,12 @@ impl From<&str> for MaskedString {
     }
 }
 
+impl From<String> for MaskedString {
+    fn from(s: String) -> MaskedString {
+        MaskedString(s)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Default)]
 pub enum TransportType {
     #[serde(rename = "websocket")]
@@ -82,6 +103,9 @@ pub struct TcpConfig {
     pub keepalive_secs: u64,
     pub keepalive_interval: u64,
     pub proxy: Option<Url>,
+    /// Static hosts override, resolved before DNS
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub hosts: HashMap<String, Vec<IpAddr>>,
 }
 
 impl Default for TcpConfig {
@@ -91,6 +115,7 @@ impl Default for TcpConfig {
             keepalive_secs: DEFAULT_KEEPALIVE_SECS,
             keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
             proxy: None,
+            hosts: HashMap::new(),
         }
     }
 }
@@ -117,34 +142,60 @@ impl Default for TransportConfig {
 }
 
 impl TransportConfig {
-    pub fn validate(config: &TransportConfig, _is_server: bool) -> Result<()> {
-        config
-            .tcp
-            .proxy
-            .as_ref()
-            .map_or(Ok(()), |u| match u.scheme() {
-                "socks5" => Ok(()),
-                "http" => Ok(()),
-                _ => Err(anyhow!(format!("Unknown proxy scheme: {}", u.scheme()))),
-            })?;
-        match config.transport_type {
-            TransportType::Tcp => Ok(()),
+    pub fn validate(config: &TransportConfig, is_server: bool) -> Result<()> {
+        let errors = config.errors(is_server);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(errors.join("\n")))
+        }
+    }
+
+    /// All problems of the config, `validate` fails if there are any
+    pub fn errors(&self, is_server: bool) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Some(proxy) = &self.tcp.proxy {
+            if !matches!(proxy.scheme(), "socks5" | "http") {
+                errors.push(format!("Unknown proxy scheme: {}", proxy.scheme()));
+            }
+        }
+        match self.transport_type {
+            TransportType::Tcp => {}
             #[cfg(feature = "rustls")]
-            TransportType::Tls => {
-                let tls_config = config
-                    .tls
-                    .as_ref()
-                    .ok_or_else(|| anyhow!("Missing TLS configuration"))?;
-                if _is_server {
-                    tls_config
-                        .pkcs12
-                        .as_ref()
-                        .and(tls_config.pkcs12_password.as_ref())
-                        .ok_or_else(|| anyhow!("Missing `pkcs12` or `pkcs12_password`"))?;
+            TransportType::Tls => match &self.tls {
+                None => errors.push("Missing TLS configuration".to_string()),
+                Some(tls)
+                    if is_server && (tls.pkcs12.is_none() || tls.pkcs12_password.is_none()) =>
+                {
+                    errors.push("Missing `pkcs12` or `pkcs12_password`".to_string())
+                }
+                Some(_) => {}
+            },
+            TransportType::Websocket => {}
+        }
+        errors
+    }
+
+    /// Files of the TLS config that don't exist, checked by the client only
+    pub fn missing_files(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Some(tls) = &self.tls {
+            for (key, path) in [("trusted_root", &tls.trusted_root), ("pkcs12", &tls.pkcs12)] {
+                if let Some(path) = path.as_ref().filter(|path| !Path::new(path).exists()) {
+                    errors.push(format!("File of `{}` not found: {}", key, path));
                 }
-                Ok(())
             }
-            TransportType::Websocket => Ok(()),
+        }
+        errors
+    }
+
+    /// Whether the connection to the server is wrapped in TLS
+    pub fn uses_tls(&self) -> bool {
+        match self.transport_type {
+            TransportType::Tcp => false,
+            #[cfg(feature = "rustls")]
+            TransportType::Tls => true,
+            TransportType::Websocket => self.websocket.as_ref().is_some_and(|ws| ws.tls),
         }
     }
 
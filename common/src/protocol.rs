@@ -9,8 +9,19 @@ use tracing::trace;
 pub use v2::*;
 
 pub trait Endpoint {
-    fn credentials(&self) -> String;
-    fn as_url(&self) -> String;
+    /// `user:password@` part of the URL, `user@` without `secret`
+    fn credentials(&self, secret: bool) -> String;
+    fn url(&self, secret: bool) -> String;
+
+    /// URL with the password, only to be shown to its owner
+    fn as_url(&self) -> String {
+        self.url(true)
+    }
+
+    /// URL without the password, for logs and events
+    fn as_redacted_url(&self) -> String {
+        self.url(false)
+    }
 }
 
 pub trait DefaultPort {
@@ -29,7 +40,9 @@ pub fn str_enum<E: TryFrom<i32> + ToString>(e: i32) -> String {
 }
 
 pub mod v2 {
+    use crate::config::Redacted;
     use crate::protocol::str_enum;
+    use crate::utils::{format_host_port, unix_socket_path};
 
     use super::{DefaultPort, Endpoint};
     use anyhow::{bail, Context, Result};
@@ -41,6 +54,7 @@ pub mod v2 {
     use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
     use tracing::debug;
     use urlencoding::encode;
+    use zeroize::Zeroize;
 
     include!(concat!(env!("OUT_DIR"), "/protocol.rs"));
 
@@ -113,6 +127,124 @@ pub mod v2 {
     }
     */
 
+    /// Capabilities supported by this build
+    pub const CAPABILITIES: [Capability; 9] = [
+        Capability::Upgrade,
+        Capability::Pong,
+        Capability::Redirect,
+        Capability::Compression,
+        Capability::EndpointStatus,
+        Capability::Visitor,
+        Capability::P2p,
+        Capability::UpgradeCheck,
+        Capability::HeartbeatEcho,
+    ];
+
+    /// Capabilities to advertise in `AgentInfo` and `AgentAck`
+    pub fn local_capabilities() -> Vec<i32> {
+        CAPABILITIES.iter().map(|c| *c as i32).collect()
+    }
+
+    /// Features both peers agreed on at hello time
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct Capabilities(Vec<Capability>);
+
+    impl Capabilities {
+        /// Intersect capabilities advertised by the peer with the local ones
+        pub fn negotiate(remote: &[i32]) -> Self {
+            Self(
+                remote
+                    .iter()
+                    .filter_map(|c| Capability::try_from(*c).ok())
+                    .filter(|c| CAPABILITIES.contains(c))
+                    .collect(),
+            )
+        }
+
+        pub fn supports(&self, capability: Capability) -> bool {
+            self.0.contains(&capability)
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.0.is_empty()
+        }
+
+        pub fn to_vec(&self) -> Vec<i32> {
+            self.0.iter().map(|c| *c as i32).collect()
+        }
+    }
+
+    impl FromStr for Compression {
+        type Err = anyhow::Error;
+
+        fn from_str(s: &str) -> Result<Self> {
+            match s {
+                "none" => Ok(Compression::None),
+                "zstd" => Ok(Compression::Zstd),
+                "deflate" => Ok(Compression::Deflate),
+                _ => bail!("Invalid compression: {}", s),
+            }
+        }
+    }
+
+    impl Display for Compression {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            match self {
+                Compression::None => write!(f, "none"),
+                Compression::Zstd => write!(f, "zstd"),
+                Compression::Deflate => write!(f, "deflate"),
+            }
+        }
+    }
+
+    impl FromStr for RestartPolicy {
+        type Err = anyhow::Error;
+
+        fn from_str(s: &str) -> Result<Self> {
+            match s {
+                "never" => Ok(RestartPolicy::Never),
+                "on-failure" => Ok(RestartPolicy::OnFailure),
+                "always" => Ok(RestartPolicy::Always),
+                _ => bail!("Invalid restart policy: {}", s),
+            }
+        }
+    }
+
+    impl Display for RestartPolicy {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            match self {
+                RestartPolicy::Never => write!(f, "never"),
+                RestartPolicy::OnFailure => write!(f, "on-failure"),
+                RestartPolicy::Always => write!(f, "always"),
+            }
+        }
+    }
+
+    impl FromStr for HealthCheckKind {
+        type Err = anyhow::Error;
+
+        fn from_str(s: &str) -> Result<Self> {
+            match s {
+                "none" => Ok(HealthCheckKind::None),
+                "tcp" => Ok(HealthCheckKind::Tcp),
+                "http" => Ok(HealthCheckKind::Http),
+                "cmd" => Ok(HealthCheckKind::Command),
+                _ => bail!("Invalid health check: {}", s),
+            }
+        }
+    }
+
+    impl Display for HealthCheckKind {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            match self {
+                HealthCheckKind::None => write!(f, "none"),
+                HealthCheckKind::Tcp => write!(f, "tcp"),
+                HealthCheckKind::Http => write!(f, "http"),
+                HealthCheckKind::Command => write!(f, "cmd"),
+            }
+        }
+    }
+
     impl FromStr for Role {
         type Err = anyhow::Error;
 
@@ -174,17 +306,17 @@ pub mod v2 {
             if let Some(name) = self.description.as_ref() {
                 write!(f, "[{}] ", name)?;
             }
-            write!(f, "{}", self.as_url())
+            write!(f, "{}", self.as_redacted_url())
         }
     }
 
     impl Endpoint for ClientEndpoint {
-        fn credentials(&self) -> String {
+        fn credentials(&self, secret: bool) -> String {
             let mut s = String::new();
             if !self.username.is_empty() {
                 s.push_str(&encode(&self.username));
             }
-            if !self.password.is_empty() {
+            if secret && !self.password.is_empty() {
                 s.push(':');
                 s.push_str(&encode(&self.password));
             }
@@ -194,10 +326,10 @@ pub mod v2 {
             s
         }
 
-        fn as_url(&self) -> String {
+        fn url(&self, secret: bool) -> String {
             match self.local_proto.try_into().unwrap() {
                 Protocol::OneC | Protocol::Minecraft | Protocol::Webdav => {
-                    let credentials = self.credentials();
+                    let credentials = self.credentials(secret);
                     format!(
                         "{}://{}{}",
                         str_enum::<Protocol>(self.local_proto),
@@ -209,14 +341,27 @@ pub mod v2 {
                 | Protocol::Https
                 | Protocol::Tcp
                 | Protocol::Udp
+                | Protocol::Rtsp
+                    if unix_socket_path(&self.local_addr).is_some() =>
+                {
+                    format!(
+                        "{}+{}{}",
+                        str_enum::<Protocol>(self.local_proto),
+                        self.local_addr,
+                        self.local_path
+                    )
+                }
+                Protocol::Http
+                | Protocol::Https
+                | Protocol::Tcp
+                | Protocol::Udp
                 | Protocol::Rtsp => {
-                    let credentials = self.credentials();
+                    let credentials = self.credentials(secret);
                     format!(
-                        "{}://{}{}:{}{}",
+                        "{}://{}{}{}",
                         str_enum::<Protocol>(self.local_proto),
                         credentials,
-                        self.local_addr,
-                        self.local_port,
+                        format_host_port(&self.local_addr, self.local_port as u16),
                         self.local_path
                     )
                 }
@@ -226,16 +371,28 @@ pub mod v2 {
 
     impl Display for ServerEndpoint {
         fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-            let client = self.client.as_ref().unwrap();
             write!(
                 f,
-                "{} -> {}://{}{}:{}{}",
-                client,
+                "{} -> {}",
+                self.client.as_ref().unwrap(),
+                self.as_redacted_url()
+            )
+        }
+    }
+
+    impl Endpoint for ServerEndpoint {
+        fn credentials(&self, secret: bool) -> String {
+            self.client.as_ref().unwrap().credentials(secret)
+        }
+
+        // Public URL of the endpoint
+        fn url(&self, secret: bool) -> String {
+            format!(
+                "{}://{}{}{}",
                 Protocol::try_from(self.remote_proto).unwrap(),
-                client.credentials(),
-                self.remote_addr,
-                self.remote_port,
-                client.local_path
+                self.credentials(secret),
+                format_host_port(&self.remote_addr, self.remote_port as u16),
+                self.client.as_ref().unwrap().local_path
             )
         }
     }
@@ -246,6 +403,100 @@ pub mod v2 {
         }
     }
 
+    // Debug of the messages with secrets, the derived one would log them
+
+    impl fmt::Debug for ClientEndpoint {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            f.debug_struct("ClientEndpoint")
+                .field("local_proto", &Protocol::try_from(self.local_proto).ok())
+                .field("local_addr", &self.local_addr)
+                .field("local_port", &self.local_port)
+                .field("local_path", &self.local_path)
+                .field("nodelay", &self.nodelay)
+                .field("description", &self.description)
+                .field("auth", &Auth::try_from(self.auth).ok())
+                .field("acl", &self.acl)
+                .field("username", &self.username)
+                .field("password", &Redacted(&self.password))
+                .field("headers", &self.headers)
+                .field("compression", &Compression::try_from(self.compression).ok())
+                .field("health_check", &self.health_check)
+                .field("error_page", &self.error_page)
+                .field("restart", &self.restart)
+                .field("private", &self.private)
+                .finish()
+        }
+    }
+
+    impl fmt::Debug for AgentInfo {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            f.debug_struct("AgentInfo")
+                .field("agent_id", &self.agent_id)
+                .field("token", &Redacted(&self.token))
+                .field("hostname", &self.hostname)
+                .field("version", &self.version)
+                .field("gui", &self.gui)
+                .field("platform", &self.platform)
+                .field("hwid", &self.hwid)
+                .field("server_host_and_port", &self.server_host_and_port)
+                .field("email", &Redacted(&self.email))
+                .field("password", &Redacted(&self.password))
+                .field("capabilities", &self.capabilities)
+                .finish()
+        }
+    }
+
+    impl fmt::Debug for AgentAck {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            f.debug_struct("AgentAck")
+                .field("token", &Redacted(&self.token))
+                .field("capabilities", &self.capabilities)
+                .field("rendezvous", &self.rendezvous)
+                .finish()
+        }
+    }
+
+    impl fmt::Debug for P2pOffer {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            f.debug_struct("P2pOffer")
+                .field("session", &Redacted(&self.session))
+                .field("guid", &self.guid)
+                .field("candidates", &self.candidates)
+                .finish()
+        }
+    }
+
+    impl fmt::Debug for P2pAnswer {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            f.debug_struct("P2pAnswer")
+                .field("session", &Redacted(&self.session))
+                .field("candidates", &self.candidates)
+                .field("certificate", &self.certificate.len())
+                .finish()
+        }
+    }
+
+    // Credentials don't outlive the handshake messages
+
+    impl Drop for AgentInfo {
+        fn drop(&mut self) {
+            self.token.zeroize();
+            self.password.zeroize();
+        }
+    }
+
+    impl Drop for AgentAck {
+        fn drop(&mut self) {
+            self.token.zeroize();
+        }
+    }
+
+    impl Drop for ClientEndpoint {
+        fn drop(&mut self) {
+            self.password.zeroize();
+        }
+    }
+
     // New Protocol Buffers message reading and writing functions
     pub async fn read_message<T: AsyncRead + Unpin>(conn: &mut T) -> Result<message::Message> {
         let mut buf = [0u8; std::mem::size_of::<u32>()];
@@ -299,6 +550,7 @@ pub mod v1 {
     use serde::de::DeserializeOwned;
     use serde::{Deserialize, Serialize};
     use std::fmt::{self, Display, Formatter};
+    use std::mem::take;
     use std::str::FromStr;
     use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
     use tracing::debug;
@@ -497,27 +749,33 @@ pub mod v1 {
                 auth: v2::Auth::from(ce.auth) as i32,
                 acl: ce.acl.into_iter().map(|a| a.into()).collect(),
                 username: ce.username,
-                password: ce.password.0,
+                password: ce.password.to_string(),
                 headers: Vec::new(),
+                compression: v2::Compression::None as i32,
+                health_check: None,
+                error_page: String::new(),
+                restart: None,
+                private: false,
             }
         }
     }
 
     impl From<v2::ClientEndpoint> for ClientEndpoint {
-        fn from(ce: v2::ClientEndpoint) -> Self {
+        // Fields are taken, the password is zeroed on drop of v2 endpoints
+        fn from(mut ce: v2::ClientEndpoint) -> Self {
             ClientEndpoint {
                 local_proto: v2::Protocol::try_from(ce.local_proto)
                     .unwrap_or(v2::Protocol::Http)
                     .into(),
-                local_addr: ce.local_addr,
+                local_addr: take(&mut ce.local_addr),
                 local_port: ce.local_port as u16,
-                local_path: ce.local_path,
+                local_path: take(&mut ce.local_path),
                 nodelay: ce.nodelay,
-                description: ce.description,
+                description: ce.description.take(),
                 auth: v2::Auth::try_from(ce.auth).unwrap_or(v2::Auth::None).into(),
-                acl: ce.acl.into_iter().map(|a| a.into()).collect(),
-                username: ce.username,
-                password: MaskedString(ce.password),
+                acl: take(&mut ce.acl).into_iter().map(|a| a.into()).collect(),
+                username: take(&mut ce.username),
+                password: MaskedString(take(&mut ce.password)),
             }
         }
     }
@@ -576,6 +834,8 @@ pub mod v1 {
         PermissionDenied,
         PublishFailed,
         ExecuteFailed,
+        CrashLoop,
+        UpgradeFailed,
     }
 
     impl From<ErrorKind> for v2::ErrorKind {
@@ -587,6 +847,8 @@ pub mod v1 {
                 ErrorKind::PermissionDenied => v2::ErrorKind::PermissionDenied,
                 ErrorKind::PublishFailed => v2::ErrorKind::PublishFailed,
                 ErrorKind::ExecuteFailed => v2::ErrorKind::ExecuteFailed,
+                ErrorKind::CrashLoop => v2::ErrorKind::CrashLoop,
+                ErrorKind::UpgradeFailed => v2::ErrorKind::UpgradeFailed,
             }
         }
     }
@@ -600,6 +862,8 @@ pub mod v1 {
                 v2::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
                 v2::ErrorKind::PublishFailed => ErrorKind::PublishFailed,
                 v2::ErrorKind::ExecuteFailed => ErrorKind::ExecuteFailed,
+                v2::ErrorKind::CrashLoop => ErrorKind::CrashLoop,
+                v2::ErrorKind::UpgradeFailed => ErrorKind::UpgradeFailed,
             }
         }
     }
@@ -624,13 +888,15 @@ pub mod v1 {
         pub hwid: String,
         #[serde(default)]
         pub server_host_and_port: String,
+        #[serde(default)]
+        pub capabilities: Vec<i32>,
     }
 
     impl From<AgentInfo> for v2::AgentInfo {
         fn from(ai: AgentInfo) -> Self {
             v2::AgentInfo {
                 agent_id: ai.agent_id,
-                token: ai.token.0,
+                token: ai.token.to_string(),
                 hostname: ai.hostname,
                 version: ai.version,
                 gui: ai.gui,
@@ -639,40 +905,61 @@ pub mod v1 {
                 server_host_and_port: ai.server_host_and_port,
                 email: String::new(),
                 password: String::new(),
+                capabilities: ai.capabilities,
             }
         }
     }
 
     impl From<v2::AgentInfo> for AgentInfo {
-        fn from(ai: v2::AgentInfo) -> Self {
+        fn from(mut ai: v2::AgentInfo) -> Self {
+            // The message zeroes its secrets on drop, so the fields are taken
             AgentInfo {
-                agent_id: ai.agent_id,
-                token: MaskedString(ai.token),
-                hostname: ai.hostname,
+                agent_id: take(&mut ai.agent_id),
+                token: MaskedString(take(&mut ai.token)),
+                hostname: take(&mut ai.hostname),
                 version: if ai.version.is_empty() {
                     default_version()
                 } else {
-                    ai.version
+                    take(&mut ai.version)
                 },
                 gui: ai.gui,
-                platform: ai.platform,
-                hwid: ai.hwid,
-                server_host_and_port: ai.server_host_and_port,
+                platform: take(&mut ai.platform),
+                hwid: take(&mut ai.hwid),
+                server_host_and_port: take(&mut ai.server_host_and_port),
+                capabilities: take(&mut ai.capabilities),
             }
         }
     }
 
     impl AgentInfo {
+        pub fn capabilities(&self) -> v2::Capabilities {
+            v2::Capabilities::negotiate(&self.capabilities)
+        }
+
+        /// Agents that advertise capabilities are trusted as is,
+        /// older ones are detected by the version number
+        fn is_support(&self, capability: v2::Capability, since: &str) -> bool {
+            if self.capabilities.is_empty() {
+                get_version_number(&self.version) >= get_version_number(since)
+            } else {
+                self.capabilities().supports(capability)
+            }
+        }
+
         pub fn is_support_upgrade(&self) -> bool {
-            get_version_number(&self.version) >= get_version_number("1.1.0")
+            self.is_support(v2::Capability::Upgrade, "1.1.0")
         }
 
         pub fn is_support_pong(&self) -> bool {
-            get_version_number(&self.version) >= get_version_number("1.2.104")
+            self.is_support(v2::Capability::Pong, "1.2.104")
         }
 
         pub fn is_support_redirect(&self) -> bool {
-            get_version_number(&self.version) >= get_version_number("1.3.2")
+            self.is_support(v2::Capability::Redirect, "1.3.2")
+        }
+
+        pub fn is_support_compression(&self) -> bool {
+            self.capabilities().supports(v2::Capability::Compression)
         }
     }
 
@@ -693,6 +980,7 @@ pub mod v1 {
             v2::DataChannelInfo {
                 agent_id: dci.agent_id,
                 guid: dci.guid,
+                compression: Vec::new(),
             }
         }
     }
@@ -756,6 +1044,8 @@ pub mod v1 {
                 Message::AgentHello(info) => ProtoMessage::AgentHello(info.into()),
                 Message::AgentAck => ProtoMessage::AgentAck(v2::AgentAck {
                     token: String::new(),
+                    capabilities: Vec::new(),
+                    rendezvous: String::new(),
                 }),
                 Message::EndpointStart(endpoint) => ProtoMessage::EndpointStart(endpoint.into()),
                 Message::EndpointAck(endpoint) => ProtoMessage::EndpointAck(endpoint.into()),
@@ -766,8 +1056,8 @@ pub mod v1 {
                 Message::CreateDataChannel(endpoint) => {
                     ProtoMessage::CreateDataChannel(endpoint.into())
                 }
-                Message::HeartBeat => ProtoMessage::HeartBeat(v2::HeartBeat {}),
-                Message::StartForwardTcp => ProtoMessage::StartForwardTcp(v2::StartForwardTcp {}),
+                Message::HeartBeat => ProtoMessage::HeartBeat(v2::HeartBeat::default()),
+                Message::StartForwardTcp => ProtoMessage::StartForwardTcp(Default::default()),
                 Message::StartForwardUdp => ProtoMessage::StartForwardUdp(v2::StartForwardUdp {}),
                 Message::Error(kind, msg) => ProtoMessage::Error(ErrorInfo {
                     kind: ErrorKind::from(kind) as i32,
@@ -0,0 +1,70 @@
+use anyhow::{anyhow, Result};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+use tracing::debug;
+
+/// Delay before starting the next connection attempt (RFC 8305, section 5)
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Race connection attempts across all addresses, starting a new one every
+/// `CONNECTION_ATTEMPT_DELAY` or as soon as the previous attempt fails.
+/// Addresses are expected to be already sorted by `utils::interleave_addrs`.
+pub async fn connect(addrs: &[SocketAddr]) -> Result<TcpStream> {
+    let mut pending = addrs.iter().copied();
+    let mut next = pending.next();
+    let mut attempts = JoinSet::new();
+    let mut last_err = None;
+
+    loop {
+        if attempts.is_empty() {
+            match next.take() {
+                Some(addr) => {
+                    attempts.spawn(attempt(addr));
+                    next = pending.next();
+                }
+                None => break,
+            }
+        }
+
+        tokio::select! {
+            res = attempts.join_next() => match res {
+                Some(Ok((addr, Ok(stream)))) => {
+                    debug!("Connected to {}", addr);
+                    // Pending attempts are aborted when the set is dropped
+                    return Ok(stream);
+                }
+                Some(Ok((addr, Err(err)))) => {
+                    debug!("Failed to connect to {}: {:?}", addr, err);
+                    last_err = Some(err);
+                    if let Some(addr) = next.take() {
+                        attempts.spawn(attempt(addr));
+                        next = pending.next();
+                    }
+                }
+                Some(Err(err)) => {
+                    last_err = Some(std::io::Error::new(std::io::ErrorKind::Other, err));
+                }
+                None => {}
+            },
+            _ = sleep(CONNECTION_ATTEMPT_DELAY), if next.is_some() => {
+                if let Some(addr) = next.take() {
+                    debug!("Starting parallel connection attempt to {}", addr);
+                    attempts.spawn(attempt(addr));
+                    next = pending.next();
+                }
+            }
+        }
+    }
+
+    Err(match last_err {
+        Some(err) => anyhow!(err).context("Failed to connect to any of the resolved addresses"),
+        None => anyhow!("No addresses to connect to"),
+    })
+}
+
+async fn attempt(addr: SocketAddr) -> (SocketAddr, std::io::Result<TcpStream>) {
+    (addr, TcpStream::connect(addr).await)
+}
@@ -1,8 +1,10 @@
 use crate::config::{TcpConfig, TransportConfig};
-use crate::utils::to_socket_addr;
+use crate::utils::{lookup_static_host, to_socket_addrs};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use std::collections::HashMap;
 use std::fmt::{Debug, Display};
+use std::net::IpAddr;
 #[cfg(unix)]
 use std::os::fd::RawFd;
 use std::time::Duration;
@@ -14,8 +16,12 @@ use anyhow::bail;
 #[cfg(target_os = "linux")]
 use tokio::net::TcpStream;
 
+pub mod happy_eyeballs;
+
 mod tcp;
-pub use tcp::{Listener, NamedSocketAddr, SocketAddr, Stream, TcpTransport};
+pub use tcp::{
+    tcp_connect_with_proxy, Listener, NamedSocketAddr, SocketAddr, Stream, TcpTransport,
+};
 
 mod websocket;
 pub use websocket::{WebsocketTransport, WebsocketTunnel};
@@ -31,6 +37,8 @@ pub use tls::TlsTransport;
 pub struct AddrMaybeCached {
     pub addr: String,
     pub socket_addr: Option<NamedSocketAddr>,
+    /// All resolved addresses, ordered for connection racing
+    pub socket_addrs: Vec<std::net::SocketAddr>,
 }
 
 impl AddrMaybeCached {
@@ -38,17 +46,23 @@ impl AddrMaybeCached {
         AddrMaybeCached {
             addr: addr.to_string(),
             socket_addr: None,
+            socket_addrs: Vec::new(),
         }
     }
 
     pub async fn resolve(&mut self) -> Result<()> {
-        match to_socket_addr(&self.addr).await {
-            Ok(s) => {
-                self.socket_addr = Some(NamedSocketAddr::Inet(s));
-                Ok(())
-            }
-            Err(e) => Err(e),
-        }
+        self.resolve_with_hosts(&HashMap::new()).await
+    }
+
+    /// Resolve all A/AAAA records, preferring the static hosts override
+    pub async fn resolve_with_hosts(&mut self, hosts: &HashMap<String, Vec<IpAddr>>) -> Result<()> {
+        let addrs = match lookup_static_host(&self.addr, hosts)? {
+            Some(addrs) => addrs,
+            None => to_socket_addrs(&self.addr).await?,
+        };
+        self.socket_addr = addrs.first().map(|s| NamedSocketAddr::Inet(*s));
+        self.socket_addrs = addrs;
+        Ok(())
     }
 }
 
@@ -162,6 +162,28 @@ pub fn load_client_config(config: &TlsConfig) -> Result<Option<ClientConfig>> {
     ))
 }
 
+/// Do the client handshake over `conn` and return the DER encoded
+/// certificate chain presented by the server
+pub async fn peer_certificates(
+    conn: Stream,
+    config: &TlsConfig,
+    host_name: &str,
+) -> Result<Vec<Vec<u8>>> {
+    let client_config = load_client_config(config)?.context("Missing TLS client config")?;
+    let connector = TlsConnector::from(Arc::new(client_config));
+    let conn = connector
+        .connect(ServerName::try_from(host_name)?.to_owned(), conn)
+        .await?;
+    Ok(conn
+        .get_ref()
+        .1
+        .peer_certificates()
+        .unwrap_or_default()
+        .iter()
+        .map(|cert| cert.to_vec())
+        .collect())
+}
+
 #[async_trait]
 impl Transport for TlsTransport {
     type Acceptor = Listener;
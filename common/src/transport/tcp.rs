@@ -1,7 +1,7 @@
 use crate::config::{TcpConfig, TransportConfig};
 
-use super::{AddrMaybeCached, SocketOpts, Transport};
-use crate::utils::host_port_pair;
+use super::{happy_eyeballs, AddrMaybeCached, SocketOpts, Transport};
+use crate::utils::{host_port_pair, to_socket_addrs};
 use anyhow::Result;
 use async_http_proxy::{http_connect_tokio, http_connect_tokio_with_basic_auth};
 use async_trait::async_trait;
@@ -94,11 +94,12 @@ pub fn try_set_tcp_keepalive(
 pub async fn tcp_connect_with_proxy(addr: &AddrMaybeCached, proxy: Option<&Url>) -> Result<Stream> {
     if let Some(url) = proxy {
         let addr = &addr.addr;
-        let mut s = TcpStream::connect((
+        let proxy_addrs = to_socket_addrs((
             url.host_str().expect("proxy url should have host field"),
             url.port().expect("proxy url should have port field"),
         ))
         .await?;
+        let mut s = happy_eyeballs::connect(&proxy_addrs).await?;
 
         let auth = if !url.username().is_empty() || url.password().is_some() {
             Some(async_socks5::Auth {
@@ -131,6 +132,8 @@ pub async fn tcp_connect_with_proxy(addr: &AddrMaybeCached, proxy: Option<&Url>)
             _ => panic!("unknown proxy scheme"),
         }
         Ok(Stream::Tcp(s))
+    } else if addr.socket_addrs.len() > 1 {
+        Ok(Stream::Tcp(happy_eyeballs::connect(&addr.socket_addrs).await?))
     } else {
         Ok(match addr.socket_addr.as_ref() {
             Some(s) => Stream::connect(s).await?,
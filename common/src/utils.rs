@@ -1,9 +1,10 @@
 use anyhow::{anyhow, Context, Result};
 use backoff::backoff::Backoff;
 use backoff::Notify;
+use std::collections::HashMap;
 use std::future::Future;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
 use tokio::net::{lookup_host, TcpListener, TcpSocket, ToSocketAddrs, UdpSocket};
 use tokio::sync::watch;
 use tracing::debug;
@@ -17,9 +18,133 @@ pub async fn to_socket_addr<A: ToSocketAddrs>(addr: A) -> Result<std::net::Socke
         .ok_or_else(|| anyhow!("Failed to lookup the host"))
 }
 
+/// Resolve all addresses of the host, ordered for connection racing (RFC 8305)
+pub async fn to_socket_addrs<A: ToSocketAddrs>(addr: A) -> Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = lookup_host(addr).await?.collect();
+    if addrs.is_empty() {
+        return Err(anyhow!("Failed to lookup the host"));
+    }
+    Ok(interleave_addrs(addrs))
+}
+
+/// Resolve the host using the static hosts override from the config
+pub fn lookup_static_host(
+    addr: &str,
+    hosts: &HashMap<String, Vec<IpAddr>>,
+) -> Result<Option<Vec<SocketAddr>>> {
+    if hosts.is_empty() {
+        return Ok(None);
+    }
+    let (host, port) = host_port_pair(addr)?;
+    Ok(hosts
+        .get(host)
+        .filter(|ips| !ips.is_empty())
+        .map(|ips| interleave_addrs(ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect())))
+}
+
+/// Sort addresses by alternating address families, starting with IPv6 (RFC 8305, section 4)
+pub fn interleave_addrs(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let mut v6 = Vec::new();
+    let mut v4 = Vec::new();
+    for addr in addrs {
+        if v6.contains(&addr) || v4.contains(&addr) {
+            continue;
+        }
+        if addr.is_ipv6() {
+            v6.push(addr);
+        } else {
+            v4.push(addr);
+        }
+    }
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    let mut res = Vec::new();
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => {
+                res.extend(a);
+                res.extend(b);
+            }
+        }
+    }
+    res
+}
+
+/// Split `host:port` or `[ipv6]:port` into the host (without brackets) and port
 pub fn host_port_pair(s: &str) -> Result<(&str, u16)> {
-    let semi = s.rfind(':').expect("missing semicolon");
-    Ok((&s[..semi], s[semi + 1..].parse()?))
+    let semi = s
+        .rfind(':')
+        .ok_or_else(|| anyhow!("Missing port in address: {}", s))?;
+    Ok((strip_brackets(&s[..semi]), s[semi + 1..].parse()?))
+}
+
+/// Scheme prefix of local targets listening on a Unix domain socket
+pub const UNIX_SOCKET_PREFIX: &str = "unix://";
+
+/// Returns the socket path if the address is `unix:///path/to/socket`
+pub fn unix_socket_path(addr: &str) -> Option<&str> {
+    addr.strip_prefix(UNIX_SOCKET_PREFIX)
+        .filter(|path| !path.is_empty())
+}
+
+/// Remove brackets around IPv6 literal, if any
+pub fn strip_brackets(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// Join host and port, enclosing IPv6 literals in brackets
+pub fn format_host_port(host: &str, port: u16) -> String {
+    let host = strip_brackets(host);
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Parse IPv6 literal with optional zone, e.g. `fe80::1%eth0` or `fe80::1%2`
+pub fn parse_scoped_ipv6(host: &str, port: u16) -> Option<SocketAddr> {
+    let (ip, scope) = match strip_brackets(host).split_once('%') {
+        Some((ip, scope)) => (ip, Some(scope)),
+        None => (strip_brackets(host), None),
+    };
+    let ip: Ipv6Addr = ip.parse().ok()?;
+    let scope_id = match scope {
+        None => 0,
+        Some(scope) => match scope.parse::<u32>() {
+            Ok(id) => id,
+            Err(_) => interface_index(scope)?,
+        },
+    };
+    Some(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)))
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => None,
+        idx => Some(idx),
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+/// Resolve a local target to all its addresses.
+/// Accepts host names, IPv4, bracketed or bare IPv6 and scoped IPv6 literals.
+pub async fn local_socket_addrs(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    if let Some(addr) = parse_scoped_ipv6(host, port) {
+        return Ok(vec![addr]);
+    }
+    to_socket_addrs((strip_brackets(host), port))
+        .await
+        .with_context(|| format!("Failed to resolve {}", format_host_port(host, port)))
 }
 
 /// Create a UDP socket and connect to `addr`
@@ -28,7 +153,7 @@ pub async fn udp_connect<A: ToSocketAddrs>(addr: A) -> Result<UdpSocket> {
 
     let bind_addr = match addr {
         std::net::SocketAddr::V4(_) => "0.0.0.0:0",
-        std::net::SocketAddr::V6(_) => ":::0",
+        std::net::SocketAddr::V6(_) => "[::]:0",
     };
 
     let s = UdpSocket::bind(bind_addr).await?;
@@ -60,14 +185,22 @@ where
     }
 }
 
+/// Find a free port on `[::]`, or on `0.0.0.0` on hosts without IPv6. The port
+/// is free for IPv4 too only where `[::]` is dual-stack, as on Linux by default
 pub async fn find_free_tcp_port() -> Result<u16> {
-    let tcp_listener = TcpListener::bind("0.0.0.0:0").await?;
+    let tcp_listener = match TcpListener::bind("[::]:0").await {
+        Ok(listener) => listener,
+        Err(_) => TcpListener::bind("0.0.0.0:0").await?,
+    };
     let port = tcp_listener.local_addr()?.port();
     Ok(port)
 }
 
 pub async fn find_free_udp_port() -> Result<u16> {
-    let udp_listener = UdpSocket::bind("0.0.0.0:0").await?;
+    let udp_listener = match UdpSocket::bind("[::]:0").await {
+        Ok(socket) => socket,
+        Err(_) => UdpSocket::bind("0.0.0.0:0").await?,
+    };
     let port = udp_listener.local_addr()?.port();
     Ok(port)
 }
@@ -80,7 +213,11 @@ pub async fn free_port_for_bind(endpoint: &mut ServerEndpoint) -> Result<()> {
 }
 
 pub async fn is_udp_port_available(bind_addr: &str, port: u16) -> Result<bool> {
-    match UdpSocket::bind((bind_addr, port)).await {
+    let bind_addr = match parse_scoped_ipv6(bind_addr, port) {
+        Some(addr) => addr,
+        None => to_socket_addr((strip_brackets(bind_addr), port)).await?,
+    };
+    match UdpSocket::bind(bind_addr).await {
         Ok(_) => Ok(true),
         Err(ref e) if e.kind() == io::ErrorKind::AddrInUse => Ok(false),
         Err(e) => Err(e).context("Failed to check UDP port")?,
@@ -88,9 +225,16 @@ pub async fn is_udp_port_available(bind_addr: &str, port: u16) -> Result<bool> {
 }
 
 pub async fn is_tcp_port_available(bind_addr: &str, port: u16) -> Result<bool> {
-    let tcp_socket = TcpSocket::new_v4()?;
+    let bind_addr = match parse_scoped_ipv6(bind_addr, port) {
+        Some(addr) => addr,
+        None => to_socket_addr((strip_brackets(bind_addr), port)).await?,
+    };
+    let tcp_socket = if bind_addr.is_ipv6() {
+        TcpSocket::new_v6()?
+    } else {
+        TcpSocket::new_v4()?
+    };
     tcp_socket.set_reuseaddr(true).unwrap();
-    let bind_addr: SocketAddr = format!("{}:{}", bind_addr, port).parse().unwrap();
     debug!("Check port: {}", bind_addr);
     match tcp_socket.bind(bind_addr) {
         Ok(_) => Ok(true),
@@ -127,14 +271,27 @@ pub fn get_platform() -> String {
     platform
 }
 
-pub fn split_host_port(host_and_port: &str, default_port: u16) -> (String, u16) {
-    let parts = host_and_port.split(':');
-    let parts: Vec<&str> = parts.collect();
-    let host = parts[0].to_string();
-    let port = if parts.len() > 1 {
-        parts[1].parse::<u16>().unwrap_or(default_port)
-    } else {
-        default_port
+/// Split `host[:port]`, `[ipv6][:port]` or bare `ipv6` into host (without brackets)
+/// and port, if any. A port which is not a valid `u16` is an error.
+pub fn split_host_port(host_and_port: &str) -> Result<(String, Option<u16>)> {
+    let parse = |port: &str| {
+        port.parse::<u16>()
+            .map(Some)
+            .with_context(|| format!("Invalid port '{}'", port))
     };
-    (host, port)
+    if let Some(rest) = host_and_port.strip_prefix('[') {
+        if let Some((host, tail)) = rest.split_once(']') {
+            let port = match tail.strip_prefix(':') {
+                Some(port) => parse(port)?,
+                None => None,
+            };
+            return Ok((host.to_string(), port));
+        }
+    }
+    match host_and_port.split_once(':') {
+        // More than one colon without brackets is a bare IPv6 address
+        Some((_, rest)) if rest.contains(':') => Ok((host_and_port.to_string(), None)),
+        Some((host, port)) => Ok((host.to_string(), parse(port)?)),
+        None => Ok((host_and_port.to_string(), None)),
+    }
 }
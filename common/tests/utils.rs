@@ -0,0 +1,21 @@
+//! Parsing of `host[:port]` addresses
+
+use common::utils::split_host_port;
+
+#[test]
+fn split_host_and_port() {
+    let split = |s: &str| split_host_port(s).unwrap();
+    assert_eq!(split("localhost"), ("localhost".to_string(), None));
+    assert_eq!(split("localhost:8080"), ("localhost".to_string(), Some(8080)));
+    assert_eq!(split("[::1]:443"), ("::1".to_string(), Some(443)));
+    assert_eq!(split("[::1]"), ("::1".to_string(), None));
+    assert_eq!(split("fe80::1"), ("fe80::1".to_string(), None));
+}
+
+#[test]
+fn invalid_port_is_an_error() {
+    assert!(split_host_port("localhost:http").is_err());
+    assert!(split_host_port("localhost:65536").is_err());
+    assert!(split_host_port("[::1]:-1").is_err());
+    assert!(split_host_port("localhost:").is_err());
+}
@@ -0,0 +1,40 @@
+use std::env;
+use std::fs;
+
+// Set to refresh the committed header after changing the C API
+const UPDATE_HEADER: &str = "CLOUDPUB_UPDATE_HEADER";
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=include/cloudpub.h");
+    println!("cargo:rerun-if-env-changed={}", UPDATE_HEADER);
+
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir))
+        .expect("Failed to read cbindgen.toml");
+
+    let generated = format!("{}/cloudpub.h", out_dir);
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Failed to generate the C header")
+        .write_to_file(&generated);
+
+    // The header is committed, so the C++ build doesn't need cargo
+    let committed = format!("{}/include/cloudpub.h", crate_dir);
+    let header = fs::read_to_string(&generated).expect("Failed to read the C header");
+    if fs::read_to_string(&committed).ok().as_deref() == Some(header.as_str()) {
+        return;
+    }
+    if env::var_os(UPDATE_HEADER).is_some() {
+        fs::write(&committed, header).expect("Failed to update the C header");
+    } else {
+        println!(
+            "cargo:warning=include/cloudpub.h is outdated, rebuild with {}=1 to update it",
+            UPDATE_HEADER
+        );
+    }
+}
@@ -0,0 +1,98 @@
+#ifndef CLOUDPUB_H
+#define CLOUDPUB_H
+
+/* Generated by cbindgen from ffi/src/lib.rs with CLOUDPUB_UPDATE_HEADER=1, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Agent with its own tokio runtime
+typedef struct CloAgent CloAgent;
+
+// Receives every event as a JSON object, called from a thread of the agent.
+// Functions of the agent must not be called from the callback.
+typedef void (*CloEventCallback)(const char *event, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Connect to the server with the config in the format of `client.toml`.
+// Missing keys take the default values. Returns NULL on error.
+//
+// # Safety
+//
+// `config` must be NULL or a valid nul terminated string.
+CloAgent *clo_agent_new(const char *config);
+
+// Publish an endpoint with the arguments of `clo publish`, e.g.
+// `{"http", "8080", "--name", "web"}`, and wait until it's online.
+// The guid and the public URL of the endpoint are stored to `guid` and
+// `url` unless they are NULL.
+//
+// # Safety
+//
+// `agent` must be returned by `clo_agent_new`, `argv` must hold `argc`
+// valid nul terminated strings.
+int clo_agent_publish(CloAgent *agent,
+                      int argc,
+                      const char *const *argv,
+                      char **guid,
+                      char **url);
+
+// Remove the endpoint from the server
+//
+// # Safety
+//
+// `agent` must be returned by `clo_agent_new`, `guid` must be a valid nul
+// terminated string.
+int clo_agent_unpublish(CloAgent *agent, const char *guid);
+
+// Wait up to `timeout_ms` for the next event and return it as a JSON object:
+//
+// - `{"type": "ConnectState", "state": "Connected"}`
+// - `{"type": "EndpointAck", "guid": "...", "status": "online", "url": "..."}`
+// - `{"type": "Error", "kind": "Fatal", "message": "..."}`
+// - `{"type": "Progress", "message": "...", "current": 10, "total": 100}`
+//
+// Returns NULL on timeout or error. Only the last 1024 events are kept.
+//
+// # Safety
+//
+// `agent` must be returned by `clo_agent_new`.
+char *clo_agent_poll_event(CloAgent *agent, uint32_t timeout_ms);
+
+// Call `callback` for every event, see `clo_agent_poll_event` for the format.
+// A NULL callback removes the previous one.
+//
+// # Safety
+//
+// `agent` must be returned by `clo_agent_new`, `user_data` must stay valid
+// until the callback is removed or the agent is freed.
+int clo_agent_set_callback(CloAgent *agent, CloEventCallback callback, void *user_data);
+
+// Disconnect from the server and free the agent
+//
+// # Safety
+//
+// `agent` must be NULL or returned by `clo_agent_new`, it can't be used
+// afterwards.
+void clo_agent_free(CloAgent *agent);
+
+// Message of the last error on this thread, valid until the next call
+const char *clo_last_error(void);
+
+// Free a string returned by the library
+//
+// # Safety
+//
+// `s` must be NULL or returned by the library.
+void clo_string_free(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CLOUDPUB_H */
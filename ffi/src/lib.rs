@@ -0,0 +1,304 @@
+//! C API of the agent, see `include/cloudpub.h`.
+//!
+//! Functions returning `int` return 0 on success and -1 on error, the
+//! message of the error is available from `clo_last_error`. Strings
+//! returned by the library must be released with `clo_string_free`.
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use client::agent::{next_event, Agent, PublishedEndpoint};
+use client::commands::PublishArgs;
+use client::config::ClientConfig;
+use common::protocol::message::Message;
+use parking_lot::Mutex;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// Agent with its own tokio runtime
+pub struct CloAgent {
+    runtime: Runtime,
+    agent: Option<Agent>,
+    events: Mutex<broadcast::Receiver<Message>>,
+    callback: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Receives every event as a JSON object, called from a thread of the agent.
+/// Functions of the agent must not be called from the callback.
+pub type CloEventCallback = Option<extern "C" fn(event: *const c_char, user_data: *mut c_void)>;
+
+// The caller is responsible for the user data being usable from other threads
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// Arguments of `clo publish`
+#[derive(Parser)]
+#[command(name = "publish")]
+struct Publish {
+    #[clap(flatten)]
+    args: PublishArgs,
+}
+
+/// Connect to the server with the config in the format of `client.toml`.
+/// Missing keys take the default values. Returns NULL on error or if
+/// the server doesn't answer in 30 seconds.
+///
+/// # Safety
+///
+/// `config` must be NULL or a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn clo_agent_new(config: *const c_char) -> *mut CloAgent {
+    ffi_try(ptr::null_mut(), || {
+        let config = parse_config(&str_arg(config)?)?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .context("Failed to create the runtime")?;
+        let agent = runtime.block_on(Agent::builder().config(config).connect())?;
+        let events = Mutex::new(agent.events());
+        Ok(Box::into_raw(Box::new(CloAgent {
+            runtime,
+            agent: Some(agent),
+            events,
+            callback: Mutex::new(None),
+        })))
+    })
+}
+
+/// Publish an endpoint with the arguments of `clo publish`, e.g.
+/// `{"http", "8080", "--name", "web"}`, and wait until it's online.
+/// The guid and the public URL of the endpoint are stored to `guid` and
+/// `url` unless they are NULL.
+///
+/// # Safety
+///
+/// `agent` must be returned by `clo_agent_new`, `argv` must hold `argc`
+/// valid nul terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn clo_agent_publish(
+    agent: *mut CloAgent,
+    argc: c_int,
+    argv: *const *const c_char,
+    guid: *mut *mut c_char,
+    url: *mut *mut c_char,
+) -> c_int {
+    ffi_try(-1, || {
+        let agent = agent_arg(agent)?;
+        if argc < 0 || (argc > 0 && argv.is_null()) {
+            bail!("Invalid arguments");
+        }
+        let mut args = vec!["publish".to_string()];
+        for i in 0..argc as usize {
+            args.push(str_arg(*argv.add(i))?);
+        }
+        let publish = Publish::try_parse_from(args)?;
+        if !publish.args.exec.is_empty() {
+            bail!("Launching commands is not supported by the library");
+        }
+        let endpoint = publish.args.parse()?;
+        let published: PublishedEndpoint = agent
+            .runtime
+            .block_on(agent.agent.as_ref().unwrap().publish(endpoint))?;
+        if !guid.is_null() {
+            *guid = into_c_string(published.guid);
+        }
+        if !url.is_null() {
+            *url = into_c_string(published.url);
+        }
+        Ok(0)
+    })
+}
+
+/// Remove the endpoint from the server
+///
+/// # Safety
+///
+/// `agent` must be returned by `clo_agent_new`, `guid` must be a valid nul
+/// terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn clo_agent_unpublish(agent: *mut CloAgent, guid: *const c_char) -> c_int {
+    ffi_try(-1, || {
+        let agent = agent_arg(agent)?;
+        let guid = str_arg(guid)?;
+        agent
+            .runtime
+            .block_on(agent.agent.as_ref().unwrap().unpublish(&guid))?;
+        Ok(0)
+    })
+}
+
+/// Wait up to `timeout_ms` for the next event and return it as a JSON object:
+///
+/// - `{"type": "ConnectState", "state": "Connected"}`
+/// - `{"type": "EndpointAck", "guid": "...", "status": "online", "url": "..."}`
+/// - `{"type": "Error", "kind": "Fatal", "message": "..."}`
+/// - `{"type": "Progress", "message": "...", "current": 10, "total": 100}`
+///
+/// Returns NULL on timeout or error. Only the last 1024 events are kept.
+///
+/// # Safety
+///
+/// `agent` must be returned by `clo_agent_new`.
+#[no_mangle]
+pub unsafe extern "C" fn clo_agent_poll_event(
+    agent: *mut CloAgent,
+    timeout_ms: u32,
+) -> *mut c_char {
+    ffi_try(ptr::null_mut(), || {
+        let agent = agent_arg(agent)?;
+        let mut events = agent.events.lock();
+        let event = agent.runtime.block_on(async {
+            tokio::time::timeout(
+                Duration::from_millis(timeout_ms as u64),
+                next_event(&mut events),
+            )
+            .await
+            .ok()
+        });
+        Ok(match event {
+            Some(Some(event)) => into_c_string(event.to_string()),
+            _ => ptr::null_mut(),
+        })
+    })
+}
+
+/// Call `callback` for every event, see `clo_agent_poll_event` for the format.
+/// A NULL callback removes the previous one.
+///
+/// # Safety
+///
+/// `agent` must be returned by `clo_agent_new`, `user_data` must stay valid
+/// until the callback is removed or the agent is freed.
+#[no_mangle]
+pub unsafe extern "C" fn clo_agent_set_callback(
+    agent: *mut CloAgent,
+    callback: CloEventCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    ffi_try(-1, || {
+        let agent = agent_arg(agent)?;
+        let mut current = agent.callback.lock();
+        if let Some(task) = current.take() {
+            task.abort();
+        }
+        let Some(callback) = callback else {
+            return Ok(0);
+        };
+        let mut events = agent.agent.as_ref().unwrap().events();
+        let user_data = UserData(user_data);
+        *current = Some(agent.runtime.spawn(async move {
+            // Move the wrapper, the raw pointer alone isn't Send
+            let user_data = user_data;
+            while let Some(event) = next_event(&mut events).await {
+                let event = CString::new(event.to_string()).unwrap_or_default();
+                callback(event.as_ptr(), user_data.0);
+            }
+        }));
+        Ok(0)
+    })
+}
+
+/// Disconnect from the server and free the agent
+///
+/// # Safety
+///
+/// `agent` must be NULL or returned by `clo_agent_new`, it can't be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn clo_agent_free(agent: *mut CloAgent) {
+    if agent.is_null() {
+        return;
+    }
+    let mut agent = Box::from_raw(agent);
+    if let Some(task) = agent.callback.lock().take() {
+        task.abort();
+    }
+    if let Some(inner) = agent.agent.take() {
+        agent.runtime.block_on(inner.shutdown());
+    }
+}
+
+/// Message of the last error on this thread, valid until the next call
+#[no_mangle]
+pub extern "C" fn clo_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Free a string returned by the library
+///
+/// # Safety
+///
+/// `s` must be NULL or returned by the library.
+#[no_mangle]
+pub unsafe extern "C" fn clo_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+// Run `f` reporting errors and panics through `clo_last_error`
+fn ffi_try<R>(on_error: R, f: impl FnOnce() -> Result<R>) -> R {
+    let err = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(res)) => {
+            LAST_ERROR.with(|e| *e.borrow_mut() = None);
+            return res;
+        }
+        Ok(Err(err)) => format!("{:#}", err),
+        Err(_) => "Internal error".to_string(),
+    };
+    LAST_ERROR.with(|e| *e.borrow_mut() = CString::new(err).ok());
+    on_error
+}
+
+unsafe fn agent_arg<'a>(agent: *mut CloAgent) -> Result<&'a CloAgent> {
+    agent.as_ref().context("Agent is NULL")
+}
+
+unsafe fn str_arg(s: *const c_char) -> Result<String> {
+    if s.is_null() {
+        return Ok(String::new());
+    }
+    Ok(CStr::from_ptr(s)
+        .to_str()
+        .context("String is not valid UTF-8")?
+        .to_string())
+}
+
+fn into_c_string(s: String) -> *mut c_char {
+    CString::new(s).unwrap_or_default().into_raw()
+}
+
+// Keys missing in `config` keep the default values
+fn parse_config(config: &str) -> Result<ClientConfig> {
+    let mut value =
+        toml::Value::try_from(ClientConfig::default()).context("Failed to serialize the config")?;
+    let overrides: toml::Value = toml::from_str(config).context("Invalid config")?;
+    merge(&mut value, overrides);
+    value.try_into().context("Invalid config")
+}
+
+fn merge(value: &mut toml::Value, overrides: toml::Value) {
+    match (value, overrides) {
+        (toml::Value::Table(table), toml::Value::Table(overrides)) => {
+            for (key, v) in overrides {
+                match table.get_mut(&key) {
+                    Some(current) => merge(current, v),
+                    None => {
+                        table.insert(key, v);
+                    }
+                }
+            }
+        }
+        (value, overrides) => *value = overrides,
+    }
+}
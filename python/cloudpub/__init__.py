@@ -0,0 +1,89 @@
+"""Publish local services to the internet with CloudPub.
+
+    import cloudpub
+
+    with cloudpub.publish("http", 8080) as tunnel:
+        print(tunnel.url)
+
+Without an explicit `Agent` the session of `clo login` is used.
+"""
+
+import asyncio
+import functools
+import threading
+from typing import Any, Callable, Dict, Generator, Optional, Union
+
+from ._native import Agent, Error, Tunnel
+
+__all__ = [
+    "Agent",
+    "Error",
+    "Tunnel",
+    "default_agent",
+    "on_event",
+    "publish",
+    "publish_async",
+    "unpublish_async",
+]
+
+_agent: Optional[Agent] = None
+_lock = threading.Lock()
+
+
+def default_agent() -> Agent:
+    """Agent shared by the module functions, connected on first use"""
+    global _agent
+    with _lock:
+        if _agent is None:
+            _agent = Agent()
+        return _agent
+
+
+def publish(protocol: str, address: Union[int, str], **kwargs: Any) -> Tunnel:
+    """Publish and wait until the endpoint is online, e.g.
+    `publish("http", 8080, name="web", args=["--auth", "basic"])`"""
+    return default_agent().publish(protocol, address, **kwargs)
+
+
+def on_event(callback: Callable[[Dict[str, Any]], Any]) -> None:
+    """Call `callback(event)` with every event of the default agent"""
+    default_agent().on_event(callback)
+
+
+class _AsyncPublish:
+    """Result of `publish_async`: await it for the tunnel, or use it with
+    `async with` to close the tunnel on exit"""
+
+    def __init__(self, protocol: str, address: Union[int, str], kwargs: Dict[str, Any]):
+        self._publish = functools.partial(publish, protocol, address, **kwargs)
+        self._tunnel: Optional[Tunnel] = None
+
+    async def _run(self) -> Tunnel:
+        loop = asyncio.get_running_loop()
+        return await loop.run_in_executor(None, self._publish)
+
+    def __await__(self) -> Generator[Any, None, Tunnel]:
+        return self._run().__await__()
+
+    async def __aenter__(self) -> Tunnel:
+        self._tunnel = await self._run()
+        return self._tunnel
+
+    async def __aexit__(self, *args: Any) -> None:
+        if self._tunnel is not None:
+            await unpublish_async(self._tunnel)
+
+
+def publish_async(protocol: str, address: Union[int, str], **kwargs: Any) -> _AsyncPublish:
+    """`publish` for asyncio, the event loop isn't blocked:
+
+        async with cloudpub.publish_async("http", 8080) as tunnel:
+            print(tunnel.url)
+    """
+    return _AsyncPublish(protocol, address, kwargs)
+
+
+async def unpublish_async(tunnel: Tunnel) -> None:
+    """`Tunnel.close` for asyncio"""
+    loop = asyncio.get_running_loop()
+    await loop.run_in_executor(None, tunnel.close)
@@ -0,0 +1,41 @@
+from typing import Any, Callable, Dict, List, Optional, Union
+
+class Error(Exception): ...
+
+class Agent:
+    """Connection to the server. Without a token it uses the config and the
+    session of `clo login`. `timeout` limits the wait for the server in
+    seconds, 30 by default."""
+
+    def __init__(
+        self,
+        token: Optional[str] = None,
+        server: Optional[str] = None,
+        config: Optional[str] = None,
+        timeout: Optional[float] = None,
+    ) -> None: ...
+    def publish(
+        self,
+        protocol: str,
+        address: Union[int, str],
+        name: Optional[str] = None,
+        auth: Optional[str] = None,
+        args: List[str] = ...,
+    ) -> Tunnel: ...
+    def unpublish(self, guid: str) -> None: ...
+    def on_event(self, callback: Callable[[Dict[str, Any]], Any]) -> None: ...
+    def close(self) -> None: ...
+    def __enter__(self) -> Agent: ...
+    def __exit__(self, *args: Any) -> None: ...
+
+class Tunnel:
+    """Published endpoint, removed from the server by `close()` or on exit from
+    the `with` block"""
+
+    @property
+    def url(self) -> str: ...
+    @property
+    def guid(self) -> str: ...
+    def close(self) -> None: ...
+    def __enter__(self) -> Tunnel: ...
+    def __exit__(self, *args: Any) -> None: ...
@@ -0,0 +1,7 @@
+import cloudpub
+
+cloudpub.on_event(lambda event: print(event))
+
+with cloudpub.publish("http", 8080) as tunnel:
+    print(f"Published URL: {tunnel.url}")
+    input("Press Enter to exit...")
@@ -0,0 +1,242 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use client::agent::{next_event, Agent};
+use client::base::CONFIG_FILE;
+use client::commands::PublishArgs;
+use client::config::ClientConfig;
+use parking_lot::{Mutex, RwLock};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
+
+create_exception!(cloudpub, Error, PyException);
+
+lazy_static::lazy_static! {
+    // Shared by all agents, Python doesn't run tokio on its own
+    static ref RUNTIME: Runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to create the runtime");
+}
+
+// Arguments of `clo publish`
+#[derive(Parser)]
+#[command(name = "publish")]
+struct Publish {
+    #[clap(flatten)]
+    args: PublishArgs,
+}
+
+/// Connection to the server. Without a token it uses the config and the
+/// session of `clo login`. `timeout` limits the wait for the server in
+/// seconds, 30 by default.
+#[pyclass(frozen, name = "Agent", module = "cloudpub")]
+struct PyAgent {
+    agent: RwLock<Option<Agent>>,
+    callbacks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// Published endpoint, removed from the server by `close()` or on exit from
+/// the `with` block
+#[pyclass(frozen, module = "cloudpub")]
+struct Tunnel {
+    #[pyo3(get)]
+    url: String,
+    #[pyo3(get)]
+    guid: String,
+    agent: Py<PyAgent>,
+    closed: AtomicBool,
+}
+
+#[pymethods]
+impl PyAgent {
+    #[new]
+    #[pyo3(signature = (token=None, server=None, config=None, timeout=None))]
+    fn new(
+        py: Python<'_>,
+        token: Option<String>,
+        server: Option<String>,
+        config: Option<String>,
+        timeout: Option<f64>,
+    ) -> PyResult<Self> {
+        let agent = py
+            .allow_threads(|| {
+                let mut builder = Agent::builder();
+                match config {
+                    Some(path) => builder = builder.config_file(path),
+                    None if token.is_none() => {
+                        builder = builder
+                            .config_file(ClientConfig::get_config_dir(true)?.join(CONFIG_FILE))
+                    }
+                    None => {}
+                }
+                if let Some(token) = token {
+                    builder = builder.token(token);
+                }
+                if let Some(server) = server {
+                    builder = builder.server(server);
+                }
+                if let Some(timeout) = timeout {
+                    builder = builder.timeout(Duration::try_from_secs_f64(timeout)?);
+                }
+                RUNTIME.block_on(builder.connect())
+            })
+            .map_err(to_py_err)?;
+        Ok(Self {
+            agent: RwLock::new(Some(agent)),
+            callbacks: Default::default(),
+        })
+    }
+
+    /// Publish with the arguments of `clo publish` and wait until the
+    /// endpoint is online: `agent.publish("http", 8080, name="web")`
+    #[pyo3(signature = (protocol, address, name=None, auth=None, args=Vec::new()))]
+    fn publish(
+        slf: &Bound<'_, Self>,
+        protocol: &str,
+        address: &Bound<'_, PyAny>,
+        name: Option<String>,
+        auth: Option<String>,
+        args: Vec<String>,
+    ) -> PyResult<Tunnel> {
+        let mut argv = vec![
+            "publish".to_string(),
+            protocol.to_string(),
+            address.str()?.to_string(),
+        ];
+        if let Some(name) = name {
+            argv.extend(["--name".to_string(), name]);
+        }
+        if let Some(auth) = auth {
+            argv.extend(["--auth".to_string(), auth]);
+        }
+        argv.extend(args);
+
+        let this = slf.get();
+        let published = slf
+            .py()
+            .allow_threads(|| {
+                let publish = Publish::try_parse_from(argv)?;
+                if !publish.args.exec.is_empty() {
+                    bail!("Launching commands is not supported by the library");
+                }
+                let endpoint = publish.args.parse()?;
+                let agent = this.agent.read();
+                let Some(agent) = agent.as_ref() else {
+                    bail!("Agent is closed");
+                };
+                RUNTIME.block_on(agent.publish(endpoint))
+            })
+            .map_err(to_py_err)?;
+        Ok(Tunnel {
+            url: published.url,
+            guid: published.guid,
+            agent: slf.clone().unbind(),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Remove the endpoint from the server
+    fn unpublish(&self, py: Python<'_>, guid: String) -> PyResult<()> {
+        py.allow_threads(|| {
+            let agent = self.agent.read();
+            let Some(agent) = agent.as_ref() else {
+                bail!("Agent is closed");
+            };
+            RUNTIME.block_on(agent.unpublish(&guid))
+        })
+        .map_err(to_py_err)
+    }
+
+    /// Call `callback(event)` with every event as a dict, e.g.
+    /// `{"type": "ConnectState", "state": "Connected"}`. It's called from
+    /// another thread.
+    fn on_event(&self, callback: PyObject) -> PyResult<()> {
+        let agent = self.agent.read();
+        let Some(agent) = agent.as_ref() else {
+            return Err(Error::new_err("Agent is closed"));
+        };
+        let mut events = agent.events();
+        let task = RUNTIME.spawn(async move {
+            while let Some(event) = next_event(&mut events).await {
+                Python::with_gil(|py| {
+                    let res = py
+                        .import_bound("json")
+                        .and_then(|json| json.call_method1("loads", (event.to_string(),)))
+                        .and_then(|event| callback.call1(py, (event,)));
+                    if let Err(err) = res {
+                        err.print(py);
+                    }
+                });
+            }
+        });
+        self.callbacks.lock().push(task);
+        Ok(())
+    }
+
+    /// Disconnect from the server, published endpoints go offline
+    fn close(&self, py: Python<'_>) {
+        for task in self.callbacks.lock().drain(..) {
+            task.abort();
+        }
+        py.allow_threads(|| {
+            if let Some(agent) = self.agent.write().take() {
+                RUNTIME.block_on(agent.shutdown());
+            }
+        });
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, py: Python<'_>, _args: &Bound<'_, PyTuple>) {
+        self.close(py);
+    }
+}
+
+#[pymethods]
+impl Tunnel {
+    /// Remove the endpoint from the server, does nothing the second time
+    fn close(&self, py: Python<'_>) -> PyResult<()> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.agent.get().unpublish(py, self.guid.clone())
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, py: Python<'_>, _args: &Bound<'_, PyTuple>) -> PyResult<()> {
+        self.close(py)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Tunnel(url='{}', guid='{}')", self.url, self.guid)
+    }
+
+    fn __str__(&self) -> String {
+        self.url.clone()
+    }
+}
+
+fn to_py_err(err: anyhow::Error) -> PyErr {
+    Error::new_err(format!("{:#}", err))
+}
+
+#[pymodule]
+fn _native(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyAgent>()?;
+    m.add_class::<Tunnel>()?;
+    m.add("Error", m.py().get_type_bound::<Error>())?;
+    Ok(())
+}